@@ -0,0 +1,296 @@
+//! Tempo synced stereo delay.
+//! Time is a note value, the length in frames follows the clock tempo and glides on changes,
+//! so tempo drifts and time switches never click.
+use sample::frame::{Frame, Stereo};
+
+use super::filters::{BiquadFilter, FilterOp, FilterType};
+use super::fx::Fx;
+use crate::config::{NoteFeel, NoteValue};
+use crate::control::{FxParam, SmoothParam};
+
+/// Longest delay, the buffer is allocated once at init
+const MAX_DELAY_SECONDS: f64 = 4.0;
+
+/// Length of the delay time glides, in frames
+const DELAY_GLIDE_LEN: usize = 4096;
+
+/// Length of the feedback and mix ramps, in frames
+const DELAY_SMOOTH_LEN: usize = 256;
+
+/// Tempo until the first clock tick
+const DEFAULT_TEMPO: f64 = 120.0;
+
+/// Weight of each clock tempo in the running average, the midi tempo jitters tick to tick
+const TEMPO_SMOOTH: f64 = 0.02;
+
+/// The length glides only once the averaged tempo moved this far, in BPM
+const TEMPO_THRESHOLD: f64 = 0.5;
+
+/// Feedback is kept under this, the filtered loop must die out
+const MAX_FEEDBACK: f32 = 0.95;
+
+/// Note values reachable from the Time param, shortest first
+const DELAY_TIMES: [NoteValue; 11] = [
+    NoteValue {
+        div: 32,
+        feel: NoteFeel::Straight,
+    },
+    NoteValue {
+        div: 16,
+        feel: NoteFeel::Triplet,
+    },
+    NoteValue {
+        div: 16,
+        feel: NoteFeel::Straight,
+    },
+    NoteValue {
+        div: 8,
+        feel: NoteFeel::Triplet,
+    },
+    NoteValue {
+        div: 16,
+        feel: NoteFeel::Dotted,
+    },
+    NoteValue {
+        div: 8,
+        feel: NoteFeel::Straight,
+    },
+    NoteValue {
+        div: 4,
+        feel: NoteFeel::Triplet,
+    },
+    NoteValue {
+        div: 8,
+        feel: NoteFeel::Dotted,
+    },
+    NoteValue {
+        div: 4,
+        feel: NoteFeel::Straight,
+    },
+    NoteValue {
+        div: 4,
+        feel: NoteFeel::Dotted,
+    },
+    NoteValue {
+        div: 2,
+        feel: NoteFeel::Straight,
+    },
+];
+
+/// DelayFx is a stereo delay line with a lowpassed feedback path, optionally ping-pong.
+pub struct DelayFx {
+    /// delay line, circular
+    line: Vec<Stereo<f32>>,
+    /// write position in the line
+    write: usize,
+    sample_rate: f64,
+    /// tempo the length is tuned to
+    tempo: f64,
+    /// running average of the clock tempo
+    tempo_avg: f64,
+    /// delay time
+    time: NoteValue,
+    /// delay length in frames
+    length: SmoothParam,
+    feedback: SmoothParam,
+    mix: SmoothParam,
+    /// input on the left, feedback crossing sides
+    ping_pong: bool,
+    /// feedback lowpass
+    damp: BiquadFilter,
+}
+
+/// DelayFx implementation
+impl DelayFx {
+    /// damp in Hz
+    pub fn new(
+        time: NoteValue,
+        feedback: f32,
+        mix: f32,
+        ping_pong: bool,
+        damp: f32,
+        sample_rate: f64,
+    ) -> Self {
+        let line_len = (MAX_DELAY_SECONDS * sample_rate) as usize + 2;
+        let length = note_frames(time, DEFAULT_TEMPO, sample_rate, line_len);
+        let feedback = feedback.max(0.0).min(MAX_FEEDBACK);
+        let mix = mix.max(0.0).min(1.0);
+
+        DelayFx {
+            line: vec![Stereo::<f32>::equilibrium(); line_len],
+            write: 0,
+            sample_rate,
+            tempo: DEFAULT_TEMPO,
+            tempo_avg: DEFAULT_TEMPO,
+            time,
+            length: SmoothParam::new(length, length),
+            feedback: SmoothParam::new(feedback, feedback),
+            mix: SmoothParam::new(mix, mix),
+            ping_pong,
+            damp: damp_filter(damp, sample_rate as f32),
+        }
+    }
+
+    /// glides to the length of the current time at the current tempo
+    fn update_length(&mut self) {
+        let length = note_frames(self.time, self.tempo, self.sample_rate, self.line.len());
+        self.length.retarget(length);
+    }
+}
+
+impl Fx for DelayFx {
+    fn process_block(&mut self, block: &mut [Stereo<f32>]) {
+        let line_len = self.line.len();
+        for frame in block.iter_mut() {
+            let length = self.length.get_param(DELAY_GLIDE_LEN);
+            let feedback = self.feedback.get_param(DELAY_SMOOTH_LEN);
+            let mix = self.mix.get_param(DELAY_SMOOTH_LEN);
+
+            // fractional read, the length glides between frames
+            let read = (self.write + line_len) as f32 - length;
+            let i0 = read.floor() as usize % line_len;
+            let i1 = (i0 + 1) % line_len;
+            let frac = read - read.floor();
+            let delayed = self.line[i0]
+                .scale_amp(1.0 - frac)
+                .add_amp(self.line[i1].scale_amp(frac));
+
+            // filtered feedback
+            let fb = self.damp.process(delayed).scale_amp(feedback);
+            let input = *frame;
+            self.line[self.write] = if self.ping_pong {
+                [(input[0] + input[1]) * 0.5 + fb[1], fb[0]]
+            } else {
+                [input[0] + fb[0], input[1] + fb[1]]
+            };
+            self.write = (self.write + 1) % line_len;
+
+            // dry / wet
+            *frame = input.scale_amp(1.0 - mix).add_amp(delayed.scale_amp(mix));
+        }
+    }
+
+    fn set_param(&mut self, param: FxParam, val: f32) {
+        let val = val.max(0.0).min(1.0);
+        match param {
+            FxParam::Time => {
+                let idx = (val * (DELAY_TIMES.len() - 1) as f32).round() as usize;
+                self.time = DELAY_TIMES[idx];
+                self.update_length();
+            }
+            FxParam::Feedback => self.feedback.retarget(val * MAX_FEEDBACK),
+            FxParam::Mix => self.mix.retarget(val),
+            FxParam::Freq => {
+                // 20Hz to 20kHz, like the filters
+                let freq = 20.0 * 1000f32.powf(val);
+                let sample_rate = self.sample_rate as f32;
+                // keeps the filter state, no click in the loop
+                self.damp.retune(
+                    FilterType::LowPass(),
+                    FilterOp::UseQ(),
+                    sample_rate,
+                    freq.min(sample_rate * 0.45),
+                    0.0,
+                    0.707,
+                    0.0,
+                    0.0,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    fn sync(&mut self, tempo: f64) {
+        if tempo <= 0.0 {
+            return;
+        }
+        // average out the tick jitter, only glide on actual changes
+        self.tempo_avg += (tempo - self.tempo_avg) * TEMPO_SMOOTH;
+        if (self.tempo_avg - self.tempo).abs() > TEMPO_THRESHOLD {
+            self.tempo = self.tempo_avg;
+            self.update_length();
+        }
+    }
+}
+
+/// Length of a note value in frames, fits in the line
+fn note_frames(time: NoteValue, tempo: f64, sample_rate: f64, line_len: usize) -> f32 {
+    let frames = time.beats() * 60.0 / tempo * sample_rate;
+    frames.max(2.0).min((line_len - 2) as f64) as f32
+}
+
+/// Lowpass of the feedback path, below nyquist
+fn damp_filter(freq: f32, sample_rate: f32) -> BiquadFilter {
+    BiquadFilter::create_filter(
+        FilterType::LowPass(),
+        FilterOp::UseQ(),
+        sample_rate,
+        freq.max(20.0).min(sample_rate * 0.45),
+        0.0,
+        0.707,
+        0.0,
+        0.0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_note_values() {
+        let dotted_16th = NoteValue {
+            div: 16,
+            feel: NoteFeel::Dotted,
+        };
+        let eighth_triplet = NoteValue {
+            div: 8,
+            feel: NoteFeel::Triplet,
+        };
+        assert_eq!(dotted_16th.beats(), 0.375);
+        assert!((eighth_triplet.beats() - 1.0 / 3.0).abs() < 1e-9);
+        // an eighth at 120 bpm is a quarter of a second
+        let eighth = NoteValue {
+            div: 8,
+            feel: NoteFeel::Straight,
+        };
+        assert_eq!(note_frames(eighth, 120.0, 48_000.0, 192_002), 12_000.0);
+    }
+
+    #[test]
+    fn test_delay_echo() {
+        let eighth = NoteValue {
+            div: 8,
+            feel: NoteFeel::Straight,
+        };
+        let mut fx = DelayFx::new(eighth, 0.0, 1.0, false, 20_000.0, 48_000.0);
+        let mut block = vec![Stereo::<f32>::equilibrium(); 16_000];
+        block[0] = [1.0, 1.0];
+        fx.process_block(&mut block);
+        // fully wet, the impulse comes back an eighth later
+        assert_eq!(block[0], [0.0, 0.0]);
+        assert!(block[12_000][0] > 0.5);
+    }
+
+    #[test]
+    fn test_tempo_jitter() {
+        let eighth = NoteValue {
+            div: 8,
+            feel: NoteFeel::Straight,
+        };
+        let mut fx = DelayFx::new(eighth, 0.0, 1.0, false, 20_000.0, 48_000.0);
+
+        // a jittery clock around the tempo keeps the length
+        let jitter = [119.0, 121.0, 120.0, 122.0, 118.0, 121.0];
+        for tempo in jitter.iter().cycle().take(2_000) {
+            fx.sync(*tempo);
+        }
+        assert_eq!(fx.tempo, DEFAULT_TEMPO);
+
+        // an actual change retunes
+        for _ in 0..2_000 {
+            fx.sync(130.0);
+        }
+        assert!((fx.tempo - 130.0).abs() <= TEMPO_THRESHOLD);
+    }
+}
@@ -12,6 +12,7 @@ use std::f32;
 
 /* bank filter */
 // plenty wow :)
+#[derive(Debug, Copy, Clone)]
 pub enum FilterType {
     LowPass(),
     HiPass(),
@@ -25,6 +26,7 @@ pub enum FilterType {
 }
 
 //
+#[derive(Debug, Copy, Clone)]
 pub enum FilterOp {
     UseQ(),
     UseBW(),
@@ -188,6 +190,32 @@ impl BiquadFilter {
         }
     }
 
+    // recomputes the coefs in place, keeping x and y so a moving filter does not click
+    pub fn retune(
+        &mut self,
+        filter_type: FilterType,
+        filter_opt: FilterOp,
+        fs: f32,
+        f0: f32,
+        db_gain: f32,
+        q: f32,
+        bw: f32,
+        slope: f32,
+    ) {
+        let f = BiquadFilter::create_filter(filter_type, filter_opt, fs, f0, db_gain, q, bw, slope);
+        self.a0 = f.a0;
+        self.a1 = f.a1;
+        self.a2 = f.a2;
+        self.b0 = f.b0;
+        self.b1 = f.b1;
+        self.b2 = f.b2;
+        self.c1 = f.c1;
+        self.c2 = f.c2;
+        self.c3 = f.c3;
+        self.c4 = f.c4;
+        self.c5 = f.c5;
+    }
+
     // process
     #[inline(always)]
     pub fn process(&mut self, frame: Stereo<f32>) -> Stereo<f32> {
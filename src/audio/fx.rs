@@ -0,0 +1,313 @@
+//! Track insert effects.
+//! Each AudioTrack owns an ordered FxChain, declared in the config.
+//! Parameters are normalized 0..1 on the control side, each FX maps them to its own range.
+use sample::frame::Stereo;
+
+use super::delay::DelayFx;
+use super::filters::{BiquadFilter, FilterOp, FilterType};
+use super::reverb::ReverbFx;
+use crate::config::{BiquadType, FxConfig};
+use crate::control::{FxParam, SmoothParam};
+
+/// Length of the parameter ramps, in frames
+const FX_SMOOTH_LEN: usize = 256;
+
+/// How often the filter coefficients follow the ramps, in frames
+const FX_RETUNE_INTERVAL: usize = 16;
+
+/// Fx trait, an insert effect processing the track buffer in place
+pub trait Fx {
+    /// process a segment of the track buffer
+    fn process_block(&mut self, block: &mut [Stereo<f32>]);
+    /// set a parameter from a normalized value, unknown params are ignored
+    fn set_param(&mut self, param: FxParam, val: f32);
+    /// follow the clock tempo, for the synced effects
+    fn sync(&mut self, _tempo: f64) {}
+}
+
+/// FxChain is the ordered list of insert effects of a track
+pub struct FxChain {
+    fxs: Vec<Box<dyn Fx + 'static + Send>>,
+}
+
+/// FxChain implementation
+impl FxChain {
+    /// builds the chain from the track config
+    pub fn new(conf: &[FxConfig], sample_rate: f64) -> Self {
+        let fxs = conf
+            .iter()
+            .map(|fx_conf| -> Box<dyn Fx + 'static + Send> {
+                match fx_conf {
+                    FxConfig::Biquad {
+                        filter,
+                        freq,
+                        q,
+                        gain,
+                    } => Box::new(BiquadFx::new(*filter, *freq, *q, *gain, sample_rate as f32)),
+                    FxConfig::Delay {
+                        time,
+                        feedback,
+                        mix,
+                        ping_pong,
+                        damp,
+                    } => Box::new(DelayFx::new(
+                        *time,
+                        *feedback,
+                        *mix,
+                        *ping_pong,
+                        *damp,
+                        sample_rate,
+                    )),
+                    FxConfig::Reverb {
+                        room_size,
+                        damping,
+                        width,
+                        mix,
+                    } => Box::new(ReverbFx::new(
+                        *room_size,
+                        *damping,
+                        *width,
+                        *mix,
+                        sample_rate,
+                    )),
+                }
+            })
+            .collect();
+
+        FxChain { fxs }
+    }
+
+    /// process a segment through all the effects, in order
+    pub fn process_block(&mut self, block: &mut [Stereo<f32>]) {
+        for fx in self.fxs.iter_mut() {
+            fx.process_block(block);
+        }
+    }
+
+    /// forwards the clock tempo to all the effects
+    pub fn sync(&mut self, tempo: f64) {
+        for fx in self.fxs.iter_mut() {
+            fx.sync(tempo);
+        }
+    }
+
+    /// set a parameter of an effect of the chain
+    pub fn set_param(&mut self, fx_num: usize, param: FxParam, val: f32) {
+        if let Some(fx) = self.fxs.get_mut(fx_num) {
+            fx.set_param(param, val);
+        }
+    }
+}
+
+/// Frequency range, mapped exponentially
+const FREQ_MIN: f32 = 20.0;
+const FREQ_MAX: f32 = 20_000.0;
+/// Resonance range, mapped exponentially
+const Q_MIN: f32 = 0.1;
+const Q_MAX: f32 = 10.0;
+/// Gain range in dB, mapped linearly
+const GAIN_MIN: f32 = -24.0;
+const GAIN_MAX: f32 = 24.0;
+
+/// BiquadFx is a cookbook filter insert.
+/// Params are smoothed in the normalized domain, so frequency sweeps are even to the ear,
+/// and coefficients are recomputed every few frames while ramping, keeping the filter state.
+pub struct BiquadFx {
+    filter: BiquadFilter,
+    filter_type: FilterType,
+    sample_rate: f32,
+    freq: SmoothParam,
+    q: SmoothParam,
+    gain: SmoothParam,
+    /// frames until the next coefficients update
+    countdown: usize,
+    /// a ramp is running or just ended
+    retune: bool,
+}
+
+/// BiquadFx implementation
+impl BiquadFx {
+    /// freq in Hz, gain in dB
+    pub fn new(filter: BiquadType, freq: f32, q: f32, gain: f32, sample_rate: f32) -> Self {
+        let filter_type = match filter {
+            BiquadType::LowPass => FilterType::LowPass(),
+            BiquadType::HiPass => FilterType::HiPass(),
+            BiquadType::BpSkirtGain => FilterType::BpSkirtGain(),
+            BiquadType::BpConstantPeak => FilterType::BpConstantPeak(),
+            BiquadType::Notch => FilterType::Notch(),
+            BiquadType::Ap => FilterType::Ap(),
+            BiquadType::PeakingEQ => FilterType::PeakingEQ(),
+            BiquadType::LowShelf => FilterType::LowShelf(),
+            BiquadType::HiShelf => FilterType::HiShelf(),
+        };
+
+        // config values are physical, ramps are normalized
+        let freq_n = unmap_exp(freq, FREQ_MIN, FREQ_MAX);
+        let q_n = unmap_exp(q, Q_MIN, Q_MAX);
+        let gain_n = unmap_lin(gain, GAIN_MIN, GAIN_MAX);
+
+        let mut fx = BiquadFx {
+            filter: BiquadFilter::create_filter(
+                filter_type,
+                FilterOp::UseQ(),
+                sample_rate,
+                FREQ_MIN,
+                0.0,
+                Q_MIN,
+                0.0,
+                0.0,
+            ),
+            filter_type,
+            sample_rate,
+            freq: SmoothParam::new(freq_n, freq_n),
+            q: SmoothParam::new(q_n, q_n),
+            gain: SmoothParam::new(gain_n, gain_n),
+            countdown: 0,
+            retune: false,
+        };
+        fx.update_coefs(freq_n, q_n, gain_n);
+        fx
+    }
+
+    /// recomputes the coefficients from normalized params
+    fn update_coefs(&mut self, freq_n: f32, q_n: f32, gain_n: f32) {
+        // stay below nyquist, the cookbook formulas blow up there
+        let freq = map_exp(freq_n, FREQ_MIN, FREQ_MAX).min(self.sample_rate * 0.45);
+        self.filter.retune(
+            self.filter_type,
+            FilterOp::UseQ(),
+            self.sample_rate,
+            freq,
+            map_lin(gain_n, GAIN_MIN, GAIN_MAX),
+            map_exp(q_n, Q_MIN, Q_MAX),
+            0.0,
+            0.0,
+        );
+    }
+
+    /// true once all the ramps are done
+    fn is_stable(&self) -> bool {
+        self.freq.is_stable(FX_SMOOTH_LEN)
+            && self.q.is_stable(FX_SMOOTH_LEN)
+            && self.gain.is_stable(FX_SMOOTH_LEN)
+    }
+}
+
+impl Fx for BiquadFx {
+    fn process_block(&mut self, block: &mut [Stereo<f32>]) {
+        for frame in block.iter_mut() {
+            // ramps advance every frame
+            let freq_n = self.freq.get_param(FX_SMOOTH_LEN);
+            let q_n = self.q.get_param(FX_SMOOTH_LEN);
+            let gain_n = self.gain.get_param(FX_SMOOTH_LEN);
+
+            // coefficients follow at control rate, the last update lands on the targets
+            if self.countdown == 0 {
+                if self.retune {
+                    self.update_coefs(freq_n, q_n, gain_n);
+                    self.retune = !self.is_stable();
+                }
+                self.countdown = FX_RETUNE_INTERVAL;
+            }
+            self.countdown -= 1;
+
+            *frame = self.filter.process(*frame);
+        }
+    }
+
+    fn set_param(&mut self, param: FxParam, val: f32) {
+        let val = val.max(0.0).min(1.0);
+        match param {
+            FxParam::Freq => self.freq.retarget(val),
+            FxParam::Q => self.q.retarget(val),
+            FxParam::Gain => self.gain.retarget(val),
+            _ => return,
+        }
+        self.retune = true;
+    }
+}
+
+/// 0..1 to min..max, exponentially
+fn map_exp(val: f32, min: f32, max: f32) -> f32 {
+    min * (max / min).powf(val)
+}
+
+/// min..max to 0..1, exponentially
+fn unmap_exp(val: f32, min: f32, max: f32) -> f32 {
+    ((val.max(min).min(max) / min).ln() / (max / min).ln())
+        .max(0.0)
+        .min(1.0)
+}
+
+/// 0..1 to min..max
+fn map_lin(val: f32, min: f32, max: f32) -> f32 {
+    min + (max - min) * val
+}
+
+/// min..max to 0..1
+fn unmap_lin(val: f32, min: f32, max: f32) -> f32 {
+    ((val - min) / (max - min)).max(0.0).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sample::Frame;
+
+    #[test]
+    fn test_param_mapping() {
+        assert!(
+            (map_exp(unmap_exp(1000.0, FREQ_MIN, FREQ_MAX), FREQ_MIN, FREQ_MAX) - 1000.0).abs()
+                < 0.1
+        );
+        assert_eq!(
+            map_lin(unmap_lin(-6.0, GAIN_MIN, GAIN_MAX), GAIN_MIN, GAIN_MAX),
+            -6.0
+        );
+    }
+
+    #[test]
+    fn test_lowpass_sweep_is_smooth() {
+        let mut fx = BiquadFx::new(BiquadType::LowPass, 18_000.0, 0.707, 0.0, 44_100.0);
+        let mut block = vec![Stereo::<f32>::equilibrium(); 1024];
+
+        // dc settles through the open filter
+        for f in block.iter_mut() {
+            *f = [0.5, 0.5];
+        }
+        fx.process_block(&mut block);
+        assert!((block[1023][0] - 0.5).abs() < 0.01);
+
+        // closing the filter keeps dc, no jump between consecutive frames
+        fx.set_param(FxParam::Freq, 0.1);
+        for f in block.iter_mut() {
+            *f = [0.5, 0.5];
+        }
+        fx.process_block(&mut block);
+        for pair in block.windows(2) {
+            assert!((pair[1][0] - pair[0][0]).abs() < 0.05);
+        }
+        assert!((block[1023][0] - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_retarget_starts_from_current() {
+        // volume and pan ramps start from the previous target
+        let mut volume = SmoothParam::new(0.0, 0.0);
+        volume.new_value(1.0);
+        for _ in 0..50 {
+            volume.get_param(100);
+        }
+        volume.new_value(0.0);
+        assert_eq!(volume.get_param(100), 1.0);
+
+        // fx params start from where the ramp is
+        let mut freq = SmoothParam::new(0.0, 0.0);
+        freq.retarget(1.0);
+        for _ in 0..50 {
+            freq.get_param(100);
+        }
+        freq.retarget(0.0);
+        assert!((freq.get_param(100) - 0.49).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,194 @@
+//! Master bus limiter.
+//! A look-ahead brickwall limiter: the gain needed by each frame is held over the look-ahead window,
+//! released slowly, then averaged over the same window, so the gain is already down when the delayed peak comes out.
+//! An optional soft clipper saturates the peaks before they reach the limiter.
+use sample::frame::{Frame, Stereo};
+
+use crate::config::MasterConfig;
+
+/// Limiter is the master bus dynamics stage, allocated once at init
+pub struct Limiter {
+    /// ceiling, linear
+    ceiling: f32,
+    /// look-ahead in frames
+    lookahead: usize,
+    /// release smoothing coefficient, per frame
+    release: f32,
+    /// saturates before limiting
+    soft_clip: bool,
+    /// input delayed by the look-ahead, circular
+    delay: Vec<Stereo<f32>>,
+    /// needed gains of the look-ahead plus the current frame, circular
+    gains: Vec<f32>,
+    /// sliding minimum, indexes in gains, monotonic increasing values
+    min_queue: Vec<usize>,
+    min_head: usize,
+    min_len: usize,
+    /// released gains averaged over the window, circular
+    released: Vec<f32>,
+    released_sum: f64,
+    /// last released gain
+    release_env: f32,
+    /// write position in gains
+    pos: usize,
+    /// write position in delay and released
+    delay_pos: usize,
+    /// lowest gain since the last read, for metering
+    min_gain: f32,
+}
+
+/// Limiter implementation
+impl Limiter {
+    /// init from the master config
+    pub fn new(conf: &MasterConfig, sample_rate: f64) -> Self {
+        let lookahead = ((conf.lookahead as f64 / 1000.0 * sample_rate) as usize).max(1);
+        let release_frames = (conf.release as f64 / 1000.0 * sample_rate).max(1.0);
+        let window = lookahead + 1;
+
+        Limiter {
+            ceiling: db_to_gain(conf.ceiling.min(0.0)),
+            lookahead,
+            release: (1.0 - (-1.0 / release_frames).exp()) as f32,
+            soft_clip: conf.soft_clip,
+            delay: vec![Stereo::<f32>::equilibrium(); lookahead],
+            gains: vec![1.0; window],
+            min_queue: vec![0; window],
+            min_head: 0,
+            min_len: 0,
+            released: vec![1.0; lookahead],
+            released_sum: lookahead as f64,
+            release_env: 1.0,
+            pos: 0,
+            delay_pos: 0,
+            min_gain: 1.0,
+        }
+    }
+
+    /// Limits a block in place
+    pub fn process_block(&mut self, block: &mut [Stereo<f32>]) {
+        for frame in block.iter_mut() {
+            *frame = self.process(*frame);
+        }
+    }
+
+    /// Lowest gain since the last call, in dB (0 or less)
+    pub fn take_gain_reduction(&mut self) -> f32 {
+        let db = gain_to_db(self.min_gain);
+        self.min_gain = 1.0;
+        db
+    }
+
+    #[inline(always)]
+    fn process(&mut self, frame: Stereo<f32>) -> Stereo<f32> {
+        let window = self.gains.len();
+
+        // saturation, keeps the limiter from working too hard
+        let frame = if self.soft_clip {
+            frame.map(|s| soft_clip(s, self.ceiling))
+        } else {
+            frame
+        };
+
+        // gain this frame needs
+        let peak = frame[0].abs().max(frame[1].abs());
+        let needed = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+
+        // sliding minimum over the look-ahead plus this frame
+        // the frame overwritten now leaves the window
+        if self.min_len > 0 && self.min_queue[self.min_head] == self.pos {
+            self.min_head = (self.min_head + 1) % window;
+            self.min_len -= 1;
+        }
+        self.gains[self.pos] = needed;
+        while self.min_len > 0 {
+            let last = self.min_queue[(self.min_head + self.min_len - 1) % window];
+            if self.gains[last] < needed {
+                break;
+            }
+            self.min_len -= 1;
+        }
+        self.min_queue[(self.min_head + self.min_len) % window] = self.pos;
+        self.min_len += 1;
+        let held = self.gains[self.min_queue[self.min_head]];
+        self.pos = (self.pos + 1) % window;
+
+        // instant down, slow up
+        self.release_env = if held < self.release_env {
+            held
+        } else {
+            self.release_env + (held - self.release_env) * self.release
+        };
+
+        // average over the look-ahead, smooth and still under the needed gain
+        // when the delayed frame comes out
+        self.released_sum += self.release_env as f64 - self.released[self.delay_pos] as f64;
+        self.released[self.delay_pos] = self.release_env;
+        let gain = (self.released_sum / self.lookahead as f64) as f32;
+
+        // delay the audio
+        let delayed = self.delay[self.delay_pos];
+        self.delay[self.delay_pos] = frame;
+        self.delay_pos = (self.delay_pos + 1) % self.lookahead;
+
+        self.min_gain = self.min_gain.min(gain);
+
+        delayed.scale_amp(gain)
+    }
+}
+
+/// Linear below half the ceiling, tanh curve up to the ceiling
+fn soft_clip(s: f32, ceiling: f32) -> f32 {
+    let knee = ceiling * 0.5;
+    let a = s.abs();
+    if a <= knee {
+        return s;
+    }
+    let range = ceiling - knee;
+    s.signum() * (knee + range * ((a - knee) / range).tanh())
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.max(1e-6).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limiter_ceiling() {
+        let conf = MasterConfig {
+            limiter: true,
+            ceiling: -1.0,
+            lookahead: 2.0,
+            release: 50.0,
+            soft_clip: false,
+        };
+        let mut limiter = Limiter::new(&conf, 48_000.0);
+        let ceiling = db_to_gain(-1.0);
+
+        // loud square bursts
+        let mut block: Vec<Stereo<f32>> = (0..48_000)
+            .map(|i| {
+                let s = if (i / 100) % 2 == 0 { 1.5 } else { -0.2 };
+                [s, -s]
+            })
+            .collect();
+        limiter.process_block(&mut block);
+
+        for f in block.iter() {
+            assert!(f[0].abs() <= ceiling + 1e-4);
+            assert!(f[1].abs() <= ceiling + 1e-4);
+        }
+        assert!(limiter.take_gain_reduction() < -3.0);
+        assert_eq!(limiter.take_gain_reduction(), 0.0);
+    }
+}
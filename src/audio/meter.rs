@@ -0,0 +1,164 @@
+//! Level metering.
+//! The mixer measures peak and RMS of each track and of the master in the audio thread,
+//! and publishes them through atomics at the end of each window, no lock nor allocation.
+//! A meter thread reads them at the same rate and notifies the remote controller.
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::Sender;
+use sample::frame::Stereo;
+
+use crate::control::Notification;
+
+/// Meter accumulates a window of frames
+#[derive(Default)]
+pub struct Meter {
+    peak: f32,
+    sum_sq: f64,
+    frames: usize,
+}
+
+/// Meter implementation
+impl Meter {
+    /// adds a frame to the window
+    #[inline(always)]
+    pub fn add(&mut self, frame: Stereo<f32>) {
+        self.peak = self.peak.max(frame[0].abs()).max(frame[1].abs());
+        self.sum_sq += (frame[0] * frame[0] + frame[1] * frame[1]) as f64 * 0.5;
+        self.frames += 1;
+    }
+
+    /// peak and rms of the window, starts a new one
+    pub fn take(&mut self) -> (f32, f32) {
+        let rms = if self.frames > 0 {
+            (self.sum_sq / self.frames as f64).sqrt() as f32
+        } else {
+            0.0
+        };
+        let peak = self.peak;
+        *self = Meter::default();
+        (peak, rms)
+    }
+}
+
+/// MeterLevels are the last published levels, shared between the mixer and the meter thread
+pub struct MeterLevels {
+    /// peak and rms pairs as f32 bits, master first then the tracks
+    levels: Vec<AtomicU32>,
+    /// master limiter gain reduction in dB, as f32 bits
+    gain_reduction: AtomicU32,
+    /// bumped after each publication
+    seq: AtomicU64,
+}
+
+/// MeterLevels implementation
+impl MeterLevels {
+    pub fn new(num_tracks: usize) -> Self {
+        MeterLevels {
+            levels: (0..(num_tracks + 1) * 2)
+                .map(|_| AtomicU32::new(0))
+                .collect(),
+            gain_reduction: AtomicU32::new(0),
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    /// number of metered tracks
+    pub fn num_tracks(&self) -> usize {
+        self.levels.len() / 2 - 1
+    }
+
+    /// stores the master levels, audio thread
+    pub fn set_master(&self, peak: f32, rms: f32) {
+        self.set(0, peak, rms);
+    }
+
+    /// stores the levels of a track, audio thread
+    pub fn set_track(&self, track_num: usize, peak: f32, rms: f32) {
+        self.set(track_num + 1, peak, rms);
+    }
+
+    /// stores the gain reduction, audio thread
+    pub fn set_gain_reduction(&self, db: f32) {
+        self.gain_reduction.store(db.to_bits(), Ordering::Relaxed);
+    }
+
+    /// makes the stored levels visible to the meter thread
+    pub fn publish(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+    }
+
+    fn set(&self, index: usize, peak: f32, rms: f32) {
+        self.levels[index * 2].store(peak.to_bits(), Ordering::Relaxed);
+        self.levels[index * 2 + 1].store(rms.to_bits(), Ordering::Relaxed);
+    }
+
+    fn get(&self, index: usize) -> (f32, f32) {
+        (
+            f32::from_bits(self.levels[index * 2].load(Ordering::Relaxed)),
+            f32::from_bits(self.levels[index * 2 + 1].load(Ordering::Relaxed)),
+        )
+    }
+}
+
+/// Initialize the meter thread, notifies the levels at the given rate (Hz) until the notification bus is gone
+pub fn initialize_meters(
+    levels: Arc<MeterLevels>,
+    rate: f64,
+    notify_tx: Sender<Notification>,
+) -> thread::JoinHandle<()> {
+    let interval = Duration::from_micros((1_000_000.0 / rate.max(0.1)) as u64);
+    thread::spawn(move || {
+        let mut last_seq = 0;
+        loop {
+            thread::sleep(interval);
+
+            // nothing new
+            let seq = levels.seq.load(Ordering::Acquire);
+            if seq == last_seq {
+                continue;
+            }
+            last_seq = seq;
+
+            // master, tracks, then the limiter
+            let (peak, rms) = levels.get(0);
+            let mut notifications = vec![Notification::MasterMeter { peak, rms }];
+            for track_num in 0..levels.num_tracks() {
+                let (peak, rms) = levels.get(track_num + 1);
+                notifications.push(Notification::TrackMeter {
+                    track_num,
+                    peak,
+                    rms,
+                });
+            }
+            let db = f32::from_bits(levels.gain_reduction.load(Ordering::Relaxed));
+            notifications.push(Notification::GainReduction { db });
+
+            for n in notifications {
+                if notify_tx.send(n).is_err() {
+                    return;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meter() {
+        let mut meter = Meter::default();
+        for i in 0..100 {
+            let s = if i % 2 == 0 { 0.5 } else { -0.5 };
+            meter.add([s, s]);
+        }
+        let (peak, rms) = meter.take();
+        assert_eq!(peak, 0.5);
+        assert!((rms - 0.5).abs() < 1e-6);
+        assert_eq!(meter.take(), (0.0, 0.0));
+    }
+}
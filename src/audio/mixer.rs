@@ -1,14 +1,33 @@
 //! Audio Mixer defines structs and traits useful for sampler routing.
 //! This is intended to be as modular as it can be.
+use std::collections::VecDeque;
+use std::sync::Arc;
+
 use sample::frame::{Frame, Stereo};
 
-use crate::config::{Config, TrackType};
-use crate::control::{ControlMessage, Direction, DirectionalParam, SmoothParam, SyncMessage};
+use super::fx::FxChain;
+use super::limiter::Limiter;
+use super::meter::{Meter, MeterLevels};
+use super::recorder::Recorder;
+use crate::config::{BusConfig, Config, RecordSource, TrackType, TriggerConfig};
+use crate::control::{AudioClock, ControlMessage, SlicerMessage, SmoothParam, SyncMessage};
 use crate::sample_gen::pvoc::PVOCGen;
 use crate::sample_gen::repitch::RePitchGen;
 use crate::sample_gen::slicer::SlicerGen;
-use crate::sample_gen::{SampleGenerator, SmartBuffer};
-use crate::sample_lib::SampleLib;
+use crate::sample_gen::{SampleGenerator, Trigger, PPQN};
+use crate::sample_lib::loader::{LoaderHandle, RecordedTake};
+
+/// How many control messages can wait for their frame, pre-allocated
+const SCHEDULED_CAPACITY: usize = 1024;
+
+/// Length of the mute, solo and cue fades, in frames
+const MUTE_FADE_LEN: usize = 512;
+
+/// Input frames waiting for the next block, the oldest are dropped past this
+const INPUT_RING_CAPACITY: usize = 4096;
+
+/// Largest block expected from the device, the block buffers are allocated at this size
+pub const MAX_BLOCK_SIZE: usize = 4096;
 
 /// extending the Stereo Trait for additional mixing power
 pub trait StereoExt<F32> {
@@ -25,6 +44,16 @@ impl StereoExt<f32> for Stereo<f32> {
     }
 }
 
+/// Silences the first frames of a block buffer, it only grows past MAX_BLOCK_SIZE
+fn clear_block(buffer: &mut Vec<Stereo<f32>>, size: usize) {
+    if buffer.len() < size {
+        buffer.resize(size, Stereo::<f32>::equilibrium());
+    }
+    for frame in buffer[..size].iter_mut() {
+        *frame = Stereo::<f32>::equilibrium();
+    }
+}
+
 /// AudioTrack is a AudioMixer track that embeds one sample generator and a chain of effects.
 struct AudioTrack {
     /// The attached sample generator.
@@ -33,83 +62,127 @@ struct AudioTrack {
     /// As we are using cpal, we dont know yet how to size it at init.
     /// A first audio round is necessary to get the size
     audio_buffer: Vec<Stereo<f32>>,
+    /// Insert effects, in order, applied to each segment
+    fx: FxChain,
     /// Volume is the volume value of the track, pre effects, smoothed
     volume: SmoothParam,
     /// Pan is the panning value of the track, pre effects, smoothed
     pan: SmoothParam,
-    /// Bank index (track-locked)
-    bank: usize,
-    /// Direction parameter for sample selection (Up/Down).
-    sample_select: DirectionalParam,
-    /// Sample name to keep track for presets as the lib grows
-    sample_name: String,
+    /// Post fader send levels, one per send bus, smoothed
+    sends: Vec<SmoothParam>,
+    /// Group bus the track is summed to, the output if None
+    group: Option<usize>,
+    /// Output pair, 0 is the main output
+    output: usize,
+    /// Post fader levels
+    meter: Meter,
+    /// Muted by the user
+    mute: bool,
+    /// Soloed by the user, other tracks are silent
+    solo: bool,
+    /// Mute and solo result, smoothed
+    gate: SmoothParam,
+    /// Pre fader listen on the cue output, smoothed
+    cue: SmoothParam,
+    /// Takes recorded into the bank, recorder tracks only
+    recorder: Option<Recorder>,
+    /// Triggered playback, the track ignores the transport
+    trigger: Option<TriggerConfig>,
+    /// Trigger waiting for its quantization, with its slice
+    pending_trigger: Option<Option<usize>>,
+    /// Tick a triggered playback of a few bars stops on
+    release_tick: Option<u64>,
+    /// Slicer track, the only one taking slicer messages
+    slicer: bool,
 }
 
 /// AudioTrack implementation.
 impl AudioTrack {
-    /// new init the track from a sample generator
-    fn new(generator: Box<dyn SampleGenerator + 'static + Send>, bank: usize) -> Self {
+    /// new init the track from a sample generator and its fx chain
+    fn new(
+        generator: Box<dyn SampleGenerator + 'static + Send>,
+        fx: FxChain,
+        sends: Vec<SmoothParam>,
+        group: Option<usize>,
+        output: usize,
+    ) -> Self {
         AudioTrack {
             generator,
+            fx,
             // we still dont know how much the buffer wants.
             // let's init at 512 and extend later.
             audio_buffer: Vec::with_capacity(512),
             volume: SmoothParam::new(0.0, 1.0),
             pan: SmoothParam::new(0.0, 0.0),
-            sample_select: DirectionalParam::new(0.0, 0.0),
-            bank,
-            sample_name: String::from(""),
+            sends,
+            group,
+            output,
+            meter: Meter::default(),
+            mute: false,
+            solo: false,
+            gate: SmoothParam::new(1.0, 1.0),
+            cue: SmoothParam::new(0.0, 0.0),
+            recorder: None,
+            trigger: None,
+            pending_trigger: None,
+            release_tick: None,
+            slicer: false,
         }
     }
 
-    /// Loads (moves) an arbitrary SmartBuffer in the gen.
-    fn load_buffer(&mut self, buffer: &SmartBuffer) {
-        // memorize
-        self.sample_name = buffer.file_name.clone(); // @TODO Clone
-        self.generator.load_buffer(buffer);
-    }
-
-    /// loads currently tracked smart buffer
-    fn load_current_buffer(&mut self, sample_lib: &SampleLib) {
-        // @TODO There is a clone here in audio path
-        self.generator
-            .load_buffer(sample_lib.get_sample_by_name(self.bank, self.sample_name.as_str()))
-    }
-
-    /// loads the first sample in the the bank
-    fn load_first_buffer(&mut self, sample_lib: &SampleLib) {
-        // @TODO There is a clone here in audio path
-        let first = sample_lib.get_first_sample(self.bank);
-        self.load_buffer(first)
-    }
-
-    /// loads the next sample in the the bank
-    fn load_next_buffer(&mut self, sample_lib: &SampleLib) {
-        // @TODO There is a clone here in audio path
-        let next = sample_lib.get_sibling_sample(self.bank, self.sample_name.as_str(), 1);
-        self.load_buffer(next)
-    }
-
-    /// loads the next sample in the the bank
-    fn load_prev_buffer(&mut self, sample_lib: &SampleLib) {
-        // @TODO There is a clone here in audio path
-        let next = sample_lib.get_sibling_sample(self.bank, self.sample_name.as_str(), -1);
-        self.load_buffer(next)
-    }
-
-    /// play the underlying sample gen
+    /// play the underlying sample gen, triggered tracks wait for their trigger
     fn play(&mut self) {
-        self.generator.play();
+        if self.trigger.is_none() {
+            self.generator.play();
+        }
     }
 
     /// pause the underlying sample gen
     fn stop(&mut self) {
+        if self.trigger.is_some() {
+            self.pending_trigger = None;
+            self.release_tick = None;
+            self.generator.release();
+        }
         self.generator.stop();
     }
 
-    /// synchronize the underlying samplegen
-    fn sync(&mut self, global_tempo: u64, tick: u64) {
-        self.generator.sync(global_tempo, tick);
+    /// synchronize the underlying samplegen and the fx
+    fn sync(&mut self, global_tempo: f64, tick: u64) {
+        // triggers start and stop on the ticks, before the generator syncs
+        if let Some(conf) = self.trigger {
+            self.sync_trigger(&conf, tick);
+        }
+        self.generator.sync(global_tempo as u64, tick);
+        self.fx.sync(global_tempo);
+    }
+
+    /// releases the playback after its bars, fires the pending trigger on its quantization
+    fn sync_trigger(&mut self, conf: &TriggerConfig, tick: u64) {
+        if self.release_tick == Some(tick) {
+            self.release_tick = None;
+            self.generator.release();
+        }
+
+        let quant_ticks = match conf.quant {
+            0 => 1,
+            quant => (PPQN as u64 * 4 / quant).max(1),
+        };
+        if tick % quant_ticks != 0 {
+            return;
+        }
+
+        if let Some(slice) = self.pending_trigger.take() {
+            self.generator.trigger(Trigger {
+                tick,
+                slice,
+                one_shot: conf.bars == 0,
+            });
+            self.release_tick = match conf.bars {
+                0 => None,
+                bars => Some(tick + bars * PPQN as u64 * 4),
+            };
+        }
     }
 
     /// set loop div
@@ -117,15 +190,18 @@ impl AudioTrack {
         self.generator.set_loop_div(loop_div);
     }
 
-    /// process and fill next block of audio.
-    fn fill_next_block(&mut self, size: usize) {
+    /// process and fill a segment of the next block of audio.
+    /// Segments are cut between scheduled control messages.
+    fn fill_next_segment(&mut self, size: usize, start: usize, end: usize) {
         // first check if the buffer is init
-        if self.audio_buffer.len() == 0 {
+        if self.audio_buffer.len() < size {
             println!("init buffer to size: {}", size);
             self.audio_buffer = vec![Stereo::<f32>::equilibrium(); size];
         }
         // fill buffer
-        self.generator.next_block(&mut self.audio_buffer);
+        self.generator.next_block(&mut self.audio_buffer[start..end]);
+        // inserts
+        self.fx.process_block(&mut self.audio_buffer[start..end]);
     }
 
     /// Get frame at specific place
@@ -137,57 +213,228 @@ impl AudioTrack {
     }
 }
 
+/// Bus sums tracks, processes them through its FX chain and adds them to the output.
+/// Used for the send (aux) buses and the group buses.
+struct Bus {
+    /// Effects of the bus
+    fx: FxChain,
+    /// Sum of the tracks
+    audio_buffer: Vec<Stereo<f32>>,
+    /// Volume of the bus, post effects, smoothed
+    volume: SmoothParam,
+    /// Pan of the bus, post effects, smoothed
+    pan: SmoothParam,
+    /// Output pair, 0 is the main output
+    output: usize,
+}
+
+/// Bus implementation.
+impl Bus {
+    /// new init the bus from its config
+    fn new(conf: &BusConfig, sample_rate: f64) -> Self {
+        Bus {
+            fx: FxChain::new(&conf.fx, sample_rate),
+            audio_buffer: vec![Stereo::<f32>::equilibrium(); MAX_BLOCK_SIZE],
+            volume: SmoothParam::new(conf.volume, conf.volume),
+            pan: SmoothParam::new(conf.pan, conf.pan),
+            output: conf.output,
+        }
+    }
+
+    /// silence the bus before the tracks are summed to it
+    fn clear(&mut self, size: usize) {
+        clear_block(&mut self.audio_buffer, size);
+    }
+
+    /// adds a frame to the bus
+    fn add_frame(&mut self, index: usize, frame: Stereo<f32>) {
+        self.audio_buffer[index] = self.audio_buffer[index].add_amp(frame);
+    }
+
+    /// process the bus effects, then volume and pan, and add it to the output
+    fn mix_into(&mut self, block_out: &mut [Stereo<f32>]) {
+        let buff_size = block_out.len();
+        self.fx.process_block(&mut self.audio_buffer[..buff_size]);
+        for (frame_out, frame) in block_out.iter_mut().zip(self.audio_buffer.iter()) {
+            let frame = frame
+                .scale_amp(self.volume.get_param(buff_size))
+                .pan(self.pan.get_param(buff_size));
+            *frame_out = frame_out.add_amp(frame);
+        }
+    }
+}
+
 /// AudioMixer manage and mixes many AudioTrack.
 /// Also take care of the control events routing.
 pub struct AudioMixer {
-    /// SampleLib, owned by the mixer
-    sample_lib: SampleLib,
+    /// Sample loader handle, buffers are prepared off the audio thread
+    loader: LoaderHandle,
     /// Tracks owned by the mixer.
     tracks: Vec<AudioTrack>,
+    /// Group buses, tracks routed there are not summed to the output directly
+    groups: Vec<Bus>,
+    /// Send buses, summed to the output after the tracks
+    sends: Vec<Bus>,
+    /// Master limiter, None if disabled in the config
+    limiter: Option<Limiter>,
+    /// Direct outputs, pair 1 first, they skip the master limiter
+    outputs: Vec<Vec<Stereo<f32>>>,
+    /// Pre fader listen of the cued tracks
+    cue_buffer: Vec<Stereo<f32>>,
+    /// Input frames from the input stream
+    input_ring: VecDeque<Stereo<f32>>,
+    /// Input of the current block, for the recorders
+    input_buffer: Vec<Stereo<f32>>,
+    /// Output levels
+    master_meter: Meter,
+    /// Levels published to the meter thread
+    meter_levels: Arc<MeterLevels>,
+    /// Frames in a metering window
+    meter_window: usize,
+    /// Frames since the last publication
+    meter_count: usize,
     /// Clock ticks are counted here to keep sync with tracks
     clock_ticks: u64,
+    /// Transport state, tracks getting a buffer start right away when playing
+    playing: bool,
     /// Command bus reader. Lockless bus to read command messages
     command_rx: crossbeam_channel::Receiver<ControlMessage>,
+    /// Audio clock shared with the threads that timestamp messages
+    audio_clock: AudioClock,
+    /// Frame position of the current block
+    frame_clock: u64,
+    /// Frames of the last block, the block buffers can be longer
+    block_size: usize,
+    /// Frame of the commands being applied
+    command_frame: u64,
+    /// Messages waiting for their frame, sorted by frame
+    scheduled: Vec<(u64, ControlMessage)>,
 }
 
 /// AudioMixer implementation.
 impl AudioMixer {
     /// init a new mixer, a lot of heavy lifting here
-    pub fn new(conf: Config, command_rx: crossbeam_channel::Receiver<ControlMessage>) -> Self {
-        // init the sample lib, crash of err
-        let sample_lib = crate::sample_lib::init_lib(conf.clone())
-            .expect("Unable to load some samples, maybe an issue with the AUDIO_ROOT in conf ?");
+    pub fn new(
+        conf: Config,
+        command_rx: crossbeam_channel::Receiver<ControlMessage>,
+        audio_clock: AudioClock,
+        loader: LoaderHandle,
+    ) -> Self {
+        // the whole engine runs at the audio clock sample rate
+        let sample_rate = audio_clock.sample_rate();
 
         // create tracks according to the config
+        // the loader sends their first buffer
         let mut tracks = Vec::new();
         for t in conf.tracks.iter() {
-            match t {
-                TrackType::RePitchGen { bank } => {
-                    let gen = RePitchGen::new();
-                    let mut track = AudioTrack::new(Box::new(gen), *bank);
-                    track.load_first_buffer(&sample_lib);
-                    tracks.push(track);
+            let fx = FxChain::new(&t.fx, sample_rate);
+            // one level per send bus, missing ones are silent
+            let sends = (0..conf.sends.len())
+                .map(|i| {
+                    let level = t.sends.get(i).cloned().unwrap_or(0.0);
+                    SmoothParam::new(level, level)
+                })
+                .collect();
+            // unknown groups go to the output
+            let group = match t.group {
+                Some(g) if g >= conf.groups.len() => {
+                    println!("mixer: no group bus {}, track goes to the output", g);
+                    None
                 }
-                TrackType::SlicerGen { bank } => {
-                    let gen = SlicerGen::new();
-                    let mut track = AudioTrack::new(Box::new(gen), *bank);
-                    track.load_first_buffer(&sample_lib);
-                    tracks.push(track);
+                g => g,
+            };
+            let mut track = match t.generator {
+                TrackType::RePitchGen { bank: _ } => {
+                    let gen = RePitchGen::new(sample_rate);
+                    AudioTrack::new(Box::new(gen), fx, sends, group, t.output)
                 }
-                TrackType::PVOCGen { bank } => {
-                    let gen = PVOCGen::new();
-                    let mut track = AudioTrack::new(Box::new(gen), *bank);
-                    track.load_first_buffer(&sample_lib);
-                    tracks.push(track);
+                TrackType::SlicerGen {
+                    bank: _,
+                    ref notes,
+                    ref pattern,
+                    ref sample_patterns,
+                } => {
+                    let mut gen = SlicerGen::new(sample_rate);
+                    if let Some(notes) = notes {
+                        gen.set_notes(notes.clone());
+                    }
+                    gen.set_patterns(pattern.clone(), sample_patterns.clone());
+                    let mut track = AudioTrack::new(Box::new(gen), fx, sends, group, t.output);
+                    track.slicer = true;
+                    track
                 }
-            }
+                TrackType::PVOCGen { bank: _ } => {
+                    let gen = PVOCGen::new(sample_rate);
+                    AudioTrack::new(Box::new(gen), fx, sends, group, t.output)
+                }
+                // plays its takes like a repitch track
+                TrackType::Recorder { bank, source, bars } => {
+                    let gen = RePitchGen::new(sample_rate);
+                    let mut track = AudioTrack::new(Box::new(gen), fx, sends, group, t.output);
+                    track.recorder = Some(Recorder::new(bank, source, bars, sample_rate));
+                    track
+                }
+            };
+            track.trigger = t.trigger;
+            tracks.push(track);
         }
 
+        // buses
+        let groups = conf
+            .groups
+            .iter()
+            .map(|g| Bus::new(g, sample_rate))
+            .collect();
+        let sends = conf
+            .sends
+            .iter()
+            .map(|s| Bus::new(s, sample_rate))
+            .collect();
+
+        // output pairs used by the tracks and buses, main included
+        let num_pairs = conf
+            .tracks
+            .iter()
+            .map(|t| t.output)
+            .chain(
+                conf.groups
+                    .iter()
+                    .chain(conf.sends.iter())
+                    .map(|b| b.output),
+            )
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        // master dynamics
+        let limiter = if conf.master.limiter {
+            Some(Limiter::new(&conf.master, sample_rate))
+        } else {
+            None
+        };
+
         AudioMixer {
             tracks,
+            groups,
+            sends,
+            limiter,
+            outputs: vec![vec![Stereo::<f32>::equilibrium(); MAX_BLOCK_SIZE]; num_pairs - 1],
+            cue_buffer: vec![Stereo::<f32>::equilibrium(); MAX_BLOCK_SIZE],
+            input_ring: VecDeque::with_capacity(INPUT_RING_CAPACITY),
+            input_buffer: vec![Stereo::<f32>::equilibrium(); MAX_BLOCK_SIZE],
+            master_meter: Meter::default(),
+            meter_levels: Arc::new(MeterLevels::new(conf.tracks.len())),
+            meter_window: (sample_rate / conf.meter_rate.max(0.1)) as usize,
+            meter_count: 0,
             command_rx,
             clock_ticks: 0,
-            sample_lib,
+            playing: false,
+            loader,
+            audio_clock,
+            frame_clock: 0,
+            block_size: 0,
+            command_frame: 0,
+            scheduled: Vec::with_capacity(SCHEDULED_CAPACITY),
         }
     }
 
@@ -196,183 +443,649 @@ impl AudioMixer {
         return self.tracks.len();
     }
 
+    /// Get the number of output pairs, main included
+    pub fn get_output_pairs(&self) -> usize {
+        self.outputs.len() + 1
+    }
+
+    /// Get a direct output of the last block, empty if the pair is not used
+    pub fn get_output_block(&self, pair: usize) -> &[Stereo<f32>] {
+        match pair.checked_sub(1).and_then(|p| self.outputs.get(p)) {
+            Some(out) => &out[..self.block_size],
+            None => &[],
+        }
+    }
+
+    /// Get the cue output of the last block
+    pub fn get_cue_block(&self) -> &[Stereo<f32>] {
+        &self.cue_buffer[..self.block_size]
+    }
+
+    /// Pushes interleaved samples of the input stream, the first pair is kept, mono goes to both sides
+    pub fn push_input(&mut self, samples: &[f32], channels: usize) {
+        for s in samples.chunks(channels.max(1)) {
+            let frame = match s.len() {
+                1 => [s[0], s[0]],
+                _ => [s[0], s[1]],
+            };
+            if self.input_ring.len() == INPUT_RING_CAPACITY {
+                self.input_ring.pop_front();
+            }
+            self.input_ring.push_back(frame);
+        }
+    }
+
+    /// Get the levels published by the mixer, for the meter thread
+    pub fn get_meter_levels(&self) -> Arc<MeterLevels> {
+        self.meter_levels.clone()
+    }
+
     /// Reads blocks for all the tracks and mix them
     pub fn next_block(&mut self, block_out: &mut [Stereo<f32>]) {
+        // get size
+        let buff_size = block_out.len();
+        let block_start = self.frame_clock;
+        let block_end = block_start + buff_size as u64;
+        self.block_size = buff_size;
+
+        // let the other threads know where the audio is
+        self.audio_clock.publish(block_start, buff_size);
+
         // first fetch commands
+        self.command_frame = block_start;
         self.fetch_commands();
 
-        // get size
-        let buff_size = block_out.len();
+        // input of the block, silence if the input stream is late
+        // the buffer only grows for a device asking more than MAX_BLOCK_SIZE
+        if self.input_buffer.len() < buff_size {
+            self.input_buffer
+                .resize(buff_size, Stereo::<f32>::equilibrium());
+        }
+        for frame in self.input_buffer[..buff_size].iter_mut() {
+            *frame = self
+                .input_ring
+                .pop_front()
+                .unwrap_or_else(Stereo::<f32>::equilibrium);
+        }
 
-        // fill each tracks blocks
-        for track in self.tracks.iter_mut() {
-            track.fill_next_block(buff_size);
+        // fill each tracks blocks, segment by segment
+        // so scheduled commands are applied on their exact frame
+        let mut cursor = 0;
+        while cursor < buff_size {
+            // apply commands due now
+            self.apply_due_commands(block_start + cursor as u64);
+
+            // the segment ends at the next scheduled command
+            let segment_end = match self.scheduled.first() {
+                Some((frame, _)) if *frame < block_end => (*frame - block_start) as usize,
+                _ => buff_size,
+            };
+
+            for track in self.tracks.iter_mut() {
+                track.fill_next_segment(buff_size, cursor, segment_end);
+            }
+            cursor = segment_end;
+        }
+
+        // clear the buses
+        for bus in self.groups.iter_mut().chain(self.sends.iter_mut()) {
+            bus.clear(buff_size);
+        }
+        clear_block(&mut self.cue_buffer, buff_size);
+        for out in self.outputs.iter_mut() {
+            clear_block(out, buff_size);
         }
 
         // MIX!
         for (i, frame_out) in block_out.iter_mut().enumerate() {
             // 64 bit mixer
             let mut acc = Stereo::<f64>::equilibrium();
+            let mut cue = Stereo::<f32>::equilibrium();
             for track in self.tracks.iter_mut() {
                 let mut frame = track.get_frame(i);
 
+                // cue stage, pre fader
+                cue = cue.add_amp(frame.scale_amp(track.cue.get_param(MUTE_FADE_LEN)));
+
                 // volume stage
                 frame = frame.scale_amp(track.volume.get_param(buff_size));
 
                 // pan stage
                 frame = frame.pan(track.pan.get_param(buff_size));
 
-                // mix stage
-                acc[0] += frame[0] as f64;
-                acc[1] += frame[1] as f64;
+                // mute and solo stage
+                frame = frame.scale_amp(track.gate.get_param(MUTE_FADE_LEN));
+
+                // meter stage
+                track.meter.add(frame);
+
+                // send stage, post fader
+                for (bus, level) in self.sends.iter_mut().zip(track.sends.iter_mut()) {
+                    bus.add_frame(i, frame.scale_amp(level.get_param(buff_size)));
+                }
+
+                // mix stage, to the group, the main or a direct output
+                match (track.group, track.output) {
+                    (Some(g), _) => self.groups[g].add_frame(i, frame),
+                    (None, 0) => {
+                        acc[0] += frame[0] as f64;
+                        acc[1] += frame[1] as f64;
+                    }
+                    (None, p) => {
+                        let out = &mut self.outputs[p - 1];
+                        out[i] = out[i].add_amp(frame);
+                    }
+                }
             }
 
             // write
             frame_out[0] = acc[0] as f32;
             frame_out[1] = acc[1] as f32;
+            self.cue_buffer[i] = cue;
+        }
+
+        // groups and send buses return
+        for bus in self.groups.iter_mut().chain(self.sends.iter_mut()) {
+            match bus.output {
+                0 => bus.mix_into(block_out),
+                p => bus.mix_into(&mut self.outputs[p - 1][..buff_size]),
+            }
+        }
+
+        // master limiter
+        if let Some(limiter) = self.limiter.as_mut() {
+            limiter.process_block(block_out);
+        }
+
+        // resampling, finished takes go to the loader
+        for (track_num, track) in self.tracks.iter_mut().enumerate() {
+            if let Some(recorder) = track.recorder.as_mut() {
+                let source = match recorder.source {
+                    RecordSource::Input => &self.input_buffer[..buff_size],
+                    RecordSource::Master => &block_out[..],
+                };
+                if let Some(frames) = recorder.capture(source) {
+                    let take = RecordedTake {
+                        track_num,
+                        bank: recorder.bank,
+                        frames,
+                        num_beats: recorder.num_beats(),
+                    };
+                    // missed, the buffer is reused for the next take
+                    if let Err(take) = self.loader.send_take(take) {
+                        recorder.recycle(take.frames);
+                        self.loader.miss_takes(1);
+                    }
+                }
+                // the loader reports them, the audio thread can't print
+                let missed = recorder.missed_takes();
+                if missed > 0 {
+                    self.loader.miss_takes(missed);
+                }
+            }
+        }
+
+        // metering, published once per window
+        for frame in block_out.iter() {
+            self.master_meter.add(*frame);
+        }
+        self.meter_count += buff_size;
+        if self.meter_count >= self.meter_window {
+            self.meter_count = 0;
+            self.publish_meters();
+        }
+
+        // advance
+        self.frame_clock = block_end;
+    }
+
+    /// Fades the tracks in or out after a mute or solo change.
+    /// Only the tracks that change are touched, their fade starts from where it is.
+    fn update_gates(&mut self) {
+        let any_solo = self.tracks.iter().any(|t| t.solo);
+        for t in self.tracks.iter_mut() {
+            let audible = !t.mute && (!any_solo || t.solo);
+            let gain = if audible { 1.0 } else { 0.0 };
+            if t.gate.target() != gain {
+                t.gate.retarget(gain);
+            }
+        }
+    }
+
+    /// Publishes the levels of the window to the meter thread, lock free
+    fn publish_meters(&mut self) {
+        let (peak, rms) = self.master_meter.take();
+        self.meter_levels.set_master(peak, rms);
+        for (track_num, track) in self.tracks.iter_mut().enumerate() {
+            let (peak, rms) = track.meter.take();
+            self.meter_levels.set_track(track_num, peak, rms);
         }
+        if let Some(limiter) = self.limiter.as_mut() {
+            self.meter_levels
+                .set_gain_reduction(limiter.take_gain_reduction());
+        }
+        self.meter_levels.publish();
     }
 
     /// Reads commands from the bus.
     /// Must iterate to consume all messages for one buffer cycle util its empty.
-    /// This is not fetching commands at sample level.
+    /// Commands are scheduled on the frame given by their timecode, late ones are applied at the block start.
     fn fetch_commands(&mut self) {
+        // swap the buffers prepared by the loader first, tracks may start playing in this block
+        while let Some(swap) = self.loader.try_swap() {
+            if let Some(t) = self.tracks.get_mut(swap.track_num) {
+                let old = t.generator.load_buffer(swap.buffer);
+                self.loader.reclaim(old);
+                // an empty track may start now
+                if self.playing {
+                    t.play();
+                }
+            }
+        }
+
+        // patterns the slicers replaced since the last block
+        for t in self.tracks.iter_mut() {
+            while let Some(old) = t.generator.replaced_pattern() {
+                self.loader.reclaim_pattern(old);
+            }
+        }
+
+        // take buffers for the recorders
+        while let Some(buffer) = self.loader.try_take_buffer() {
+            let tr = self.tracks.get_mut(buffer.track_num);
+            if let Some(recorder) = tr.and_then(|t| t.recorder.as_mut()) {
+                recorder.recycle(buffer.frames);
+            }
+        }
+
         // loop until all simultaneous commands are fetched
         loop {
             match self.command_rx.try_recv() {
                 // we have a message
-                Ok(command) => match command {
-                    // Change tracked Sample inside the bank
-                    ControlMessage::TrackSampleSelect {
-                        tcode: _,
-                        val,
-                        track_num,
-                    } => {
-                        // check if tracknum is around
-                        let tr = self.tracks.get_mut(track_num);
-                        if let Some(t) = tr {
-                            // set the new selecta
-                            t.sample_select.new_value(val);
-
-                            // match the resulting dir enum
-                            match t.sample_select.get_param() {
-                                Direction::Up(_) => {
-                                    t.load_next_buffer(&self.sample_lib);
-                                }
-                                Direction::Down(_) => {
-                                    t.load_prev_buffer(&self.sample_lib);
-                                }
-                                Direction::Stable(_) => {}
-                            }
-                        }
+                Ok(command) => {
+                    // no room left, apply now (this should not happen)
+                    if self.scheduled.len() == SCHEDULED_CAPACITY {
+                        self.apply_command(command);
+                        continue;
                     }
-                    // Next Sample
-                    ControlMessage::TrackNextSample {
-                        tcode: _,
-                        track_num,
-                    } => {
-                        // check if tracknum is around
-                        let tr = self.tracks.get_mut(track_num);
-                        if let Some(t) = tr {
-                            // set the next sample
-                            t.load_next_buffer(&self.sample_lib);
-                        }
+
+                    // late or untimed commands are applied at the block start
+                    let frame = command.tcode().max(self.frame_clock);
+
+                    // keep sorted, after the commands of the same frame
+                    let pos = self
+                        .scheduled
+                        .iter()
+                        .position(|(f, _)| *f > frame)
+                        .unwrap_or(self.scheduled.len());
+                    self.scheduled.insert(pos, (frame, command));
+                }
+                // its empty
+                _ => return,
+            };
+        } // loop
+    }
+
+    /// Applies all the scheduled commands that are due at this frame
+    fn apply_due_commands(&mut self, frame: u64) {
+        self.command_frame = frame;
+        while self.scheduled.first().map_or(false, |(f, _)| *f <= frame) {
+            let (_, command) = self.scheduled.remove(0);
+            self.apply_command(command);
+        }
+    }
+
+    /// Applies a command to the mixer and tracks
+    fn apply_command(&mut self, command: ControlMessage) {
+        match command {
+            // Sample selection is done by the loader, the buffer comes back as a swap
+            ControlMessage::TrackSampleSelect { .. }
+            | ControlMessage::TrackNextSample { .. }
+            | ControlMessage::TrackPrevSample { .. } => {
+                self.loader.request(command);
+            }
+            // Volume
+            ControlMessage::TrackVolume {
+                tcode: _,
+                val,
+                track_num,
+            } => {
+                // check if tracknum is around
+                let tr = self.tracks.get_mut(track_num);
+                if let Some(t) = tr {
+                    // set the volume
+                    t.volume.new_value(val);
+                }
+            }
+            // Pan
+            ControlMessage::TrackPan {
+                tcode: _,
+                val,
+                track_num,
+            } => {
+                // check if tracknum is around
+                let tr = self.tracks.get_mut(track_num);
+                if let Some(t) = tr {
+                    // set the pan
+                    t.pan.new_value(val);
+                }
+            }
+            // Insert FX params
+            ControlMessage::TrackFxParam {
+                tcode: _,
+                val,
+                track_num,
+                fx_num,
+                param,
+            } => {
+                // check if tracknum is around
+                let tr = self.tracks.get_mut(track_num);
+                if let Some(t) = tr {
+                    t.fx.set_param(fx_num, param, val);
+                }
+            }
+            // Send level
+            ControlMessage::TrackSend {
+                tcode: _,
+                val,
+                track_num,
+                send_num,
+            } => {
+                // check if tracknum and send are around
+                let tr = self.tracks.get_mut(track_num);
+                if let Some(level) = tr.and_then(|t| t.sends.get_mut(send_num)) {
+                    level.new_value(val);
+                }
+            }
+            // Send bus FX params
+            ControlMessage::SendFxParam {
+                tcode: _,
+                val,
+                send_num,
+                fx_num,
+                param,
+            } => {
+                if let Some(bus) = self.sends.get_mut(send_num) {
+                    bus.fx.set_param(fx_num, param, val);
+                }
+            }
+            // Send buses volume and pan
+            ControlMessage::SendVolume {
+                tcode: _,
+                val,
+                send_num,
+            } => {
+                if let Some(bus) = self.sends.get_mut(send_num) {
+                    bus.volume.new_value(val);
+                }
+            }
+            ControlMessage::SendPan {
+                tcode: _,
+                val,
+                send_num,
+            } => {
+                if let Some(bus) = self.sends.get_mut(send_num) {
+                    bus.pan.new_value(val);
+                }
+            }
+            // Group buses volume, pan and FX params
+            ControlMessage::GroupVolume {
+                tcode: _,
+                val,
+                group_num,
+            } => {
+                if let Some(bus) = self.groups.get_mut(group_num) {
+                    bus.volume.new_value(val);
+                }
+            }
+            ControlMessage::GroupPan {
+                tcode: _,
+                val,
+                group_num,
+            } => {
+                if let Some(bus) = self.groups.get_mut(group_num) {
+                    bus.pan.new_value(val);
+                }
+            }
+            ControlMessage::GroupFxParam {
+                tcode: _,
+                val,
+                group_num,
+                fx_num,
+                param,
+            } => {
+                if let Some(bus) = self.groups.get_mut(group_num) {
+                    bus.fx.set_param(fx_num, param, val);
+                }
+            }
+            // Mute and solo, the gates of all the tracks follow
+            ControlMessage::TrackMute {
+                tcode: _,
+                val,
+                track_num,
+            } => {
+                if let Some(t) = self.tracks.get_mut(track_num) {
+                    t.mute = val > 0.5;
+                }
+                self.update_gates();
+            }
+            ControlMessage::TrackSolo {
+                tcode: _,
+                val,
+                track_num,
+            } => {
+                if let Some(t) = self.tracks.get_mut(track_num) {
+                    t.solo = val > 0.5;
+                }
+                self.update_gates();
+            }
+            // Cue
+            ControlMessage::TrackCue {
+                tcode: _,
+                val,
+                track_num,
+            } => {
+                if let Some(t) = self.tracks.get_mut(track_num) {
+                    let gain = if val > 0.5 { 1.0 } else { 0.0 };
+                    if t.cue.target() != gain {
+                        t.cue.retarget(gain);
                     }
-                    // Previous Sample
-                    ControlMessage::TrackPrevSample {
-                        tcode: _,
-                        track_num,
-                    } => {
-                        // check if tracknum is around
-                        let tr = self.tracks.get_mut(track_num);
-                        if let Some(t) = tr {
-                            // set the prev sample
-                            t.load_prev_buffer(&self.sample_lib);
-                        }
+                }
+            }
+            // Recorder
+            ControlMessage::TrackRecord {
+                tcode: _,
+                val,
+                track_num,
+            } => {
+                let tr = self.tracks.get_mut(track_num);
+                if let Some(recorder) = tr.and_then(|t| t.recorder.as_mut()) {
+                    recorder.arm(val > 0.5);
+                }
+            }
+            // Triggered playback
+            ControlMessage::TrackTrigger {
+                tcode: _,
+                track_num,
+                slice,
+            } => {
+                if let Some(t) = self.tracks.get_mut(track_num) {
+                    if t.trigger.is_some() {
+                        t.pending_trigger = Some(slice);
                     }
-                    // Volume
-                    ControlMessage::TrackVolume {
-                        tcode: _,
-                        val,
-                        track_num,
-                    } => {
-                        // check if tracknum is around
-                        let tr = self.tracks.get_mut(track_num);
-                        if let Some(t) = tr {
-                            // set the volume
-                            t.volume.new_value(val);
-                        }
+                }
+            }
+            ControlMessage::TrackRelease {
+                tcode: _,
+                track_num,
+            } => {
+                if let Some(t) = self.tracks.get_mut(track_num) {
+                    if t.trigger.is_some() {
+                        t.pending_trigger = None;
+                        t.release_tick = None;
+                        t.generator.release();
                     }
-                    // Pan
-                    ControlMessage::TrackPan {
-                        tcode: _,
-                        val,
-                        track_num,
-                    } => {
-                        // check if tracknum is around
-                        let tr = self.tracks.get_mut(track_num);
-                        if let Some(t) = tr {
-                            // set the pan
-                            t.pan.new_value(val);
+                }
+            }
+            // Freeze
+            ControlMessage::TrackFreeze {
+                tcode: _,
+                val,
+                track_num,
+            } => {
+                if let Some(t) = self.tracks.get_mut(track_num) {
+                    if val > 0.5 {
+                        t.generator.freeze();
+                    } else {
+                        t.generator.unfreeze();
+                    }
+                }
+            }
+            // LoopDiv
+            ControlMessage::TrackLoopDiv {
+                tcode: _,
+                val,
+                track_num,
+            } => {
+                // check if tracknum is around
+                let tr = self.tracks.get_mut(track_num);
+                if let Some(t) = tr {
+                    // set the loop div
+                    t.set_loop_div(val);
+                }
+            }
+            // Playback management
+            ControlMessage::Playback(playback_message) => {
+                match playback_message.sync {
+                    SyncMessage::Start() => {
+                        // unmute all tracks
+                        for track in self.tracks.iter_mut() {
+                            track.play();
                         }
+                        self.clock_ticks = 0;
+                        self.playing = true;
                     }
-                    // LoopDiv
-                    ControlMessage::TrackLoopDiv {
-                        tcode: _,
-                        val,
-                        track_num,
-                    } => {
-                        // check if tracknum is around
-                        let tr = self.tracks.get_mut(track_num);
-                        if let Some(t) = tr {
-                            // set the loop div
-                            t.set_loop_div(val);
+                    SyncMessage::Stop() => {
+                        // mute all tracks
+                        for track in self.tracks.iter_mut() {
+                            track.stop();
                         }
+                        self.clock_ticks = 0;
+                        self.playing = false;
                     }
-                    // Playback management
-                    ControlMessage::Playback(playback_message) => {
-                        match playback_message.sync {
-                            SyncMessage::Start() => {
-                                // unmute all tracks
-                                for track in self.tracks.iter_mut() {
-                                    track.play();
-                                }
-                                self.clock_ticks = 0;
-                            }
-                            SyncMessage::Stop() => {
-                                // mute all tracks
-                                for track in self.tracks.iter_mut() {
-                                    track.stop();
-                                }
-                                self.clock_ticks = 0;
-                            }
-                            SyncMessage::Tick(_tick) => {
-                                // update tracks sync
-                                let global_tempo = playback_message.time.tempo;
-                                for track in self.tracks.iter_mut() {
-                                    track.sync(global_tempo as u64, self.clock_ticks);
+                    SyncMessage::Tick(_tick) => {
+                        // update tracks sync
+                        let global_tempo = playback_message.time.tempo;
+                        for track in self.tracks.iter_mut() {
+                            track.sync(global_tempo, self.clock_ticks);
+                        }
+                        for bus in self.groups.iter_mut().chain(self.sends.iter_mut()) {
+                            bus.fx.sync(global_tempo);
+                        }
+                        // recorders start and stop on the bars
+                        if self.clock_ticks % (PPQN as u64 * 4) == 0 {
+                            let offset = (self.command_frame - self.frame_clock) as usize;
+                            for track in self.tracks.iter_mut() {
+                                if let Some(recorder) = track.recorder.as_mut() {
+                                    recorder.bar(offset);
                                 }
-                                // inc ticks received by the mixer
-                                self.clock_ticks += 1;
                             }
                         }
+                        // inc ticks received by the mixer
+                        self.clock_ticks += 1;
                     }
-                    // got a slicer message, we just find the right track and pass down to the generator implementation
-                    ControlMessage::Slicer {
-                        tcode: _,
-                        track_num,
-                        message: _,
-                    } => {
-                        // check if tracknum is around
-                        let tr = self.tracks.get_mut(track_num);
-                        if let Some(t) = tr {
-                            t.generator.push_control_message(command);
+                }
+            }
+            // got a slicer message, we just find the right track and pass down to the generator implementation
+            ControlMessage::Slicer {
+                tcode: _,
+                track_num,
+                message: _,
+            } => {
+                // check if tracknum is around and is a slicer
+                let tr = self.tracks.get_mut(track_num).filter(|t| t.slicer);
+                match tr {
+                    Some(t) => t.generator.push_control_message(command),
+                    // nobody takes it, a pattern goes back to the loader to be freed
+                    None => {
+                        if let ControlMessage::Slicer {
+                            message: SlicerMessage::Pattern(pattern),
+                            ..
+                        } = command
+                        {
+                            self.loader.reclaim_pattern(pattern);
                         }
                     }
-                },
-                // its empty
-                _ => return,
-            };
-        } // loop
+                }
+            }
+            // clock commands are handled by the internal clock
+            ControlMessage::ClockStart { .. }
+            | ControlMessage::ClockStop { .. }
+            | ControlMessage::ClockTempo { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample_lib::loader::SampleLoader;
+    use crossbeam_channel::unbounded;
+    use std::fs;
+
+    /// a mixer of two repitch tracks on an empty library
+    fn test_mixer(name: &str) -> AudioMixer {
+        let root = std::env::temp_dir().join(format!("smplr_mixer_{}", name));
+        fs::create_dir_all(&root).expect("create test root");
+        let conf: Config = toml::from_str(&format!(
+            "audio_root = \"{}\"\ntracks = [{{ RePitchGen = {{ bank = 0 }} }}, {{ RePitchGen = {{ bank = 0 }} }}]\n[midi_map.cc]\n",
+            root.to_str().unwrap()
+        ))
+        .expect("test config");
+        let (_cx_tx, cx_rx) = unbounded::<ControlMessage>();
+        let (_loader, handle) = SampleLoader::new(conf.clone(), 44_100.0);
+        AudioMixer::new(conf, cx_rx, AudioClock::new(44_100.0), handle)
+    }
+
+    fn mute(mixer: &mut AudioMixer, track_num: usize, on: bool) {
+        mixer.apply_command(ControlMessage::TrackMute {
+            tcode: 0,
+            val: if on { 1.0 } else { 0.0 },
+            track_num,
+        });
+    }
+
+    /// steps the gate of a track, fails if it jumps more than one fade step
+    fn step_gate(mixer: &mut AudioMixer, track_num: usize, frames: usize, last: &mut f32) {
+        let max_step = 1.0 / MUTE_FADE_LEN as f32 + 1e-5;
+        for _ in 0..frames {
+            let gain = mixer.tracks[track_num].gate.get_param(MUTE_FADE_LEN);
+            assert!(
+                (gain - *last).abs() <= max_step,
+                "gate stepped from {} to {}",
+                last,
+                gain
+            );
+            *last = gain;
+        }
+    }
+
+    #[test]
+    fn test_mute_toggle_mid_fade() {
+        let mut mixer = test_mixer("mute_toggle");
+        let mut last = 1.0;
+
+        // mute, unmute and mute again before the fades end
+        mute(&mut mixer, 0, true);
+        step_gate(&mut mixer, 0, MUTE_FADE_LEN / 4, &mut last);
+        mute(&mut mixer, 0, false);
+        step_gate(&mut mixer, 0, MUTE_FADE_LEN / 8, &mut last);
+        mute(&mut mixer, 0, true);
+        step_gate(&mut mixer, 0, MUTE_FADE_LEN / 4, &mut last);
+
+        // muting another track leaves the running fade alone
+        mute(&mut mixer, 1, true);
+        step_gate(&mut mixer, 0, MUTE_FADE_LEN * 2, &mut last);
+        assert_eq!(last, 0.0);
     }
 }
@@ -1,68 +1,104 @@
 #![allow(dead_code)]
 extern crate cpal;
 
-mod filters;
+mod delay;
+pub(crate) mod filters;
+mod fx;
+mod limiter;
+mod meter;
 mod mixer;
+pub mod offline;
+mod recorder;
+mod reverb;
 
-use self::cpal::{EventLoop, SampleFormat, StreamData, UnknownTypeOutputBuffer};
+use self::cpal::{
+    EventLoop, SampleFormat, StreamData, UnknownTypeInputBuffer, UnknownTypeOutputBuffer,
+};
 use sample::frame::Stereo;
+use self::meter::initialize_meters;
 use sample::ToFrameSliceMut;
-use crate::control::ControlMessage;
+use crate::control::{AudioClock, ControlMessage, Notification};
+use crate::sample_lib::loader::SampleLoader;
 use std::thread;
 
-use crate::config::Config;
-
-/// Loudness, per block
-pub fn loudness(block_out: &[Stereo<f32>]) -> f32 {
-    let mut sum = 0.0f32;
-    for s in block_out {
-        let a = (s[0] + s[1]) / 2.0;
-        sum += a * a;
-    }
-
-    let rms = f32::sqrt(sum / block_out.len() as f32);
-    //  let decibel = 20.0 * f32::log10(rms);
-    return rms;
-}
+use crate::config::{Config, RecordSource, TrackType};
 
 /// Initialize audio machinery
 pub fn initialize_audio(
-    conf: Config,
+    mut conf: Config,
     hub_rx: crossbeam_channel::Receiver<ControlMessage>,
+    audio_clock: AudioClock,
+    notify_tx: crossbeam_channel::Sender<Notification>,
 ) -> thread::JoinHandle<()> {
-    // init mixer
-    let mut mixer = mixer::AudioMixer::new(conf, hub_rx);
-
-    // enumerate all devices
-    //  enumerate_all_devices();
-
     // init audio with CPAL !
     // creates event loop
     let event_loop = EventLoop::new();
 
-    // audio out device
-    let device = cpal::default_output_device().expect("audio: no output device available");
+    // audio out device, by name if set
+    let device = select_device(&conf.device, false).expect("audio: no output device available");
 
     // get the current default out format
+    // the device sample rate is the engine sample rate, samples are resampled at load time
     let mut format = device
         .default_output_format()
         .expect("should have a default format");
 
-    // it's doing weird things otherwise 
-    if format.sample_rate != cpal::SampleRate(44100) {
-        println!("Unsupported Device SampleRate, should be 44100");
-        ::std::process::exit(1);
-    }
+    // the whole engine runs at this rate
+    audio_clock.set_sample_rate(format.sample_rate.0 as f64);
+
+    // init the sample loader, in its own thread, watching the audio root
+    let (mut loader, loader_handle) = SampleLoader::new(conf.clone(), format.sample_rate.0 as f64);
+    loader.watch(conf.clone(), notify_tx.clone());
+    thread::spawn(move || loader.run());
+
+    // the cue needs its own output pair
+    let cue_pair = match conf.cue_pair {
+        Some(0) => {
+            println!("audio: the cue can't use the main output pair, disabled");
+            None
+        }
+        p => p,
+    };
+
+    // the input is only opened for the recorders
+    let needs_input = conf.tracks.iter().any(|t| match t.generator {
+        TrackType::Recorder { source, .. } => source == RecordSource::Input,
+        _ => false,
+    });
+    let input_device = if needs_input {
+        select_device(&conf.input_device, true)
+    } else {
+        None
+    };
+
+    // pairs the device can't take are folded back into the main pair
+    let max_pairs = max_output_channels(&device) / 2;
+    fold_outputs(&mut conf, max_pairs);
+
+    // init mixer
+    let meter_rate = conf.meter_rate;
+    let mut mixer = mixer::AudioMixer::new(conf, hub_rx, audio_clock, loader_handle);
 
-    // force the sample rate
-    format.sample_rate = cpal::SampleRate(44100);
+    // levels go to the remote controller from their own thread
+    initialize_meters(mixer.get_meter_levels(), meter_rate, notify_tx);
 
-    // force the number of channel
-    format.channels = 2;
+    // force the number of channels, enough pairs for the outputs and the cue
+    let pairs = mixer.get_output_pairs().max(cue_pair.map_or(0, |p| p + 1));
+    if pairs > max_pairs {
+        println!(
+            "audio: the cue needs pair {}, the device has {} pairs, cue disabled",
+            pairs - 1,
+            max_pairs
+        );
+    }
+    let pairs = pairs.min(max_pairs).max(1);
+    let channels = pairs * 2;
+    format.channels = channels as u16;
 
     // display some info
     println!("audio device: {}", device.name());
-    println!("audio: Fixed OUTPUT Samplerate: {}", format.sample_rate.0);
+    println!("audio: OUTPUT Samplerate: {}", format.sample_rate.0);
+    println!("audio: OUTPUT Channels: {}", channels);
 
     match format.data_type {
         SampleFormat::U16 => println!("audio: Supported sample type is U16"),
@@ -78,29 +114,80 @@ pub fn initialize_audio(
     // add stream
     event_loop.play_stream(stream_id);
 
-//    let mut _max_rms = 0.0;
+    // input stream, at the engine sample rate, channels of the device
+    let mut input_channels = 0;
+    if let Some(input) = input_device {
+        let input_format = input.default_input_format().map(|mut f| {
+            f.sample_rate = format.sample_rate;
+            f
+        });
+        match input_format.map(|f| (event_loop.build_input_stream(&input, &f), f)) {
+            Ok((Ok(input_id), f)) => {
+                println!("audio input device: {}", input.name());
+                event_loop.play_stream(input_id);
+                input_channels = f.channels as usize;
+                if f.data_type != SampleFormat::F32 {
+                    println!("audio: the input is not F32, the recorders get silence");
+                }
+            }
+            _ => println!("audio: unable to open the input device {}", input.name()),
+        }
+    }
+
+    // main output, when the device has more than two channels
+    let mut main_out = vec![Stereo::<f32>::equilibrium(); mixer::MAX_BLOCK_SIZE];
 
     // initialize in its own thread
     let audio_thread = thread::spawn(move || {
         // audio callback
         event_loop.run(move |_stream_id, stream_data| {
             match stream_data {
+                // recorders input, read at the next output block
+                StreamData::Input {
+                    buffer: UnknownTypeInputBuffer::F32(buffer),
+                } => mixer.push_input(&buffer, input_channels),
                 StreamData::Output {
                     buffer: UnknownTypeOutputBuffer::F32(mut buffer),
                 } => {
-                    // here we implement the trait sample::ToFrameSliceMut;
-                    // we can take a mutable buffer from the audio callback, but framed in stereo !!
-                    let buffer: &mut [Stereo<f32>] = buffer.to_frame_slice_mut().unwrap();
-
-                    // write audio from the mixer
-                    mixer.next_block(buffer);
-
-                    // calculate output volume
-                    // let loud = loudness(buffer);
-                    // if loud > max_rms {
-                    //  max_rms = loud;
-                    // }
-                    // println!("lourd {}", max_rms);
+                    if pairs == 1 {
+                        // here we implement the trait sample::ToFrameSliceMut;
+                        // we can take a mutable buffer from the audio callback, but framed in stereo !!
+                        let buffer: &mut [Stereo<f32>] = buffer.to_frame_slice_mut().unwrap();
+
+                        // write audio from the mixer, levels are metered there
+                        mixer.next_block(buffer);
+                        return;
+                    }
+
+                    // the main output only grows for a device asking more than MAX_BLOCK_SIZE
+                    let frames = buffer.len() / channels;
+                    if main_out.len() < frames {
+                        main_out.resize(frames, Stereo::<f32>::equilibrium());
+                    }
+                    let main_out = &mut main_out[..frames];
+                    mixer.next_block(main_out);
+
+                    // interleave the pairs, silence on the unused ones
+                    for pair in 0..pairs {
+                        let src = match pair {
+                            0 => &main_out[..],
+                            p => mixer.get_output_block(p),
+                        };
+                        for (i, out) in buffer.chunks_mut(channels).enumerate() {
+                            let frame = src.get(i).cloned().unwrap_or([0.0, 0.0]);
+                            out[pair * 2] = frame[0];
+                            out[pair * 2 + 1] = frame[1];
+                        }
+                    }
+
+                    // the cue is summed on its pair
+                    if let Some(pair) = cue_pair.filter(|p| *p < pairs) {
+                        let cue = mixer.get_cue_block();
+                        for (out, frame) in buffer.chunks_mut(channels).zip(cue.iter()) {
+                            out[pair * 2] += frame[0];
+                            out[pair * 2 + 1] += frame[1];
+                        }
+                    }
                 }
                 _ => (),
             }
@@ -110,6 +197,60 @@ pub fn initialize_audio(
     audio_thread
 }
 
+/// Finds a device by name, case insensitive, falls back on the default device
+fn select_device(name: &Option<String>, input: bool) -> Option<cpal::Device> {
+    if let Some(name) = name {
+        let wanted = name.to_lowercase();
+        let matching = |d: &cpal::Device| d.name().to_lowercase().contains(&wanted);
+        let found = if input {
+            cpal::input_devices().find(matching)
+        } else {
+            cpal::output_devices().find(matching)
+        };
+        if found.is_some() {
+            return found;
+        }
+        println!(
+            "audio: no device matching \"{}\", using the default one",
+            name
+        );
+        enumerate_all_devices();
+    }
+    if input {
+        cpal::default_input_device()
+    } else {
+        cpal::default_output_device()
+    }
+}
+
+/// Sends the tracks and buses on a pair the device doesn't have to the main pair
+fn fold_outputs(conf: &mut Config, max_pairs: usize) {
+    let tracks = conf.tracks.iter_mut().map(|t| &mut t.output);
+    let buses = conf
+        .groups
+        .iter_mut()
+        .chain(conf.sends.iter_mut())
+        .map(|b| &mut b.output);
+    for output in tracks.chain(buses) {
+        if *output >= max_pairs {
+            println!(
+                "audio: no output pair {}, the device has {}, it goes to the main pair",
+                output, max_pairs
+            );
+            *output = 0;
+        }
+    }
+}
+
+/// Highest channel count the device supports, at least a stereo pair
+fn max_output_channels(device: &cpal::Device) -> usize {
+    device
+        .supported_output_formats()
+        .map(|formats| formats.map(|f| f.channels as usize).max().unwrap_or(2))
+        .unwrap_or(2)
+        .max(2)
+}
+
 // enumerate devices
 fn enumerate_all_devices() {
     let devices = cpal::devices();
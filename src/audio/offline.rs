@@ -0,0 +1,299 @@
+//! Offline renderer drives the AudioMixer without any sound card.
+//! A render script feeds the mixer with a clock and scripted control messages,
+//! the output is bounced to a wave file.
+//! Useful on headless build boxes, and to regression-test the sample generators deterministically.
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+
+use crossbeam_channel::unbounded;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use sample::frame::{Frame, Stereo};
+use toml::from_str;
+
+use super::mixer::AudioMixer;
+use crate::config::Config;
+use crate::control::{AudioClock, ControlMessage, PlaybackMessage, SyncMessage};
+use crate::midi::MidiTime;
+use crate::sample_lib::loader::SampleLoader;
+use crate::sample_gen::PPQN;
+
+/// Default sample rate of the offline render
+const RENDER_SAMPLE_RATE: u32 = 44_100;
+
+/// Block size used to pull audio from the mixer, same as the live audio callback
+const RENDER_BLOCK_SIZE: usize = 128;
+
+/// RenderScript describes an offline render session.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RenderScript {
+    /// Tempo of the render clock
+    pub tempo: f64,
+    /// Length of the render in bars (4/4)
+    pub bars: u64,
+    /// Sample rate of the render
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: u32,
+    /// Scripted control messages
+    #[serde(default)]
+    pub events: Vec<ScriptEvent>,
+}
+
+/// ScriptEvent is a control message scheduled on a clock tick.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScriptEvent {
+    /// Tick position from the render start, in PPQN
+    pub tick: u64,
+    /// Message sent to the mixer at that tick
+    pub message: ControlMessage,
+}
+
+/// Serde default for the render sample rate
+fn default_sample_rate() -> u32 {
+    RENDER_SAMPLE_RATE
+}
+
+/// RenderScript implementation
+impl RenderScript {
+    /// Length of the render in clock ticks
+    pub fn total_ticks(&self) -> u64 {
+        self.bars * 4 * PPQN as u64
+    }
+}
+
+/// Loads and parse a render script.
+/// Events past the end of the render are rejected, they would never be sent.
+pub fn load_script(path: &str) -> Result<RenderScript, Box<dyn Error>> {
+    // load toml file
+    let mut input = String::new();
+
+    // open
+    let mut f = File::open(&path)?;
+    f.read_to_string(&mut input)?;
+
+    // parse
+    let script: RenderScript = from_str(&input)?;
+
+    // check the events fit in the render
+    let total_ticks = script.total_ticks();
+    if let Some(e) = script.events.iter().find(|e| e.tick >= total_ticks) {
+        return Err(format!(
+            "event on tick {} is past the end of the render ({} ticks)",
+            e.tick, total_ticks
+        )
+        .into());
+    }
+
+    // ret
+    Ok(script)
+}
+
+/// Renders a script to a 32 bits float wave file.
+/// Blocks are written as they are rendered, so long sets don't sit in memory.
+pub fn render_to_file(
+    conf: Config,
+    script: &RenderScript,
+    path: &str,
+) -> Result<(), Box<dyn Error>> {
+    // wave spec
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate: script.sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+
+    // write interleaved, block by block
+    let mut writer = WavWriter::create(path, spec)?;
+    render_blocks(conf, script, |block| {
+        for frame in block.iter() {
+            writer.write_sample(frame[0])?;
+            writer.write_sample(frame[1])?;
+        }
+        Ok(())
+    })?;
+    writer.finalize()?;
+
+    Ok(())
+}
+
+/// Renders a script in memory
+#[cfg(test)]
+fn render(conf: Config, script: &RenderScript) -> Vec<Stereo<f32>> {
+    let mut frames = Vec::new();
+    render_blocks(conf, script, |block| {
+        frames.extend_from_slice(block);
+        Ok(())
+    })
+    .expect("in memory render");
+    frames
+}
+
+/// Renders a script, each block is handed to the sink as soon as the mixer filled it.
+/// Ticks and events are timestamped on their exact frame, as the live clocks do.
+fn render_blocks<F>(conf: Config, script: &RenderScript, mut sink: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&[Stereo<f32>]) -> Result<(), Box<dyn Error>>,
+{
+    // the mixer reads from this bus, just like in the audio thread
+    let (cx_tx, cx_rx) = unbounded::<ControlMessage>();
+    let audio_clock = AudioClock::new(script.sample_rate as f64);
+    let (mut loader, loader_handle) = SampleLoader::new(conf.clone(), script.sample_rate as f64);
+    let mut mixer = AudioMixer::new(conf, cx_rx, audio_clock, loader_handle);
+
+    // events must be sent in order, the ones past the end are never sent
+    let mut events = script.events.clone();
+    events.sort_by_key(|e| e.tick);
+    let mut events = events.into_iter().peekable();
+
+    // clock
+    let frames_per_tick = 60.0 * script.sample_rate as f64 / (script.tempo * PPQN as f64);
+    let total_ticks = script.total_ticks();
+    let total_frames = (total_ticks as f64 * frames_per_tick) as usize;
+    let mut midi_time = MidiTime::new(script.tempo);
+    let mut next_tick = 0u64;
+
+    // output, one block at a time
+    let mut block = vec![Stereo::<f32>::equilibrium(); RENDER_BLOCK_SIZE];
+
+    // start the playback
+    cx_tx
+        .send(ControlMessage::Playback(PlaybackMessage {
+            tcode: 0,
+            sync: SyncMessage::Start(),
+            time: midi_time.clone(),
+        }))
+        .expect("mixer bus is open");
+
+    let mut block_start = 0;
+    while block_start < total_frames {
+        // the last block may be shorter
+        let block_out = &mut block[..RENDER_BLOCK_SIZE.min(total_frames - block_start)];
+
+        // end of this block in frames
+        let block_end = (block_start + block_out.len()) as f64;
+
+        // push every tick that falls in this block
+        while next_tick < total_ticks && next_tick as f64 * frames_per_tick < block_end {
+            // exact frame of the tick
+            let tick_frame = (next_tick as f64 * frames_per_tick) as u64;
+
+            // scripted events first, so they are applied on their tick
+            while events.peek().map_or(false, |e| e.tick <= next_tick) {
+                let mut message = events.next().unwrap().message;
+                message.set_tcode(tick_frame);
+                cx_tx.send(message).expect("mixer bus is open");
+            }
+
+            // then the tick itself
+            cx_tx
+                .send(ControlMessage::Playback(PlaybackMessage {
+                    tcode: tick_frame,
+                    sync: SyncMessage::Tick(next_tick),
+                    time: midi_time.clone(),
+                }))
+                .expect("mixer bus is open");
+            midi_time.tick_at_tempo(script.tempo);
+            next_tick += 1;
+        }
+
+        // the loader answers between two blocks, so sample switches land at the same place on every render
+        loader.poll();
+
+        // pull audio
+        mixer.next_block(block_out);
+        sink(block_out)?;
+
+        block_start += block_out.len();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample_gen::slicer::TransformType;
+    use std::f32::consts::PI;
+    use std::fs;
+
+    /// writes a 4 beats loop at 120 bpm, one decaying burst per beat
+    fn write_test_loop(root: &str) {
+        let bank = format!("{}/0", root);
+        fs::create_dir_all(&bank).expect("create test bank");
+
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: RENDER_SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer =
+            WavWriter::create(format!("{}/loop_120bpm.wav", bank), spec).expect("create test wave");
+
+        let beat_len = RENDER_SAMPLE_RATE as usize / 2;
+        for i in 0..(beat_len * 4) {
+            let env = (-((i % beat_len) as f32) / 2000.0).exp();
+            let s = env * (2.0 * PI * 220.0 * i as f32 / RENDER_SAMPLE_RATE as f32).sin();
+            let s = (s * i16::max_value() as f32) as i16;
+            writer.write_sample(s).unwrap();
+            writer.write_sample(s).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    /// renders one bar of a single track twice and check both renders are equal.
+    /// Renders at 48 kHz, so the 44.1 kHz loop gets resampled.
+    fn assert_deterministic(name: &str, track: &str, events: Vec<ScriptEvent>) {
+        let root = std::env::temp_dir().join(format!("smplr_offline_{}", name));
+        let root = root.to_str().unwrap();
+        write_test_loop(root);
+
+        let conf: Config = from_str(&format!(
+            "audio_root = \"{}\"\ntracks = [{}]\n[midi_map.cc]\n",
+            root, track
+        ))
+        .expect("test config");
+        let script = RenderScript {
+            tempo: 130.0,
+            bars: 1,
+            sample_rate: 48_000,
+            events,
+        };
+
+        let first = render(conf.clone(), &script);
+        let second = render(conf, &script);
+
+        assert!(first.len() > 0);
+        assert!(first.iter().any(|f| f[0] != 0.0));
+        assert_eq!(first, second);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_render_slicer() {
+        let events = vec![ScriptEvent {
+            tick: 48,
+            message: ControlMessage::Slicer {
+                tcode: 0,
+                track_num: 0,
+                message: crate::control::SlicerMessage::Transform(TransformType::QuantRepeat {
+                    quant: 8,
+                    slice_index: 0,
+                }),
+            },
+        }];
+        assert_deterministic("slicer", "{ SlicerGen = { bank = 0 } }", events);
+    }
+
+    #[test]
+    fn test_render_repitch() {
+        assert_deterministic("repitch", "{ RePitchGen = { bank = 0 } }", vec![]);
+    }
+
+    #[test]
+    fn test_render_pvoc() {
+        assert_deterministic("pvoc", "{ PVOCGen = { bank = 0 } }", vec![]);
+    }
+}
@@ -0,0 +1,211 @@
+//! Live resampling.
+//! A recorder captures the input or the master output from a bar boundary to another,
+//! into a take buffer allocated off the audio thread. Finished takes go to the loader,
+//! that turns them into SmartBuffers with a known tempo and beat count.
+use sample::frame::Stereo;
+
+use crate::config::RecordSource;
+
+/// Slowest tempo a full take fits in, the take buffers are sized for it
+const MIN_RECORD_TEMPO: f64 = 60.0;
+
+/// Beats in a bar
+const BAR_BEATS: usize = 4;
+
+/// RecordState enum, where the recorder is at
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum RecordState {
+    /// Waiting to be armed
+    Idle(),
+    /// Starts on the next bar
+    Armed(),
+    /// Capturing, stops after the given bars
+    Recording(),
+}
+
+/// Recorder is the recording part of a recorder track
+pub struct Recorder {
+    /// Bank the takes are stored in
+    pub bank: usize,
+    /// What is captured
+    pub source: RecordSource,
+    /// Length of a take in bars
+    bars: usize,
+    /// Current state
+    state: RecordState,
+    /// Bars captured in the current take
+    bars_done: usize,
+    /// Take buffer, None while the loader prepares the next one
+    frames: Option<Vec<Stereo<f32>>>,
+    /// Capture starts at this offset of the current block
+    block_start: usize,
+    /// Capture ends at this offset of the current block, the take is done
+    block_end: Option<usize>,
+    /// Takes refused or dropped since the mixer last asked
+    missed: usize,
+}
+
+/// Recorder implementation
+impl Recorder {
+    /// Allocates a take buffer long enough for the slowest tempo
+    pub fn new(bank: usize, source: RecordSource, bars: usize, sample_rate: f64) -> Self {
+        let bars = bars.max(1);
+        let capacity = (bars * BAR_BEATS) as f64 * 60.0 / MIN_RECORD_TEMPO * sample_rate;
+        Recorder {
+            bank,
+            source,
+            bars,
+            state: RecordState::Idle(),
+            bars_done: 0,
+            frames: Some(Vec::with_capacity(capacity as usize)),
+            block_start: 0,
+            block_end: None,
+            missed: 0,
+        }
+    }
+
+    /// Length of a take in beats
+    pub fn num_beats(&self) -> usize {
+        self.bars * BAR_BEATS
+    }
+
+    /// Arms or disarms, only a take waiting for its first bar can be disarmed
+    pub fn arm(&mut self, on: bool) {
+        match (on, self.state) {
+            (true, RecordState::Idle()) => {
+                if self.frames.is_some() {
+                    self.state = RecordState::Armed();
+                } else {
+                    // the previous take is not stored yet
+                    self.missed += 1;
+                }
+            }
+            (false, RecordState::Armed()) => self.state = RecordState::Idle(),
+            _ => {}
+        }
+    }
+
+    /// A bar starts at this offset of the current block
+    pub fn bar(&mut self, offset: usize) {
+        match self.state {
+            RecordState::Armed() => {
+                self.state = RecordState::Recording();
+                self.bars_done = 0;
+                self.block_start = offset;
+            }
+            RecordState::Recording() => {
+                self.bars_done += 1;
+                if self.bars_done >= self.bars {
+                    self.block_end = Some(offset);
+                }
+            }
+            RecordState::Idle() => {}
+        }
+    }
+
+    /// Captures the current block of the source, returns the take when done
+    pub fn capture(&mut self, block: &[Stereo<f32>]) -> Option<Vec<Stereo<f32>>> {
+        if self.state != RecordState::Recording() {
+            return None;
+        }
+
+        // the part of the block in the take, the buffer never grows
+        let end = self.block_end.unwrap_or(block.len()).min(block.len());
+        let start = self.block_start.min(end);
+        if let Some(frames) = self.frames.as_mut() {
+            let len = (end - start).min(frames.capacity() - frames.len());
+            frames.extend_from_slice(&block[start..start + len]);
+
+            // slower than the slowest tempo, the take would miss beats
+            if len < end - start {
+                self.missed += 1;
+                frames.clear();
+                self.state = RecordState::Idle();
+                self.block_end = None;
+                self.block_start = 0;
+                return None;
+            }
+        }
+        self.block_start = 0;
+
+        // done
+        match self.block_end.take() {
+            Some(_) => {
+                self.state = RecordState::Idle();
+                self.frames.take()
+            }
+            None => None,
+        }
+    }
+
+    /// Takes refused or dropped since the last call, and resets the count
+    pub fn missed_takes(&mut self) -> usize {
+        std::mem::replace(&mut self.missed, 0)
+    }
+
+    /// Gets a take buffer back, a fresh one or a take that could not be sent
+    pub fn recycle(&mut self, mut frames: Vec<Stereo<f32>>) {
+        frames.clear();
+        self.frames = Some(frames);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_on_bars() {
+        let mut rec = Recorder::new(0, RecordSource::Input, 1, 1000.0);
+        let block: Vec<Stereo<f32>> = (0..100).map(|i| [i as f32, i as f32]).collect();
+
+        // nothing until armed and a bar starts
+        assert!(rec.capture(&block).is_none());
+        rec.arm(true);
+        assert!(rec.capture(&block).is_none());
+
+        // starts in the middle of a block, ends on the next bar
+        rec.bar(40);
+        assert!(rec.capture(&block).is_none());
+        assert!(rec.capture(&block).is_none());
+        rec.bar(10);
+        let take = rec.capture(&block).unwrap();
+        assert_eq!(take.len(), 60 + 100 + 10);
+        assert_eq!(take[0], [40.0, 40.0]);
+        assert_eq!(take[169], [9.0, 9.0]);
+
+        // no buffer until the loader sends one
+        assert_eq!(rec.missed_takes(), 0);
+        rec.arm(true);
+        assert_eq!(rec.state, RecordState::Idle());
+        assert_eq!(rec.missed_takes(), 1);
+        assert_eq!(rec.missed_takes(), 0);
+        rec.recycle(take);
+        rec.arm(true);
+        assert_eq!(rec.state, RecordState::Armed());
+    }
+
+    #[test]
+    fn test_take_overflow() {
+        let mut rec = Recorder::new(0, RecordSource::Input, 1, 1000.0);
+        let block: Vec<Stereo<f32>> = (0..100).map(|i| [i as f32, i as f32]).collect();
+
+        // a bar slower than the slowest tempo, the take is dropped
+        rec.arm(true);
+        rec.bar(0);
+        for _ in 0..40 {
+            assert!(rec.capture(&block).is_none());
+        }
+        assert_eq!(rec.state, RecordState::Recording());
+        assert!(rec.capture(&block).is_none());
+        assert_eq!(rec.state, RecordState::Idle());
+        assert_eq!(rec.missed_takes(), 1);
+        rec.bar(0);
+        assert!(rec.capture(&block).is_none());
+
+        // the buffer is kept for the next take
+        rec.arm(true);
+        assert_eq!(rec.state, RecordState::Armed());
+        assert_eq!(rec.frames.as_ref().map(|f| f.len()), Some(0));
+    }
+}
@@ -0,0 +1,189 @@
+//! Algorithmic reverb, after Jezar's Freeverb.
+//! Eight lowpassed feedback combs in parallel and four allpasses in series per side,
+//! the right side delays are spread for a wide stereo image.
+use sample::frame::{Frame, Stereo};
+
+use super::fx::Fx;
+use crate::control::{FxParam, SmoothParam};
+
+/// Comb lengths at 44.1kHz
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+
+/// Allpass lengths at 44.1kHz
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+
+/// Right side offset at 44.1kHz
+const STEREO_SPREAD: usize = 23;
+
+/// Input attenuation, the combs sum up loud
+const FIXED_GAIN: f32 = 0.015;
+
+/// Room size to comb feedback
+const SCALE_ROOM: f32 = 0.28;
+const OFFSET_ROOM: f32 = 0.7;
+
+/// Damping to comb lowpass
+const SCALE_DAMP: f32 = 0.4;
+
+/// Allpass feedback
+const ALLPASS_FEEDBACK: f32 = 0.5;
+
+/// Length of the parameter ramps, in frames
+const REVERB_SMOOTH_LEN: usize = 256;
+
+/// Comb filter with a one pole lowpass in the loop
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    store: f32,
+}
+
+impl Comb {
+    fn new(len: usize) -> Self {
+        Comb {
+            buffer: vec![0.0; len.max(1)],
+            index: 0,
+            store: 0.0,
+        }
+    }
+
+    #[inline(always)]
+    fn process(&mut self, input: f32, feedback: f32, damp: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.store = output * (1.0 - damp) + self.store * damp;
+        self.buffer[self.index] = input + self.store * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        output
+    }
+}
+
+/// Schroeder allpass
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Allpass {
+            buffer: vec![0.0; len.max(1)],
+            index: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.index];
+        self.buffer[self.index] = input + buffered * ALLPASS_FEEDBACK;
+        self.index = (self.index + 1) % self.buffer.len();
+        buffered - input
+    }
+}
+
+/// ReverbFx is a stereo Freeverb.
+/// Params are normalized 0..1 and smoothed, they only scale the feedback loops so they never click.
+pub struct ReverbFx {
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    room_size: SmoothParam,
+    damping: SmoothParam,
+    width: SmoothParam,
+    mix: SmoothParam,
+}
+
+/// ReverbFx implementation
+impl ReverbFx {
+    /// all params are 0..1
+    pub fn new(room_size: f32, damping: f32, width: f32, mix: f32, sample_rate: f64) -> Self {
+        // tunings are given at 44.1kHz
+        let scale = |len: usize| (len as f64 * sample_rate / 44_100.0) as usize;
+        let spread = scale(STEREO_SPREAD);
+        let side_combs = |offset: usize| -> Vec<Comb> {
+            COMB_TUNING
+                .iter()
+                .map(|len| Comb::new(scale(*len) + offset))
+                .collect()
+        };
+        let side_allpasses = |offset: usize| -> Vec<Allpass> {
+            ALLPASS_TUNING
+                .iter()
+                .map(|len| Allpass::new(scale(*len) + offset))
+                .collect()
+        };
+
+        let clamp = |v: f32| v.max(0.0).min(1.0);
+        ReverbFx {
+            combs: [side_combs(0), side_combs(spread)],
+            allpasses: [side_allpasses(0), side_allpasses(spread)],
+            room_size: SmoothParam::new(clamp(room_size), clamp(room_size)),
+            damping: SmoothParam::new(clamp(damping), clamp(damping)),
+            width: SmoothParam::new(clamp(width), clamp(width)),
+            mix: SmoothParam::new(clamp(mix), clamp(mix)),
+        }
+    }
+}
+
+impl Fx for ReverbFx {
+    fn process_block(&mut self, block: &mut [Stereo<f32>]) {
+        for frame in block.iter_mut() {
+            let feedback = self.room_size.get_param(REVERB_SMOOTH_LEN) * SCALE_ROOM + OFFSET_ROOM;
+            let damp = self.damping.get_param(REVERB_SMOOTH_LEN) * SCALE_DAMP;
+            let width = self.width.get_param(REVERB_SMOOTH_LEN);
+            let mix = self.mix.get_param(REVERB_SMOOTH_LEN);
+
+            // mono in, both sides
+            let input = (frame[0] + frame[1]) * FIXED_GAIN;
+            let mut wet = Stereo::<f32>::equilibrium();
+            let sides = self.combs.iter_mut().zip(self.allpasses.iter_mut());
+            for (side, (combs, allpasses)) in sides.enumerate() {
+                let mut out = 0.0;
+                for comb in combs.iter_mut() {
+                    out += comb.process(input, feedback, damp);
+                }
+                for allpass in allpasses.iter_mut() {
+                    out = allpass.process(out);
+                }
+                wet[side] = out;
+            }
+
+            // width mixes the sides, 0 is mono
+            let wet1 = (1.0 + width) * 0.5;
+            let wet2 = (1.0 - width) * 0.5;
+            let wet = [wet[0] * wet1 + wet[1] * wet2, wet[1] * wet1 + wet[0] * wet2];
+
+            // dry / wet
+            *frame = frame.scale_amp(1.0 - mix).add_amp(wet.scale_amp(mix));
+        }
+    }
+
+    fn set_param(&mut self, param: FxParam, val: f32) {
+        let val = val.max(0.0).min(1.0);
+        match param {
+            FxParam::RoomSize => self.room_size.retarget(val),
+            FxParam::Damping => self.damping.retarget(val),
+            FxParam::Width => self.width.retarget(val),
+            FxParam::Mix => self.mix.retarget(val),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reverb_tail_decays() {
+        let mut fx = ReverbFx::new(0.5, 0.5, 1.0, 1.0, 44_100.0);
+        let mut block = vec![Stereo::<f32>::equilibrium(); 44_100];
+        block[0] = [1.0, 1.0];
+        fx.process_block(&mut block);
+
+        // some tail, not blowing up
+        let energy = |b: &[Stereo<f32>]| b.iter().map(|f| f[0] * f[0] + f[1] * f[1]).sum::<f32>();
+        let first = energy(&block[..22_050]);
+        let second = energy(&block[22_050..]);
+        assert!(first > 0.0);
+        assert!(second < first);
+    }
+}
@@ -0,0 +1,314 @@
+//! Internal master clock.
+//! An alternative to the midi clock when no DAW is around.
+//! Emits playback ticks at 24 PPQN for the configured tempo, can be started / stopped / changed live.
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{bounded, RecvTimeoutError};
+
+use crate::config::{ClockSource, Config};
+use crate::control::{AudioClock, ControlMessage, PlaybackMessage, SyncMessage};
+use crate::midi::MidiTime;
+use crate::sample_gen::PPQN;
+
+/// How long the clock thread sleeps when the clock is stopped
+const IDLE_TIMEOUT_MS: u64 = 500;
+
+/// Tempo range of the clock, a tempo out of it is clamped
+const TEMPO_MIN: f64 = 20.0;
+const TEMPO_MAX: f64 = 999.0;
+
+/// InternalClock keeps the state of the master clock
+struct InternalClock {
+    /// Is the clock active ? (clock source in config)
+    enabled: bool,
+    /// Is the clock running ?
+    running: bool,
+    /// Current tempo
+    tempo: f64,
+    /// Time to send in playback messages
+    midi_time: MidiTime,
+    /// Instant the clock was started
+    started_at: Instant,
+    /// Audio frame the clock was started at, no tick is stamped before it
+    start_frame: u64,
+    /// Start as soon as the audio is running
+    autostart: bool,
+    /// Audio clock used to timestamp the ticks
+    audio_clock: AudioClock,
+    /// Deadline of the next tick
+    next_tick_at: Instant,
+    /// Playback messages output
+    cx_tx: crossbeam_channel::Sender<ControlMessage>,
+}
+
+/// InternalClock implementation
+impl InternalClock {
+    /// Duration of one tick at the current tempo
+    fn tick_duration(&self) -> Duration {
+        Duration::from_nanos((60_000_000_000.0 / (self.tempo * PPQN as f64)) as u64)
+    }
+
+    /// Time until next tick
+    fn timeout(&self) -> Duration {
+        if !self.running {
+            return Duration::from_millis(IDLE_TIMEOUT_MS);
+        }
+        let now = Instant::now();
+        if self.next_tick_at > now {
+            return self.next_tick_at - now;
+        }
+        Duration::from_millis(0)
+    }
+
+    /// Audio frame of a past deadline, not of the time the message is sent
+    fn frame_at(&self, at: Instant) -> u64 {
+        let late = self.audio_clock.to_frames(Instant::now() - at);
+        self.audio_clock.now().saturating_sub(late)
+    }
+
+    /// Handles a clock command
+    fn command(&mut self, message: ControlMessage) {
+        // only when the internal clock is the source
+        if !self.enabled {
+            return;
+        }
+        match message {
+            ControlMessage::ClockStart { .. } => self.start(),
+            ControlMessage::ClockStop { .. } => self.stop(),
+            ControlMessage::ClockTempo { tcode: _, val } => {
+                if val > 0.0 {
+                    self.tempo = clamp_tempo(val as f64);
+                    println!("clock: Tempo: {}", self.tempo);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// starts the clock from the first tick
+    fn start(&mut self) {
+        if self.running {
+            return;
+        }
+        self.midi_time = MidiTime::new(self.tempo);
+        self.running = true;
+        self.started_at = Instant::now();
+        self.next_tick_at = self.started_at;
+        self.start_frame = self.frame_at(self.started_at);
+        self.send(SyncMessage::Start(), self.start_frame);
+    }
+
+    /// stops the clock
+    fn stop(&mut self) {
+        if !self.running {
+            return;
+        }
+        self.running = false;
+        self.send(SyncMessage::Stop(), self.audio_clock.now());
+    }
+
+    /// sends all the ticks that are due
+    fn tick(&mut self) {
+        // waits for the audio to run before the autostart
+        if self.autostart && self.audio_clock.is_running() {
+            self.autostart = false;
+            self.start();
+        }
+        if !self.running {
+            return;
+        }
+        while self.next_tick_at <= Instant::now() {
+            let elapsed = self.next_tick_at - self.started_at;
+            // the audio clock runs one block ahead at most, a late tick could land before the start
+            let frame = self.frame_at(self.next_tick_at).max(self.start_frame);
+            self.send(SyncMessage::Tick(elapsed.as_micros() as u64), frame);
+            self.midi_time.tick_at_tempo(self.tempo);
+            self.next_tick_at += self.tick_duration();
+        }
+    }
+
+    /// sends a playback message, timestamped in audio frames
+    fn send(&self, sync: SyncMessage, tcode: u64) {
+        let res = self.cx_tx.try_send(ControlMessage::Playback(PlaybackMessage {
+            tcode,
+            sync,
+            time: self.midi_time.clone(),
+        }));
+        if let Err(e) = res {
+            println!("clock: missed in control bus {:?}", e);
+        }
+    }
+}
+
+/// Keeps a tempo in the clock range, the ticks stay apart
+fn clamp_tempo(tempo: f64) -> f64 {
+    tempo.max(TEMPO_MIN).min(TEMPO_MAX)
+}
+
+/// Initialize the internal clock thread.
+/// Returns the thread, a sender for clock commands and the playback messages receiver.
+pub fn initialize_clock(
+    conf: Config,
+    audio_clock: AudioClock,
+) -> (
+    thread::JoinHandle<()>,
+    crossbeam_channel::Sender<ControlMessage>,
+    crossbeam_channel::Receiver<ControlMessage>,
+) {
+    // commands in
+    let (cmd_tx, cmd_rx) = bounded::<ControlMessage>(1024);
+
+    // playback messages out
+    let (cx_tx, cx_rx) = bounded::<ControlMessage>(1024);
+
+    // init the clock thread
+    let clock_thread = thread::spawn(move || {
+        let (enabled, tempo, autostart) = match conf.clock {
+            ClockSource::Midi => (false, 120.0, false),
+            ClockSource::Internal { tempo, autostart } => (true, clamp_tempo(tempo), autostart),
+        };
+
+        let mut clock = InternalClock {
+            enabled,
+            running: false,
+            tempo,
+            midi_time: MidiTime::new(tempo),
+            started_at: Instant::now(),
+            start_frame: 0,
+            autostart: enabled && autostart,
+            audio_clock,
+            next_tick_at: Instant::now(),
+            cx_tx,
+        };
+
+        if enabled {
+            println!("clock: Internal clock, Initial Tempo: {}", tempo);
+        }
+
+        // clock loop, wakes up for commands or ticks
+        loop {
+            match cmd_rx.recv_timeout(clock.timeout()) {
+                Ok(m) => clock.command(m),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            clock.tick();
+        }
+    });
+
+    // return thread handle, command sender and receiver
+    (clock_thread, cmd_tx, cx_rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a stopped internal clock at 120 bpm, with its output
+    fn test_clock() -> (InternalClock, crossbeam_channel::Receiver<ControlMessage>) {
+        let (cx_tx, cx_rx) = bounded::<ControlMessage>(1024);
+        let clock = InternalClock {
+            enabled: true,
+            running: false,
+            tempo: 120.0,
+            midi_time: MidiTime::new(120.0),
+            started_at: Instant::now(),
+            start_frame: 0,
+            autostart: false,
+            audio_clock: AudioClock::new(44_100.0),
+            next_tick_at: Instant::now(),
+            cx_tx,
+        };
+        (clock, cx_rx)
+    }
+
+    /// the sync messages sent so far
+    fn received(cx_rx: &crossbeam_channel::Receiver<ControlMessage>) -> Vec<SyncMessage> {
+        cx_rx
+            .try_iter()
+            .filter_map(|m| match m {
+                ControlMessage::Playback(p) => Some(p.sync),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_start_tick_stop() {
+        let (mut clock, cx_rx) = test_clock();
+
+        // nothing before the start
+        clock.tick();
+        assert!(received(&cx_rx).is_empty());
+
+        // the first tick is due right away
+        clock.start();
+        clock.tick();
+        let sent = received(&cx_rx);
+        assert_eq!(sent.len(), 2);
+        assert!(matches!(sent[0], SyncMessage::Start()));
+        assert!(matches!(sent[1], SyncMessage::Tick(0)));
+
+        // late by ten ticks and a half, the missed ticks are sent at once, on their deadlines
+        let tick_len = clock.tick_duration();
+        clock.started_at = Instant::now() - tick_len * 21 / 2;
+        clock.next_tick_at = clock.started_at + tick_len;
+        clock.tick();
+        let sent = received(&cx_rx);
+        assert_eq!(sent.len(), 10);
+        for (i, sync) in sent.iter().enumerate() {
+            let micros = (tick_len * (i as u32 + 1)).as_micros() as u64;
+            assert!(matches!(sync, SyncMessage::Tick(t) if *t == micros));
+        }
+        assert!(clock.next_tick_at > Instant::now());
+
+        // no tick once stopped
+        clock.stop();
+        clock.next_tick_at = Instant::now() - tick_len;
+        clock.tick();
+        let sent = received(&cx_rx);
+        assert_eq!(sent.len(), 1);
+        assert!(matches!(sent[0], SyncMessage::Stop()));
+    }
+
+    #[test]
+    fn test_ticks_after_start() {
+        let (mut clock, cx_rx) = test_clock();
+        clock.audio_clock.publish(10_000, 128);
+
+        // the clock thread wakes up late, its first ticks are due before the audio block
+        clock.start();
+        let late = Duration::from_millis(50);
+        clock.started_at -= late;
+        clock.next_tick_at -= late;
+        clock.tick();
+        let tcodes: Vec<u64> = cx_rx
+            .try_iter()
+            .filter_map(|m| match m {
+                ControlMessage::Playback(p) => Some(p.tcode),
+                _ => None,
+            })
+            .collect();
+        assert!(tcodes.len() > 1);
+        assert!(tcodes.iter().all(|t| *t >= tcodes[0]));
+    }
+
+    #[test]
+    fn test_tempo_clamp() {
+        let (mut clock, _cx_rx) = test_clock();
+        clock.command(ControlMessage::ClockTempo {
+            tcode: 0,
+            val: 1.0e12,
+        });
+        assert_eq!(clock.tempo, TEMPO_MAX);
+        assert!(clock.tick_duration() > Duration::from_millis(1));
+
+        clock.command(ControlMessage::ClockTempo { tcode: 0, val: 1.0 });
+        assert_eq!(clock.tempo, TEMPO_MIN);
+
+        // not a tempo
+        clock.command(ControlMessage::ClockTempo { tcode: 0, val: 0.0 });
+        assert_eq!(clock.tempo, TEMPO_MIN);
+    }
+}
@@ -11,21 +11,383 @@ use toml::from_str;
 use std::io::Read;
 
 use crate::control::ControlMessage;
+use crate::sample_gen::slicer::SlicePattern;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 /// Config struct
 pub struct Config {
-    pub tracks: Vec<TrackType>,
+    pub tracks: Vec<TrackConfig>,
     pub audio_root: String,
     pub midi_map: MidiMap,
+    #[serde(default)]
+    pub clock: ClockSource,
+    #[serde(default)]
+    pub downmix: Downmix,
+    #[serde(default)]
+    pub sends: Vec<BusConfig>,
+    #[serde(default)]
+    pub groups: Vec<BusConfig>,
+    #[serde(default)]
+    pub master: MasterConfig,
+    /// How many times per second the levels are sent to the OSC controller
+    #[serde(default = "default_meter_rate")]
+    pub meter_rate: f64,
+    /// Output pair of the cue (pre fader listen), 1 is channels 3 and 4
+    #[serde(default)]
+    pub cue_pair: Option<usize>,
+    /// Output device, matched on part of its name, the default device if None
+    #[serde(default)]
+    pub device: Option<String>,
+    /// Input device of the recorders, same matching, the default device if None
+    #[serde(default)]
+    pub input_device: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+/// MasterConfig struct, the output dynamics
+pub struct MasterConfig {
+    /// Look-ahead brickwall limiter on the output
+    #[serde(default = "default_true")]
+    pub limiter: bool,
+    /// Limiter ceiling in dB
+    #[serde(default = "default_ceiling")]
+    pub ceiling: f32,
+    /// Limiter look-ahead in ms, also its latency
+    #[serde(default = "default_lookahead")]
+    pub lookahead: f32,
+    /// Limiter release in ms
+    #[serde(default = "default_release")]
+    pub release: f32,
+    /// Saturates the peaks before the limiter
+    #[serde(default)]
+    pub soft_clip: bool,
+}
+
+impl Default for MasterConfig {
+    fn default() -> Self {
+        MasterConfig {
+            limiter: true,
+            ceiling: default_ceiling(),
+            lookahead: default_lookahead(),
+            release: default_release(),
+            soft_clip: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+/// ClockSource enum, where the ticks come from
+pub enum ClockSource {
+    /// Follows the clock received on the virtual midi input
+    Midi,
+    /// Internal master clock
+    Internal {
+        tempo: f64,
+        #[serde(default)]
+        autostart: bool,
+    },
+}
+
+impl Default for ClockSource {
+    fn default() -> Self {
+        ClockSource::Midi
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+/// Downmix enum, how files with more than two channels are folded to stereo
+pub enum Downmix {
+    /// Keeps the first two channels
+    FirstPair,
+    /// Averages the odd channels to the left and the even channels to the right
+    Fold,
+    /// Averages all the channels to both sides
+    Mono,
+}
+
+impl Default for Downmix {
+    fn default() -> Self {
+        Downmix::FirstPair
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+/// TrackConfig struct, the sample generator and its insert FX chain
+pub struct TrackConfig {
+    #[serde(flatten)]
+    pub generator: TrackType,
+    #[serde(default)]
+    pub fx: Vec<FxConfig>,
+    /// Post fader send levels, by send bus
+    #[serde(default)]
+    pub sends: Vec<f32>,
+    /// Group bus index, the track goes straight to the output if None
+    #[serde(default)]
+    pub group: Option<usize>,
+    /// Output pair when not grouped, 0 is the main output, others skip the master
+    #[serde(default)]
+    pub output: usize,
+    /// Triggered playback, the track follows the transport if None
+    #[serde(default)]
+    pub trigger: Option<TriggerConfig>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+/// TriggerConfig struct, how a triggered track starts and stops
+pub struct TriggerConfig {
+    /// Midi channel of the trigger notes, 1 to 16
+    #[serde(default = "default_channel")]
+    pub channel: u8,
+    /// Midi note playing the whole sample, the notes above play the slices
+    #[serde(default = "default_note")]
+    pub note: u8,
+    /// Starts on the next 1/quant of a bar, on the next tick if 0
+    #[serde(default = "default_quant")]
+    pub quant: u64,
+    /// Plays for this many bars, once to the end of the sample or slice if 0
+    #[serde(default)]
+    pub bars: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+/// BusConfig struct, a send or group bus summed into the output after its FX chain
+pub struct BusConfig {
+    #[serde(default)]
+    pub fx: Vec<FxConfig>,
+    #[serde(default = "default_full")]
+    pub volume: f32,
+    #[serde(default)]
+    pub pan: f32,
+    /// Output pair, 0 is the main output, others skip the master
+    #[serde(default)]
+    pub output: usize,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 /// TrackType enum
 pub enum TrackType {
-    SlicerGen { bank: usize },
+    /// Plays note triggered slices instead of the slice sequence if `notes` is set
+    SlicerGen {
+        bank: usize,
+        #[serde(default)]
+        notes: Option<SliceNotes>,
+        /// Step pattern of the bank, played in place of the sample order
+        #[serde(default)]
+        pattern: Option<SlicePattern>,
+        /// Step patterns by sample file name, played in place of the bank one
+        #[serde(default)]
+        sample_patterns: HashMap<String, SlicePattern>,
+    },
     RePitchGen { bank: usize },
     PVOCGen { bank: usize },
+    /// Records takes of `bars` bars into the bank and plays them back, repitched
+    Recorder {
+        bank: usize,
+        #[serde(default)]
+        source: RecordSource,
+        #[serde(default = "default_bars")]
+        bars: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+/// SliceNotes struct, midi notes playing the slices of a slicer
+pub struct SliceNotes {
+    /// Midi channel of the notes, 1 to 16
+    #[serde(default = "default_channel")]
+    pub channel: u8,
+    /// Midi note of the first slice, the notes above play the next ones
+    #[serde(default = "default_note")]
+    pub note: u8,
+    /// Starts the slices on the next 1/quant of a bar, right away if 0
+    #[serde(default)]
+    pub quant: u64,
+    /// Groups of slices cutting each other, ex: [[2, 3]]
+    #[serde(default)]
+    pub chokes: Vec<Vec<usize>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+/// RecordSource enum, what a recorder track captures
+pub enum RecordSource {
+    /// The input device, first pair
+    Input,
+    /// The main output, after the limiter
+    Master,
+}
+
+impl Default for RecordSource {
+    fn default() -> Self {
+        RecordSource::Input
+    }
+}
+
+impl TrackType {
+    /// Bank played by the generator
+    pub fn bank(&self) -> usize {
+        match self {
+            TrackType::SlicerGen { bank, .. }
+            | TrackType::RePitchGen { bank }
+            | TrackType::PVOCGen { bank }
+            | TrackType::Recorder { bank, .. } => *bank,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+/// FxConfig enum, an insert effect and its initial parameters
+pub enum FxConfig {
+    /// RBJ cookbook biquad filter, freq in Hz, gain in dB (shelves and peaking only)
+    Biquad {
+        filter: BiquadType,
+        freq: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+        #[serde(default)]
+        gain: f32,
+    },
+    /// Stereo delay synced to the clock tempo, damp is the cutoff of the feedback lowpass in Hz
+    Delay {
+        time: NoteValue,
+        #[serde(default = "default_feedback")]
+        feedback: f32,
+        #[serde(default = "default_mix")]
+        mix: f32,
+        #[serde(default)]
+        ping_pong: bool,
+        #[serde(default = "default_damp")]
+        damp: f32,
+    },
+    /// Freeverb style reverb, all params are 0..1
+    Reverb {
+        #[serde(default = "default_half")]
+        room_size: f32,
+        #[serde(default = "default_half")]
+        damping: f32,
+        #[serde(default = "default_full")]
+        width: f32,
+        #[serde(default = "default_full")]
+        mix: f32,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+/// NoteValue struct, a musical duration as 1/div of a whole note
+pub struct NoteValue {
+    pub div: u32,
+    #[serde(default)]
+    pub feel: NoteFeel,
+}
+
+impl NoteValue {
+    /// Length in beats (quarter notes)
+    pub fn beats(&self) -> f64 {
+        let straight = 4.0 / self.div.max(1) as f64;
+        match self.feel {
+            NoteFeel::Straight => straight,
+            NoteFeel::Dotted => straight * 1.5,
+            NoteFeel::Triplet => straight * 2.0 / 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+/// NoteFeel enum
+pub enum NoteFeel {
+    Straight,
+    Dotted,
+    Triplet,
+}
+
+impl Default for NoteFeel {
+    fn default() -> Self {
+        NoteFeel::Straight
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+/// BiquadType enum, the cookbook filter types
+pub enum BiquadType {
+    LowPass,
+    HiPass,
+    BpSkirtGain,
+    BpConstantPeak,
+    Notch,
+    Ap,
+    PeakingEQ,
+    LowShelf,
+    HiShelf,
+}
+
+/// Serde default for the filters resonance
+fn default_q() -> f32 {
+    0.707
+}
+
+/// Serde default for the delay feedback
+fn default_feedback() -> f32 {
+    0.4
+}
+
+/// Serde default for the delay dry / wet
+fn default_mix() -> f32 {
+    0.3
+}
+
+/// Serde default for the delay feedback lowpass
+fn default_damp() -> f32 {
+    6000.0
+}
+
+/// Serde default for the middle of a 0..1 param
+fn default_half() -> f32 {
+    0.5
+}
+
+/// Serde default for the top of a 0..1 param
+fn default_full() -> f32 {
+    1.0
+}
+
+/// Serde default for the switches that are on
+fn default_true() -> bool {
+    true
+}
+
+/// Serde default for the limiter ceiling
+fn default_ceiling() -> f32 {
+    -0.3
+}
+
+/// Serde default for the limiter look-ahead
+fn default_lookahead() -> f32 {
+    5.0
+}
+
+/// Serde default for the limiter release
+fn default_release() -> f32 {
+    80.0
+}
+
+/// Serde default for the metering rate
+fn default_meter_rate() -> f64 {
+    20.0
+}
+
+fn default_bars() -> usize {
+    1
+}
+
+fn default_channel() -> u8 {
+    1
+}
+
+fn default_note() -> u8 {
+    36
+}
+
+fn default_quant() -> u64 {
+    4
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -4,8 +4,11 @@ extern crate crossbeam_channel;
 use self::crossbeam_channel::bounded;
 use crate::config::Config;
 use serde::Deserialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::thread;
-use crate::sample_gen::slicer::TransformType;
+use std::time::{Duration, Instant};
+use crate::sample_gen::slicer::{BeatRepeat, SlicePattern, TransformType};
 use crate::midi::MidiTime;
 
 /// ControlMessage Enum is the main message for the control bus
@@ -48,25 +51,206 @@ pub enum ControlMessage {
         val: u64,
         track_num: usize,
     },
+    /// Track insert FX parameter, val is normalized 0..1 and mapped by the FX
+    TrackFxParam {
+        tcode: u64,
+        val: f32,
+        track_num: usize,
+        fx_num: usize,
+        param: FxParam,
+    },
+    /// Track post fader send level, to a send bus
+    TrackSend {
+        tcode: u64,
+        val: f32,
+        track_num: usize,
+        send_num: usize,
+    },
+    /// Send bus FX parameter, val is normalized 0..1 and mapped by the FX
+    SendFxParam {
+        tcode: u64,
+        val: f32,
+        send_num: usize,
+        fx_num: usize,
+        param: FxParam,
+    },
+    /// Track mute, val above 0.5 mutes
+    TrackMute {
+        tcode: u64,
+        val: f32,
+        track_num: usize,
+    },
+    /// Track solo, val above 0.5 solos
+    TrackSolo {
+        tcode: u64,
+        val: f32,
+        track_num: usize,
+    },
+    /// Track pre fader listen on the cue output, val above 0.5 cues
+    TrackCue {
+        tcode: u64,
+        val: f32,
+        track_num: usize,
+    },
+    /// Arms the recorder of the track, val above 0.5 arms, a started take always runs to its end
+    TrackRecord {
+        tcode: u64,
+        val: f32,
+        track_num: usize,
+    },
+    /// Starts a triggered track on its next quantization, from a slice or the whole sample if None
+    TrackTrigger {
+        tcode: u64,
+        track_num: usize,
+        #[serde(default)]
+        slice: Option<usize>,
+    },
+    /// Stops a triggered track now
+    TrackRelease {
+        tcode: u64,
+        track_num: usize,
+    },
+    /// Holds the track on a small looped window, val above 0.5 freezes
+    TrackFreeze {
+        tcode: u64,
+        val: f32,
+        track_num: usize,
+    },
+    /// Send bus volume
+    SendVolume {
+        tcode: u64,
+        val: f32,
+        send_num: usize,
+    },
+    /// Send bus pan
+    SendPan {
+        tcode: u64,
+        val: f32,
+        send_num: usize,
+    },
+    /// Group bus volume
+    GroupVolume {
+        tcode: u64,
+        val: f32,
+        group_num: usize,
+    },
+    /// Group bus pan
+    GroupPan {
+        tcode: u64,
+        val: f32,
+        group_num: usize,
+    },
+    /// Group bus FX parameter, val is normalized 0..1 and mapped by the FX
+    GroupFxParam {
+        tcode: u64,
+        val: f32,
+        group_num: usize,
+        fx_num: usize,
+        param: FxParam,
+    },
     /// Slicer messages
     Slicer {
         tcode: u64,
         track_num: usize,
         message: SlicerMessage
-    }
+    },
+    /// Starts the internal clock
+    ClockStart {
+        tcode: u64,
+    },
+    /// Stops the internal clock
+    ClockStop {
+        tcode: u64,
+    },
+    /// Internal clock tempo
+    ClockTempo {
+        tcode: u64,
+        val: f32,
+    },
 }
 
 /// Implement control message helpers
 impl ControlMessage {
+    /// Gets the message timecode, in audio frames. Zero means as soon as possible.
+    pub fn tcode(&self) -> u64 {
+        match self {
+            ControlMessage::Playback(playback_message) => playback_message.tcode,
+            ControlMessage::TrackVolume { tcode, .. }
+            | ControlMessage::TrackPan { tcode, .. }
+            | ControlMessage::TrackSampleSelect { tcode, .. }
+            | ControlMessage::TrackNextSample { tcode, .. }
+            | ControlMessage::TrackPrevSample { tcode, .. }
+            | ControlMessage::TrackLoopDiv { tcode, .. }
+            | ControlMessage::TrackFxParam { tcode, .. }
+            | ControlMessage::TrackSend { tcode, .. }
+            | ControlMessage::TrackMute { tcode, .. }
+            | ControlMessage::TrackSolo { tcode, .. }
+            | ControlMessage::TrackCue { tcode, .. }
+            | ControlMessage::TrackRecord { tcode, .. }
+            | ControlMessage::TrackTrigger { tcode, .. }
+            | ControlMessage::TrackRelease { tcode, .. }
+            | ControlMessage::TrackFreeze { tcode, .. }
+            | ControlMessage::SendFxParam { tcode, .. }
+            | ControlMessage::SendVolume { tcode, .. }
+            | ControlMessage::SendPan { tcode, .. }
+            | ControlMessage::GroupVolume { tcode, .. }
+            | ControlMessage::GroupPan { tcode, .. }
+            | ControlMessage::GroupFxParam { tcode, .. }
+            | ControlMessage::Slicer { tcode, .. }
+            | ControlMessage::ClockStart { tcode }
+            | ControlMessage::ClockStop { tcode }
+            | ControlMessage::ClockTempo { tcode, .. } => *tcode,
+        }
+    }
+
+    /// Sets the message timecode, in audio frames
+    pub fn set_tcode(&mut self, new_tcode: u64) {
+        match self {
+            ControlMessage::Playback(playback_message) => playback_message.tcode = new_tcode,
+            ControlMessage::TrackVolume { tcode, .. }
+            | ControlMessage::TrackPan { tcode, .. }
+            | ControlMessage::TrackSampleSelect { tcode, .. }
+            | ControlMessage::TrackNextSample { tcode, .. }
+            | ControlMessage::TrackPrevSample { tcode, .. }
+            | ControlMessage::TrackLoopDiv { tcode, .. }
+            | ControlMessage::TrackFxParam { tcode, .. }
+            | ControlMessage::TrackSend { tcode, .. }
+            | ControlMessage::TrackMute { tcode, .. }
+            | ControlMessage::TrackSolo { tcode, .. }
+            | ControlMessage::TrackCue { tcode, .. }
+            | ControlMessage::TrackRecord { tcode, .. }
+            | ControlMessage::TrackTrigger { tcode, .. }
+            | ControlMessage::TrackRelease { tcode, .. }
+            | ControlMessage::TrackFreeze { tcode, .. }
+            | ControlMessage::SendFxParam { tcode, .. }
+            | ControlMessage::SendVolume { tcode, .. }
+            | ControlMessage::SendPan { tcode, .. }
+            | ControlMessage::GroupVolume { tcode, .. }
+            | ControlMessage::GroupPan { tcode, .. }
+            | ControlMessage::GroupFxParam { tcode, .. }
+            | ControlMessage::Slicer { tcode, .. }
+            | ControlMessage::ClockStart { tcode }
+            | ControlMessage::ClockStop { tcode }
+            | ControlMessage::ClockTempo { tcode, .. } => *tcode = new_tcode,
+        }
+    }
+
     /// Useful to map value from midi which is usually 0..1 only (midi CC)
     pub fn remap_from_midi(&mut self) {
         match self {
-            ControlMessage::TrackVolume{tcode: _, val, track_num: _} => {
+            ControlMessage::TrackVolume{tcode: _, val, track_num: _}
+            | ControlMessage::SendVolume{tcode: _, val, send_num: _}
+            | ControlMessage::GroupVolume{tcode: _, val, group_num: _} => {
                 *val = ControlMessage::map(*val, 0.0, 1.0, 0.0, 1.2);
             }
-            ControlMessage::TrackPan{tcode: _, val, track_num: _} => {
+            ControlMessage::TrackPan{tcode: _, val, track_num: _}
+            | ControlMessage::SendPan{tcode: _, val, send_num: _}
+            | ControlMessage::GroupPan{tcode: _, val, group_num: _} => {
                 *val = ControlMessage::map(*val, 0.0, 1.0, -1.0, 1.0);
             }
+            ControlMessage::ClockTempo{tcode: _, val} => {
+                *val = ControlMessage::map(*val, 0.0, 1.0, 60.0, 200.0).round();
+            }
             // default case just sets the val
             _ => {
                 unimplemented!();
@@ -84,12 +268,111 @@ impl ControlMessage {
 /// Slicer specific messages
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum SlicerMessage {
-    Transform(TransformType)
+    Transform(TransformType),
+    /// Plays a step pattern from the next bar, an empty one goes back to the sample order.
+    /// Built off the audio thread, the slicer gives it back to the loader once replaced
+    Pattern(#[serde(with = "crate::sample_gen::slicer::shared_pattern")] Arc<SlicePattern>),
+    /// Starts a beat repeat on its next quantization
+    BeatRepeat(BeatRepeat),
+    /// Stops the beat repeat
+    BeatRelease(),
+    /// Plays a slice of the current buffer, velocity is the gain, 0 to 1
+    NoteOn {
+        slice: usize,
+        velocity: f32,
+    },
+}
+
+/// Slicer messages implementation
+impl SlicerMessage {
+    /// Fills in the value of a midi CC, 0..1, buttons send nothing when released.
+    /// The fader of a Generate transform is its variation, a held beat repeat is released with its button
+    pub fn from_midi(self, val: f32) -> Option<Self> {
+        match self {
+            SlicerMessage::Transform(TransformType::RandReverse { .. }) => {
+                Some(SlicerMessage::Transform(TransformType::RandReverse {
+                    amount: val,
+                }))
+            }
+            SlicerMessage::Transform(TransformType::SliceGain { slice_index, .. }) => {
+                Some(SlicerMessage::Transform(TransformType::SliceGain {
+                    slice_index,
+                    gain: ControlMessage::map(val, 0.0, 1.0, 0.0, 1.2),
+                }))
+            }
+            SlicerMessage::Transform(TransformType::SliceRepitch { slice_index, .. }) => {
+                Some(SlicerMessage::Transform(TransformType::SliceRepitch {
+                    slice_index,
+                    semitones: ControlMessage::map(val, 0.0, 1.0, -12.0, 12.0).round(),
+                }))
+            }
+            SlicerMessage::Transform(TransformType::Generate { density, seed, .. }) => {
+                Some(SlicerMessage::Transform(TransformType::Generate {
+                    density,
+                    variation: val,
+                    seed,
+                }))
+            }
+            SlicerMessage::BeatRepeat(repeat) if val <= 0.0 && repeat.bars == 0 => {
+                Some(SlicerMessage::BeatRelease())
+            }
+            SlicerMessage::NoteOn { slice, .. } if val > 0.0 => Some(SlicerMessage::NoteOn {
+                slice,
+                velocity: val,
+            }),
+            _ if val > 0.0 => Some(self),
+            _ => None,
+        }
+    }
+}
+
+/// FX parameters, each FX uses the ones it knows
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FxParam {
+    /// Cutoff or center frequency
+    Freq,
+    /// Resonance
+    Q,
+    /// Shelf or peak gain
+    Gain,
+    /// Delay time, picked among the synced note values
+    Time,
+    /// Delay feedback
+    Feedback,
+    /// Dry / wet
+    Mix,
+    /// Reverb size
+    RoomSize,
+    /// Reverb high frequencies damping
+    Damping,
+    /// Reverb stereo width
+    Width,
+}
+
+impl FxParam {
+    /// Parses the OSC name of a param
+    pub fn from_name(name: &str) -> Option<FxParam> {
+        match name {
+            "freq" => Some(FxParam::Freq),
+            "q" => Some(FxParam::Q),
+            "gain" => Some(FxParam::Gain),
+            "time" => Some(FxParam::Time),
+            "feedback" => Some(FxParam::Feedback),
+            "mix" => Some(FxParam::Mix),
+            "room_size" => Some(FxParam::RoomSize),
+            "damping" => Some(FxParam::Damping),
+            "width" => Some(FxParam::Width),
+            _ => None,
+        }
+    }
 }
 
 /// PlaybackMessage have all data used for sync
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PlaybackMessage {
+    /// timecode in audio frames
+    #[serde(default)]
+    pub tcode: u64,
     pub sync: SyncMessage,
     // @TODO should be more generic struct for time
     pub time: MidiTime,
@@ -103,6 +386,19 @@ pub enum SyncMessage {
     Tick(u64),
 }
 
+/// Notifications sent back to the remote controller
+#[derive(Clone, Debug)]
+pub enum Notification {
+    /// A bank of the sample lib changed, gives the new sample list
+    BankUpdated { bank: usize, samples: Vec<String> },
+    /// Deepest gain reduction of the master limiter since the last report, in dB
+    GainReduction { db: f32 },
+    /// Output levels since the last report, linear
+    MasterMeter { peak: f32, rms: f32 },
+    /// Post fader levels of a track since the last report, linear
+    TrackMeter { track_num: usize, peak: f32, rms: f32 },
+}
+
 /// Enum that indicates a direction (for DirectionalParam)
 #[derive(Clone, Debug)]
 pub enum Direction {
@@ -153,6 +449,8 @@ pub struct SmoothParam {
     next_val: f32,
     /// memorize the ramp
     t: usize,
+    /// last value given, a new ramp starts from here
+    current: f32,
 }
 
 impl SmoothParam {
@@ -162,6 +460,7 @@ impl SmoothParam {
             prev_val: pv,
             next_val: nv,
             t: 0,
+            current: pv,
         };
     }
 
@@ -180,6 +479,20 @@ impl SmoothParam {
         self.t = 0;
     }
 
+    /// set next value, the ramp starts from where it is, not from its target,
+    /// so a fast CC stream on an FX param doesn't jump
+    pub fn retarget(&mut self, v: f32) {
+        self.prev_val = self.current;
+        self.next_val = v;
+        // reset t
+        self.t = 0;
+    }
+
+    /// the value the ramp goes to
+    pub fn target(&self) -> f32 {
+        self.next_val
+    }
+
     /// lin interp between previous and next value, keeping ramp state
     pub fn get_param(&mut self, len: usize) -> f32 {
         let rt = self.t as f32 / len as f32;
@@ -188,8 +501,85 @@ impl SmoothParam {
         if self.t < len {
             self.t += 1;
         }
+        self.current = smoothed;
         return smoothed;
     }
+
+    /// true once the ramp reached the next value
+    pub fn is_stable(&self, len: usize) -> bool {
+        self.t >= len
+    }
+}
+
+/// AudioClock shares the audio thread position with the other threads, lock free.
+/// Control messages are timestamped against it, so the mixer can apply them at the exact frame.
+#[derive(Clone)]
+pub struct AudioClock {
+    /// common time reference
+    epoch: Instant,
+    /// first frame of the latest audio block
+    block_frame: Arc<AtomicU64>,
+    /// time of the latest audio block, in micros from the epoch
+    block_micros: Arc<AtomicU64>,
+    /// latest audio block length
+    block_len: Arc<AtomicU64>,
+    /// audio engine sample rate, as f64 bits
+    sample_rate: Arc<AtomicU64>,
+}
+
+impl AudioClock {
+    /// constructor
+    pub fn new(sample_rate: f64) -> Self {
+        AudioClock {
+            epoch: Instant::now(),
+            block_frame: Arc::new(AtomicU64::new(0)),
+            block_micros: Arc::new(AtomicU64::new(0)),
+            block_len: Arc::new(AtomicU64::new(0)),
+            sample_rate: Arc::new(AtomicU64::new(sample_rate.to_bits())),
+        }
+    }
+
+    /// Sets the engine sample rate, once the audio device is known
+    pub fn set_sample_rate(&self, sample_rate: f64) {
+        self.sample_rate.store(sample_rate.to_bits(), Ordering::Release);
+    }
+
+    /// Gets the engine sample rate
+    pub fn sample_rate(&self) -> f64 {
+        f64::from_bits(self.sample_rate.load(Ordering::Acquire))
+    }
+
+    /// Publish the position of the block being processed, called by the audio thread
+    pub fn publish(&self, block_frame: u64, block_len: usize) {
+        let micros = self.epoch.elapsed().as_micros() as u64;
+        self.block_frame.store(block_frame, Ordering::Relaxed);
+        self.block_micros.store(micros, Ordering::Relaxed);
+        self.block_len.store(block_len as u64, Ordering::Release);
+    }
+
+    /// Is the audio thread running ?
+    pub fn is_running(&self) -> bool {
+        self.block_len.load(Ordering::Acquire) > 0
+    }
+
+    /// Timecode for an event that happens now, in audio frames.
+    /// One block of latency is added so events keep their relative spacing.
+    pub fn now(&self) -> u64 {
+        let block_len = self.block_len.load(Ordering::Acquire);
+        let block_frame = self.block_frame.load(Ordering::Relaxed);
+        let block_micros = self.block_micros.load(Ordering::Relaxed);
+
+        // time elapsed since the block started, clamped to one block
+        let micros = self.epoch.elapsed().as_micros() as u64;
+        let elapsed = self.to_frames(Duration::from_micros(micros.saturating_sub(block_micros)));
+
+        block_frame + block_len + elapsed.min(block_len)
+    }
+
+    /// Converts a duration to audio frames
+    pub fn to_frames(&self, duration: Duration) -> u64 {
+        (duration.as_micros() as f64 * self.sample_rate() / 1_000_000.0) as u64
+    }
 }
 
 /// ControlHub is the central place that mux messages from MIDI / OSC ... into a unique place.
@@ -204,9 +594,11 @@ impl ControlHub {
     /// init the control hub
     pub fn new(
         _config: Config,
-        _osc_send: crossbeam_channel::Sender<ControlMessage>,
+        _osc_send: crossbeam_channel::Sender<Notification>,
         osc_rcv: crossbeam_channel::Receiver<ControlMessage>,
         midi_rcv: crossbeam_channel::Receiver<ControlMessage>,
+        clock_send: crossbeam_channel::Sender<ControlMessage>,
+        clock_rcv: crossbeam_channel::Receiver<ControlMessage>,
     ) -> (Self, crossbeam_channel::Receiver<ControlMessage>) {
         // init the hub out bus
         let (out_cx_tx, out_cx_rx) = bounded::<ControlMessage>(1024);
@@ -214,6 +606,7 @@ impl ControlHub {
         // use crossbeam to have clonable senders
         let (cx_tx, cx_rx) = bounded::<ControlMessage>(1024);
         let cx_tx2 = cx_tx.clone();
+        let cx_tx3 = cx_tx.clone();
 
         // thread that listen to midi events
         thread::spawn(move || {
@@ -246,13 +639,38 @@ impl ControlHub {
             }
         });
 
-        // muxer thread that reads crossbeam reciever and send out to bus
+        // thread that listen to internal clock events
         thread::spawn(move || {
+            // clock listen loop
             loop {
-                match cx_rx.recv() {
+                match clock_rcv.recv() {
                     Ok(m) => {
-                        out_cx_tx.send(m).unwrap();
+                        cx_tx3.send(m).unwrap();
+                    }
+                    Err(e) => {
+                        println!("{}", e);
+                        break;
                     }
+                }
+            }
+        });
+
+        // muxer thread that reads crossbeam reciever and send out to bus
+        thread::spawn(move || {
+            loop {
+                match cx_rx.recv() {
+                    Ok(m) => match m {
+                        // clock commands are routed to the internal clock
+                        ControlMessage::ClockStart { .. }
+                        | ControlMessage::ClockStop { .. }
+                        | ControlMessage::ClockTempo { .. } => {
+                            let _res = clock_send.try_send(m);
+                        }
+                        // everything else goes to the audio
+                        _ => {
+                            out_cx_tx.send(m).unwrap();
+                        }
+                    },
                     _ => {}
                 }
             }
@@ -2,6 +2,7 @@
 extern crate serde;
 
 mod audio;
+mod clock;
 mod config;
 mod control;
 mod midi;
@@ -24,17 +25,53 @@ fn main() {
     // load default config, immutable
     let conf = config::load_default();
 
+    // offline render mode, no sound card involved
+    // usage: smplr render <script.toml> <out.wav>
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() == 4 && args[1] == "render" {
+        let script = match audio::offline::load_script(&args[2]) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Failed to load render script: {}", e);
+                ::std::process::exit(1);
+            }
+        };
+        match audio::offline::render_to_file(conf, &script, &args[3]) {
+            Ok(_) => println!("Rendered to {}", args[3]),
+            Err(e) => {
+                println!("Render Errored: {}", e);
+                ::std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // audio clock, shared to timestamp the control messages
+    // the sample rate is updated when the audio device is open
+    let audio_clock = control::AudioClock::new(44_100.0);
+
     // init midi
-    let (midi_thread, midi_rx) = midi::initialize_midi(conf.clone());
+    let (midi_thread, midi_rx) = midi::initialize_midi(conf.clone(), audio_clock.clone());
 
     // init midi osc
-    let (osc_thread, osc_in, osc_out_rx) = osc::initialize_osc(conf.clone());
+    let (osc_thread, osc_in, osc_out_rx) = osc::initialize_osc(conf.clone(), audio_clock.clone());
+
+    // init the internal clock
+    let (_clock_thread, clock_tx, clock_rx) =
+        clock::initialize_clock(conf.clone(), audio_clock.clone());
 
     // init the control hub
-    let (_control_hub, hub_rx) = control::ControlHub::new(conf.clone(), osc_in, osc_out_rx, midi_rx);
+    let (_control_hub, hub_rx) = control::ControlHub::new(
+        conf.clone(),
+        osc_in.clone(),
+        osc_out_rx,
+        midi_rx,
+        clock_tx,
+        clock_rx,
+    );
 
     // init audio
-    let audio_thread = audio::initialize_audio(conf.clone(), hub_rx);
+    let audio_thread = audio::initialize_audio(conf.clone(), hub_rx, audio_clock, osc_in);
 
     // wait fo audio thread to exit
     match audio_thread.join() {
@@ -7,8 +7,8 @@ use midir::MidiInput;
 use time_calc::{Ppqn, Ticks};
 use wmidi::MidiMessage;
 
-use crate::config::Config;
-use crate::control::{ControlMessage, PlaybackMessage, SyncMessage};
+use crate::config::{ClockSource, Config, TrackType};
+use crate::control::{AudioClock, ControlMessage, PlaybackMessage, SlicerMessage, SyncMessage};
 
 const PPQN: Ppqn = 24;
 
@@ -23,6 +23,16 @@ pub struct MidiTime {
 
 /// MidiTime implementation
 impl MidiTime {
+    /// init a new midi time at a given tempo
+    pub fn new(tempo: f64) -> Self {
+        MidiTime {
+            tempo,
+            ticks: 0,
+            beats: 0.0,
+            last_timecode: 0,
+        }
+    }
+
     // restart midi time
     fn restart(&mut self) {
         self.ticks = 0;
@@ -51,6 +61,25 @@ impl MidiTime {
         //     // println!("midi: BAR at beat {}", self.beats);
         // }
     }
+
+    /// advance one tick at a known tempo, no timecode estimation.
+    /// Used by clocks that are not driven by an external midi device.
+    pub fn tick_at_tempo(&mut self, tempo: f64) {
+        self.tempo = tempo;
+        // update tick counter
+        self.ticks += 1;
+
+        // how many beats from the start
+        self.beats = Ticks(self.ticks as i64).beats(PPQN);
+    }
+}
+
+// is the midi clock the clock source ?
+fn is_midi_clock(conf: &Config) -> bool {
+    match conf.clock {
+        ClockSource::Midi => true,
+        _ => false,
+    }
 }
 
 // midi callback in midi thread
@@ -58,10 +87,18 @@ impl MidiTime {
 fn midi_cb(
     midi_tcode: u64,
     mid_data: &[u8],
-    cb_data: &mut (crossbeam_channel::Sender<ControlMessage>, MidiTime, Config),
+    cb_data: &mut (
+        crossbeam_channel::Sender<ControlMessage>,
+        MidiTime,
+        Config,
+        AudioClock,
+    ),
 ) {
     // destructure the tuple
-    let (cx_tx, midi_time, conf) = cb_data;
+    let (cx_tx, midi_time, conf, audio_clock) = cb_data;
+
+    // timestamp against the audio clock
+    let tcode = audio_clock.now();
 
     // parse raw midi inito a usable message
     let message = MidiMessage::from_bytes(mid_data);
@@ -69,7 +106,47 @@ fn midi_cb(
         Ok(mess) => {
             match mess {
                 MidiMessage::NoteOff(_, _, _) => {}
-                MidiMessage::NoteOn(_, _, _) => {}
+                MidiMessage::NoteOn(chan, note, vel) => {
+                    // a note on with no velocity is a note off
+                    if vel > 0 {
+                        for (track_num, track) in conf.tracks.iter().enumerate() {
+                            // the base note plays the whole sample, the notes above the slices
+                            match track.trigger {
+                                Some(t) if t.channel == chan.number() && note >= t.note => {
+                                    let slice = match note - t.note {
+                                        0 => None,
+                                        n => Some(n as usize - 1),
+                                    };
+                                    let m = ControlMessage::TrackTrigger {
+                                        tcode,
+                                        track_num,
+                                        slice,
+                                    };
+                                    // a full queue drops the note, never the midi thread
+                                    let _res = cx_tx.try_send(m);
+                                }
+                                _ => {}
+                            }
+                            // slicers playing their slices from the notes, velocity is the gain
+                            match &track.generator {
+                                TrackType::SlicerGen { notes: Some(n), .. }
+                                    if n.channel == chan.number() && note >= n.note =>
+                                {
+                                    let m = ControlMessage::Slicer {
+                                        tcode,
+                                        track_num,
+                                        message: SlicerMessage::NoteOn {
+                                            slice: (note - n.note) as usize,
+                                            velocity: vel as f32 / 127.0,
+                                        },
+                                    };
+                                    let _res = cx_tx.try_send(m);
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                }
                 MidiMessage::PolyphonicKeyPressure(_, _, _) => {}
                 MidiMessage::ControlChange(chan, cc_num, val) => {
                     // floatify the val
@@ -96,7 +173,7 @@ fn midi_cb(
                                 } => {
                                     // broadcast
                                     let mut m = ControlMessage::TrackVolume {
-                                        tcode: midi_tcode,
+                                        tcode,
                                         val: val_f,
                                         track_num,
                                     };
@@ -111,7 +188,7 @@ fn midi_cb(
                                 } => {
                                     // broadcast
                                     let mut m = ControlMessage::TrackPan {
-                                        tcode: midi_tcode,
+                                        tcode,
                                         val: val_f,
                                         track_num,
                                     };
@@ -125,13 +202,225 @@ fn midi_cb(
                                     track_num,
                                 } => {
                                     let m = ControlMessage::TrackSampleSelect {
-                                        tcode: midi_tcode,
+                                        tcode,
                                         val: val_f,
                                         track_num,
                                     };
                                     // no need to remap
                                     cx_tx.try_send(m).unwrap();
                                 }
+                                ControlMessage::TrackFxParam {
+                                    tcode: _,
+                                    val: _,
+                                    track_num,
+                                    fx_num,
+                                    param,
+                                } => {
+                                    let m = ControlMessage::TrackFxParam {
+                                        tcode,
+                                        val: val_f,
+                                        track_num,
+                                        fx_num,
+                                        param,
+                                    };
+                                    // no need to remap, the fx maps the normalized val
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::TrackSend {
+                                    tcode: _,
+                                    val: _,
+                                    track_num,
+                                    send_num,
+                                } => {
+                                    let m = ControlMessage::TrackSend {
+                                        tcode,
+                                        val: val_f,
+                                        track_num,
+                                        send_num,
+                                    };
+                                    // no need to remap
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::SendFxParam {
+                                    tcode: _,
+                                    val: _,
+                                    send_num,
+                                    fx_num,
+                                    param,
+                                } => {
+                                    let m = ControlMessage::SendFxParam {
+                                        tcode,
+                                        val: val_f,
+                                        send_num,
+                                        fx_num,
+                                        param,
+                                    };
+                                    // no need to remap, the fx maps the normalized val
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::TrackMute {
+                                    tcode: _,
+                                    val: _,
+                                    track_num,
+                                } => {
+                                    let m = ControlMessage::TrackMute {
+                                        tcode,
+                                        val: val_f,
+                                        track_num,
+                                    };
+                                    // no need to remap, on above half
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::TrackSolo {
+                                    tcode: _,
+                                    val: _,
+                                    track_num,
+                                } => {
+                                    let m = ControlMessage::TrackSolo {
+                                        tcode,
+                                        val: val_f,
+                                        track_num,
+                                    };
+                                    // no need to remap, on above half
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::TrackCue {
+                                    tcode: _,
+                                    val: _,
+                                    track_num,
+                                } => {
+                                    let m = ControlMessage::TrackCue {
+                                        tcode,
+                                        val: val_f,
+                                        track_num,
+                                    };
+                                    // no need to remap, on above half
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::TrackRecord {
+                                    tcode: _,
+                                    val: _,
+                                    track_num,
+                                } => {
+                                    let m = ControlMessage::TrackRecord {
+                                        tcode,
+                                        val: val_f,
+                                        track_num,
+                                    };
+                                    // no need to remap, armed above half
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::TrackTrigger {
+                                    tcode: _,
+                                    track_num,
+                                    slice,
+                                } => {
+                                    // button press only
+                                    if val > 0 {
+                                        let m = ControlMessage::TrackTrigger {
+                                            tcode,
+                                            track_num,
+                                            slice,
+                                        };
+                                        cx_tx.try_send(m).unwrap();
+                                    }
+                                }
+                                ControlMessage::TrackRelease {
+                                    tcode: _,
+                                    track_num,
+                                } => {
+                                    // button press only
+                                    if val > 0 {
+                                        let m = ControlMessage::TrackRelease { tcode, track_num };
+                                        cx_tx.try_send(m).unwrap();
+                                    }
+                                }
+                                ControlMessage::TrackFreeze {
+                                    tcode: _,
+                                    val: _,
+                                    track_num,
+                                } => {
+                                    let m = ControlMessage::TrackFreeze {
+                                        tcode,
+                                        val: val_f,
+                                        track_num,
+                                    };
+                                    // no need to remap, held above half
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::SendVolume {
+                                    tcode: _,
+                                    val: _,
+                                    send_num,
+                                } => {
+                                    let mut m = ControlMessage::SendVolume {
+                                        tcode,
+                                        val: val_f,
+                                        send_num,
+                                    };
+                                    // needs a remapping
+                                    m.remap_from_midi();
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::SendPan {
+                                    tcode: _,
+                                    val: _,
+                                    send_num,
+                                } => {
+                                    let mut m = ControlMessage::SendPan {
+                                        tcode,
+                                        val: val_f,
+                                        send_num,
+                                    };
+                                    // needs a remapping
+                                    m.remap_from_midi();
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::GroupVolume {
+                                    tcode: _,
+                                    val: _,
+                                    group_num,
+                                } => {
+                                    let mut m = ControlMessage::GroupVolume {
+                                        tcode,
+                                        val: val_f,
+                                        group_num,
+                                    };
+                                    // needs a remapping
+                                    m.remap_from_midi();
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::GroupPan {
+                                    tcode: _,
+                                    val: _,
+                                    group_num,
+                                } => {
+                                    let mut m = ControlMessage::GroupPan {
+                                        tcode,
+                                        val: val_f,
+                                        group_num,
+                                    };
+                                    // needs a remapping
+                                    m.remap_from_midi();
+                                    cx_tx.try_send(m).unwrap();
+                                }
+                                ControlMessage::GroupFxParam {
+                                    tcode: _,
+                                    val: _,
+                                    group_num,
+                                    fx_num,
+                                    param,
+                                } => {
+                                    let m = ControlMessage::GroupFxParam {
+                                        tcode,
+                                        val: val_f,
+                                        group_num,
+                                        fx_num,
+                                        param,
+                                    };
+                                    // no need to remap, the fx maps the normalized val
+                                    cx_tx.try_send(m).unwrap();
+                                }
                                 ControlMessage::TrackLoopDiv {
                                     tcode: _,
                                     val: _,
@@ -141,10 +430,18 @@ fn midi_cb(
                                 }
                                 ControlMessage::Slicer {
                                     tcode: _,
-                                    track_num: _,
-                                    message: _,
+                                    track_num,
+                                    message,
                                 } => {
-                                    unimplemented!();
+                                    // buttons trigger the transforms, faders set their value
+                                    if let Some(message) = message.from_midi(val_f) {
+                                        let m = ControlMessage::Slicer {
+                                            tcode,
+                                            track_num,
+                                            message,
+                                        };
+                                        cx_tx.try_send(m).unwrap();
+                                    }
                                 }
                                 ControlMessage::TrackNextSample { tcode: _, track_num: _ } => {
                                     unimplemented!();
@@ -152,6 +449,29 @@ fn midi_cb(
                                 ControlMessage::TrackPrevSample { tcode: _, track_num: _ } => {
                                     unimplemented!();
                                 }
+                                ControlMessage::ClockStart { tcode: _ } => {
+                                    // button press only
+                                    if val > 0 {
+                                        let m = ControlMessage::ClockStart { tcode };
+                                        cx_tx.try_send(m).unwrap();
+                                    }
+                                }
+                                ControlMessage::ClockStop { tcode: _ } => {
+                                    // button press only
+                                    if val > 0 {
+                                        let m = ControlMessage::ClockStop { tcode };
+                                        cx_tx.try_send(m).unwrap();
+                                    }
+                                }
+                                ControlMessage::ClockTempo { tcode: _, val: _ } => {
+                                    let mut m = ControlMessage::ClockTempo {
+                                        tcode,
+                                        val: val_f,
+                                    };
+                                    // needs a remapping
+                                    m.remap_from_midi();
+                                    cx_tx.try_send(m).unwrap();
+                                }
                             }
                         }
                     }
@@ -165,11 +485,15 @@ fn midi_cb(
                 MidiMessage::SongSelect(_) => {}
                 MidiMessage::Reserved(_) => {}
                 MidiMessage::TuneRequest => {}
+                // clock messages are ignored when the internal clock is the source
+                MidiMessage::TimingClock | MidiMessage::Start | MidiMessage::Stop
+                    if !is_midi_clock(conf) => {}
                 // clock ticks
                 MidiMessage::TimingClock => {
                     midi_time.tick(midi_tcode);
                     let message = SyncMessage::Tick(midi_tcode);
                     cx_tx.try_send(ControlMessage::Playback(PlaybackMessage {
+                        tcode,
                         sync: message,
                         time: midi_time.clone(),
                     })).unwrap();
@@ -179,6 +503,7 @@ fn midi_cb(
                     midi_time.restart();
                     let message = SyncMessage::Start();
                     cx_tx.try_send(ControlMessage::Playback(PlaybackMessage {
+                        tcode,
                         sync: message,
                         time: midi_time.clone(),
                     })).unwrap();
@@ -189,6 +514,7 @@ fn midi_cb(
                     midi_time.restart();
                     let message = SyncMessage::Stop();
                     cx_tx.try_send(ControlMessage::Playback(PlaybackMessage {
+                        tcode,
                         sync: message,
                         time: midi_time.clone(),
                     })).unwrap();
@@ -202,7 +528,10 @@ fn midi_cb(
 }
 
 // initialize midi machinery
-pub fn initialize_midi(conf: Config) -> (thread::JoinHandle<()>, crossbeam_channel::Receiver<ControlMessage>) {
+pub fn initialize_midi(
+    conf: Config,
+    audio_clock: AudioClock,
+) -> (thread::JoinHandle<()>, crossbeam_channel::Receiver<ControlMessage>) {
     // init the control bus
     let (cx_tx, cx_rx) = bounded::<ControlMessage>(1024);
 
@@ -212,12 +541,7 @@ pub fn initialize_midi(conf: Config) -> (thread::JoinHandle<()>, crossbeam_chann
         let (i_cx_tx, i_cx_rx) = bounded::<ControlMessage>(1024);
 
         // mutable midi time
-        let midi_time = MidiTime {
-            tempo: 120.0,
-            ticks: 0,
-            beats: 0.0,
-            last_timecode: 0,
-        };
+        let midi_time = MidiTime::new(120.0);
 
         // ->
         println!("midi: Listen to midi on port: {}", "Rust Smplr Input");
@@ -227,7 +551,7 @@ pub fn initialize_midi(conf: Config) -> (thread::JoinHandle<()>, crossbeam_chann
         let input = MidiInput::new("Smplr").expect("midi: Couldn't open midi input");
 
         // we need to move a lot of stuff in our midi
-        let data_tup = (i_cx_tx, midi_time, conf);
+        let data_tup = (i_cx_tx, midi_time, conf, audio_clock);
         // take first port
         // let port_name = input.port_name(0).expect("Couldn't get midi port");
 
@@ -3,11 +3,12 @@ use rosc::encoder;
 use rosc::{OscMessage, OscPacket, OscType};
 use serde_json::to_string;
 use crate::config::Config;
-use crate::control::{ControlMessage, SlicerMessage};
+use crate::control::{AudioClock, ControlMessage, FxParam, Notification, SlicerMessage};
 use std::net::{SocketAddr, SocketAddrV4, UdpSocket};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use crate::sample_gen::slicer::TransformType;
+use crate::sample_gen::slicer::{BeatRepeat, SlicePattern, TransformType};
 
 /// OSCRemoteControl keeps track of the remote controller app that control this smplr instance
 struct OSCRemoteControl {
@@ -20,32 +21,50 @@ const OSC_REMOTE_CONTROL_PORT: u16 = 6666;
 /// Initialize the OSC thread / routines
 pub fn initialize_osc(
     conf: Config,
+    audio_clock: AudioClock,
 ) -> (
     thread::JoinHandle<()>,
-    crossbeam_channel::Sender<ControlMessage>,
+    crossbeam_channel::Sender<Notification>,
     crossbeam_channel::Receiver<ControlMessage>,
 ) {
     // initialise the IN -> OUT crossbeam bus
     let (out_cx_tx, out_cx_rx) = bounded::<ControlMessage>(1024);
 
-    // initialise the OUT -> IN crossbeam bus
-    let (in_cx_tx, _in_cx_rx) = bounded::<ControlMessage>(1024);
+    // initialise the OUT -> IN crossbeam bus, notifications to the remote controller
+    let (in_cx_tx, in_cx_rx) = bounded::<Notification>(1024);
+
+    // keep track of the remote UI controller using this datastruct
+    let osc_controller = Arc::new(Mutex::new(OSCRemoteControl { address: None }));
+
+    // init host address
+    let host_addr = SocketAddrV4::from_str("0.0.0.0:6667").unwrap();
+
+    // init the receiving socket
+    let socket = UdpSocket::bind(host_addr).unwrap();
+    println!("osc: Listening to {}", host_addr);
+
+    // init the notification thread, sends on the same socket
+    let notify_socket = socket.try_clone().unwrap();
+    let notify_controller = osc_controller.clone();
+    thread::spawn(move || {
+        for notification in in_cx_rx.iter() {
+            // no one to notify yet
+            let send_to = match notify_controller.lock().unwrap().address {
+                Some(a) => a,
+                None => continue,
+            };
+            let msg_buf = encoder::encode(&notification_packet(notification)).unwrap();
+            if let Err(e) = notify_socket.send_to(&msg_buf, send_to) {
+                println!("osc: Error sending notification: {}", e);
+            }
+        }
+    });
 
     // init the osc thread
     let osc_thread = thread::spawn(move || {
         // better name
         let command_tx = out_cx_tx;
 
-        // keep track of the remote UI controller using this datastruct
-        let mut osc_controller = OSCRemoteControl { address: None };
-
-        // init host address
-        let host_addr = SocketAddrV4::from_str("0.0.0.0:6667").unwrap();
-
-        // init the receiving socket
-        let socket = UdpSocket::bind(host_addr).unwrap();
-        println!("osc: Listening to {}", host_addr);
-
         // OSC buffer
         let mut buf = [0u8; rosc::decoder::MTU];
 
@@ -54,14 +73,21 @@ pub fn initialize_osc(
             match socket.recv_from(&mut buf) {
                 Ok((size, addr)) => {
                     // println!("osc: Received packet with size {} from: {}", size, addr);
-                    let packet = rosc::decoder::decode(&buf[..size]).unwrap();
+                    let packet = match rosc::decoder::decode(&buf[..size]) {
+                        Ok(p) => p,
+                        Err(e) => {
+                            println!("osc: Malformed packet from {}, ignoring: {:?}", addr, e);
+                            continue;
+                        }
+                    };
                     handle_incoming_packet(
                         packet,
                         addr,
-                        &mut osc_controller,
+                        &mut osc_controller.lock().unwrap(),
                         &socket,
                         &conf,
                         command_tx.clone(),
+                        audio_clock.now(),
                     );
                 }
                 Err(e) => {
@@ -76,6 +102,39 @@ pub fn initialize_osc(
     return (osc_thread, in_cx_tx, out_cx_rx);
 }
 
+// builds the osc packet of a notification
+fn notification_packet(notification: Notification) -> OscPacket {
+    match notification {
+        // bank index followed by the sample names
+        Notification::BankUpdated { bank, samples } => {
+            let mut args = vec![OscType::Int(bank as i32)];
+            args.extend(samples.into_iter().map(OscType::String));
+            OscPacket::Message(OscMessage {
+                addr: "/smplr/lib/bank_updated".to_string(),
+                args: Some(args),
+            })
+        }
+        // master limiter metering
+        Notification::GainReduction { db } => OscPacket::Message(OscMessage {
+            addr: "/smplr/meter/gain_reduction".to_string(),
+            args: Some(vec![OscType::Float(db)]),
+        }),
+        // levels
+        Notification::MasterMeter { peak, rms } => OscPacket::Message(OscMessage {
+            addr: "/smplr/meter/master".to_string(),
+            args: Some(vec![OscType::Float(peak), OscType::Float(rms)]),
+        }),
+        Notification::TrackMeter { track_num, peak, rms } => OscPacket::Message(OscMessage {
+            addr: "/smplr/meter/track".to_string(),
+            args: Some(vec![
+                OscType::Int(track_num as i32),
+                OscType::Float(peak),
+                OscType::Float(rms),
+            ]),
+        }),
+    }
+}
+
 // handle an incoming os packet
 fn handle_incoming_packet(
     packet: OscPacket,
@@ -84,9 +143,15 @@ fn handle_incoming_packet(
     socket: &UdpSocket,
     conf: &Config,
     command_tx: crossbeam_channel::Sender<ControlMessage>,
+    tcode: u64,
 ) {
     match packet {
         OscPacket::Message(msg) => {
+            // missing args are None, a malformed message is ignored
+            let args: &[OscType] = match &msg.args {
+                Some(a) => a,
+                None => &[],
+            };
             // route this packet
             match msg.addr.as_str() {
                 // ping is important to keep the state of connection
@@ -104,22 +169,23 @@ fn handle_incoming_packet(
                     }))
                     .unwrap();
 
-                    // extract addr
-                    let send_to = osc_controller.address.unwrap();
+                    // extract addr, no ping yet
+                    let send_to = match osc_controller.address {
+                        Some(a) => a,
+                        None => return,
+                    };
 
                     // send back the config
                     socket.send_to(&msg_buf, send_to).unwrap();
                 }
                 // track volume
-                // @TODO take care of the message timecodes
                 "/smplr/track/volume" => {
-                    let args = msg.args.unwrap();
                     // nice way to handle args :D
-                    match (&args[0], &args[1]) {
-                        (OscType::Int(idx), OscType::Float(val)) => {
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Float(val))) => {
                             // build message
                             let m = ControlMessage::TrackVolume {
-                                tcode: 0,
+                                tcode,
                                 val: *val,
                                 track_num: *idx as usize,
                             };
@@ -130,13 +196,12 @@ fn handle_incoming_packet(
                     }
                 }
                 "/smplr/track/pan" => {
-                    let args = msg.args.unwrap();
                     // nice way to handle args :D
-                    match (&args[0], &args[1]) {
-                        (OscType::Int(idx), OscType::Float(val)) => {
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Float(val))) => {
                             // build message
                             let m = ControlMessage::TrackPan {
-                                tcode: 0,
+                                tcode,
                                 val: *val,
                                 track_num: *idx as usize,
                             };
@@ -147,13 +212,12 @@ fn handle_incoming_packet(
                     }
                 }
                 "/smplr/track/loop_div" => {
-                    let args = msg.args.unwrap();
                     // nice way to handle args :D
-                    match (&args[0], &args[1]) {
-                        (OscType::Int(idx), OscType::Int(val)) => {
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Int(val))) => {
                             // build message
                             let m = ControlMessage::TrackLoopDiv {
-                                tcode: 0,
+                                tcode,
                                 val: *val as u64,
                                 track_num: *idx as usize,
                             };
@@ -163,14 +227,239 @@ fn handle_incoming_packet(
                         _ => {}
                     }
                 }
+                "/smplr/track/fx" => {
+                    // track, fx index in the chain, param name, normalized val
+                    match (args.first(), args.get(1), args.get(2), args.get(3)) {
+                        (
+                            Some(OscType::Int(idx)),
+                            Some(OscType::Int(fx)),
+                            Some(OscType::String(p)),
+                            Some(OscType::Float(val)),
+                        ) => {
+                            if let Some(param) = FxParam::from_name(p) {
+                                let _res = command_tx.try_send(ControlMessage::TrackFxParam {
+                                    tcode,
+                                    val: *val,
+                                    track_num: *idx as usize,
+                                    fx_num: *fx as usize,
+                                    param,
+                                });
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/mute" => {
+                    // track, 1 on / 0 off
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Int(on))) => {
+                            let _res = command_tx.try_send(ControlMessage::TrackMute {
+                                tcode,
+                                val: *on as f32,
+                                track_num: *idx as usize,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/solo" => {
+                    // track, 1 on / 0 off
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Int(on))) => {
+                            let _res = command_tx.try_send(ControlMessage::TrackSolo {
+                                tcode,
+                                val: *on as f32,
+                                track_num: *idx as usize,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/cue" => {
+                    // track, 1 on / 0 off
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Int(on))) => {
+                            let _res = command_tx.try_send(ControlMessage::TrackCue {
+                                tcode,
+                                val: *on as f32,
+                                track_num: *idx as usize,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/record" => {
+                    // track, 1 arms / 0 disarms
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Int(on))) => {
+                            let _res = command_tx.try_send(ControlMessage::TrackRecord {
+                                tcode,
+                                val: *on as f32,
+                                track_num: *idx as usize,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/trigger" => {
+                    // track, optional slice
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), slice) => {
+                            let slice = match slice {
+                                Some(OscType::Int(s)) if *s >= 0 => Some(*s as usize),
+                                _ => None,
+                            };
+                            let _res = command_tx.try_send(ControlMessage::TrackTrigger {
+                                tcode,
+                                track_num: *idx as usize,
+                                slice,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/release" => {
+                    // track
+                    match args.first() {
+                        Some(OscType::Int(idx)) => {
+                            let _res = command_tx.try_send(ControlMessage::TrackRelease {
+                                tcode,
+                                track_num: *idx as usize,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/freeze" => {
+                    // track, 1 holds / 0 releases
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Int(on))) => {
+                            let _res = command_tx.try_send(ControlMessage::TrackFreeze {
+                                tcode,
+                                val: *on as f32,
+                                track_num: *idx as usize,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/send" => {
+                    // track, send bus, level
+                    match (args.first(), args.get(1), args.get(2)) {
+                        (
+                            Some(OscType::Int(idx)),
+                            Some(OscType::Int(send)),
+                            Some(OscType::Float(val)),
+                        ) => {
+                            let _res = command_tx.try_send(ControlMessage::TrackSend {
+                                tcode,
+                                val: *val,
+                                track_num: *idx as usize,
+                                send_num: *send as usize,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/send/fx" => {
+                    // send bus, fx index in the chain, param name, normalized val
+                    match (args.first(), args.get(1), args.get(2), args.get(3)) {
+                        (
+                            Some(OscType::Int(send)),
+                            Some(OscType::Int(fx)),
+                            Some(OscType::String(p)),
+                            Some(OscType::Float(val)),
+                        ) => {
+                            if let Some(param) = FxParam::from_name(p) {
+                                let _res = command_tx.try_send(ControlMessage::SendFxParam {
+                                    tcode,
+                                    val: *val,
+                                    send_num: *send as usize,
+                                    fx_num: *fx as usize,
+                                    param,
+                                });
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/send/volume" => {
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Float(val))) => {
+                            let _res = command_tx.try_send(ControlMessage::SendVolume {
+                                tcode,
+                                val: *val,
+                                send_num: *idx as usize,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/send/pan" => {
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Float(val))) => {
+                            let _res = command_tx.try_send(ControlMessage::SendPan {
+                                tcode,
+                                val: *val,
+                                send_num: *idx as usize,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/group/volume" => {
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Float(val))) => {
+                            let _res = command_tx.try_send(ControlMessage::GroupVolume {
+                                tcode,
+                                val: *val,
+                                group_num: *idx as usize,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/group/pan" => {
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Float(val))) => {
+                            let _res = command_tx.try_send(ControlMessage::GroupPan {
+                                tcode,
+                                val: *val,
+                                group_num: *idx as usize,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/group/fx" => {
+                    // group bus, fx index in the chain, param name, normalized val
+                    match (args.first(), args.get(1), args.get(2), args.get(3)) {
+                        (
+                            Some(OscType::Int(group)),
+                            Some(OscType::Int(fx)),
+                            Some(OscType::String(p)),
+                            Some(OscType::Float(val)),
+                        ) => {
+                            if let Some(param) = FxParam::from_name(p) {
+                                let _res = command_tx.try_send(ControlMessage::GroupFxParam {
+                                    tcode,
+                                    val: *val,
+                                    group_num: *group as usize,
+                                    fx_num: *fx as usize,
+                                    param,
+                                });
+                            }
+                        }
+                        _ => {}
+                    }
+                }
                 "/smplr/track/next_sample" => {
-                    let args = msg.args.unwrap();
                     // nice way to handle args :D
-                    match &args[0] {
-                        OscType::Int(idx) => {
+                    match args.first() {
+                        Some(OscType::Int(idx)) => {
                             // build message
                             let m = ControlMessage::TrackNextSample {
-                                tcode: 0,
+                                tcode,
                                 track_num: *idx as usize,
                             };
                             // send
@@ -180,13 +469,12 @@ fn handle_incoming_packet(
                     }
                 },
                 "/smplr/track/prev_sample" => {
-                    let args = msg.args.unwrap();
                     // nice way to handle args :D
-                    match &args[0] {
-                        OscType::Int(idx) => {
+                    match args.first() {
+                        Some(OscType::Int(idx)) => {
                             // build message
                             let m = ControlMessage::TrackPrevSample {
-                                tcode: 0,
+                                tcode,
                                 track_num: *idx as usize,
                             };
                             // send
@@ -196,21 +484,20 @@ fn handle_incoming_packet(
                     }
                 },
                 "/smplr/track/slicer/transform" => {
-                    let args = msg.args.unwrap();
                     // nice way to handle args :D
-                    match (&args[0], &args[1]) {
-                        (OscType::Int(idx), OscType::String(t)) => {
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::String(t))) => {
                             match &t[..] {
                                 "reset" => {
                                     let _res = command_tx.try_send(ControlMessage::Slicer {
-                                        tcode: 0,
+                                        tcode,
                                         track_num: *idx as usize,
                                         message: SlicerMessage::Transform(TransformType::Reset())
                                     });
                                 }
                                 "rand_swap" => {
                                     let _res = command_tx.try_send(ControlMessage::Slicer {
-                                        tcode: 0,
+                                        tcode,
                                         track_num: *idx as usize,
                                         message: SlicerMessage::Transform(TransformType::RandSwap())
                                     });
@@ -222,12 +509,11 @@ fn handle_incoming_packet(
                     }
                 },
                 "/smplr/track/slicer/repeat" => {
-                    let args = msg.args.unwrap();
                     // nice way to handle args :D
-                    match (&args[0], &args[1]) {
-                        (OscType::Int(idx), OscType::Int(q)) => {
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Int(q))) => {
                             let _res = command_tx.try_send(ControlMessage::Slicer {
-                                tcode: 0,
+                                tcode,
                                 track_num: *idx as usize,
                                 message: SlicerMessage::Transform(TransformType::QuantRepeat{
                                     quant: *q as usize,
@@ -238,6 +524,211 @@ fn handle_incoming_packet(
                         _ => {}
                     }
                 }
+                "/smplr/track/slicer/reverse" => {
+                    // track, slice to flip or probability to flip each slice
+                    let transform = match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Int(slice))) => Some((
+                            idx,
+                            TransformType::Reverse {
+                                slice_index: *slice as usize,
+                            },
+                        )),
+                        (Some(OscType::Int(idx)), Some(OscType::Float(amount))) => {
+                            Some((idx, TransformType::RandReverse { amount: *amount }))
+                        }
+                        _ => None,
+                    };
+                    if let Some((idx, t)) = transform {
+                        let _res = command_tx.try_send(ControlMessage::Slicer {
+                            tcode,
+                            track_num: *idx as usize,
+                            message: SlicerMessage::Transform(t),
+                        });
+                    }
+                }
+                "/smplr/track/slicer/stutter" => {
+                    // track, slice, repeats
+                    match (args.first(), args.get(1), args.get(2)) {
+                        (
+                            Some(OscType::Int(idx)),
+                            Some(OscType::Int(slice)),
+                            Some(OscType::Int(repeats)),
+                        ) => {
+                            let _res = command_tx.try_send(ControlMessage::Slicer {
+                                tcode,
+                                track_num: *idx as usize,
+                                message: SlicerMessage::Transform(TransformType::Stutter {
+                                    slice_index: *slice as usize,
+                                    repeats: *repeats as usize,
+                                }),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/slicer/gain" => {
+                    // track, slice, gain
+                    match (args.first(), args.get(1), args.get(2)) {
+                        (
+                            Some(OscType::Int(idx)),
+                            Some(OscType::Int(slice)),
+                            Some(OscType::Float(gain)),
+                        ) => {
+                            let _res = command_tx.try_send(ControlMessage::Slicer {
+                                tcode,
+                                track_num: *idx as usize,
+                                message: SlicerMessage::Transform(TransformType::SliceGain {
+                                    slice_index: *slice as usize,
+                                    gain: *gain,
+                                }),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/slicer/pitch" => {
+                    // track, slice, semitones
+                    match (args.first(), args.get(1), args.get(2)) {
+                        (
+                            Some(OscType::Int(idx)),
+                            Some(OscType::Int(slice)),
+                            Some(OscType::Float(semitones)),
+                        ) => {
+                            let _res = command_tx.try_send(ControlMessage::Slicer {
+                                tcode,
+                                track_num: *idx as usize,
+                                message: SlicerMessage::Transform(TransformType::SliceRepitch {
+                                    slice_index: *slice as usize,
+                                    semitones: *semitones,
+                                }),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/slicer/generate" => {
+                    // track, density, variation, optional seed
+                    match (args.first(), args.get(1), args.get(2), args.get(3)) {
+                        (
+                            Some(OscType::Int(idx)),
+                            Some(OscType::Float(density)),
+                            Some(OscType::Float(variation)),
+                            seed,
+                        ) => {
+                            let seed = match seed {
+                                Some(OscType::Int(s)) => *s as u64,
+                                _ => 0,
+                            };
+                            let _res = command_tx.try_send(ControlMessage::Slicer {
+                                tcode,
+                                track_num: *idx as usize,
+                                message: SlicerMessage::Transform(TransformType::Generate {
+                                    density: *density,
+                                    variation: *variation,
+                                    seed,
+                                }),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/slicer/pattern" => {
+                    // track, steps, optional steps per bar
+                    match (args.first(), args.get(1), args.get(2)) {
+                        (Some(OscType::Int(idx)), Some(OscType::String(steps)), div) => {
+                            let div = match div {
+                                Some(OscType::Int(d)) if *d > 0 => *d as u64,
+                                _ => 16,
+                            };
+                            match SlicePattern::parse(steps, div) {
+                                Ok(pattern) => {
+                                    let _res = command_tx.try_send(ControlMessage::Slicer {
+                                        tcode,
+                                        track_num: *idx as usize,
+                                        message: SlicerMessage::Pattern(Arc::new(pattern)),
+                                    });
+                                }
+                                Err(e) => println!("osc: {}", e),
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/slicer/beat_repeat" => {
+                    // track, quant, optional gate, roll, pitch, decay and bars
+                    match (args.first(), args.get(1)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Int(quant))) if *quant > 0 => {
+                            let mut repeat = BeatRepeat::new(*quant as u64);
+                            let float = |i: usize, default: f32| match args.get(i) {
+                                Some(OscType::Float(v)) => *v,
+                                _ => default,
+                            };
+                            repeat.gate = float(2, repeat.gate);
+                            repeat.roll = float(3, repeat.roll);
+                            repeat.pitch = float(4, repeat.pitch);
+                            repeat.decay = float(5, repeat.decay);
+                            if let Some(OscType::Int(bars)) = args.get(6) {
+                                repeat.bars = (*bars).max(0) as u64;
+                            }
+                            let _res = command_tx.try_send(ControlMessage::Slicer {
+                                tcode,
+                                track_num: *idx as usize,
+                                message: SlicerMessage::BeatRepeat(repeat),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/slicer/beat_repeat/release" => {
+                    // track
+                    match args.first() {
+                        Some(OscType::Int(idx)) => {
+                            let _res = command_tx.try_send(ControlMessage::Slicer {
+                                tcode,
+                                track_num: *idx as usize,
+                                message: SlicerMessage::BeatRelease(),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/track/slicer/note" => {
+                    // track, slice, optional velocity
+                    match (args.first(), args.get(1), args.get(2)) {
+                        (Some(OscType::Int(idx)), Some(OscType::Int(slice)), vel) => {
+                            let velocity = match vel {
+                                Some(OscType::Float(v)) => *v,
+                                _ => 1.0,
+                            };
+                            let _res = command_tx.try_send(ControlMessage::Slicer {
+                                tcode,
+                                track_num: *idx as usize,
+                                message: SlicerMessage::NoteOn {
+                                    slice: *slice as usize,
+                                    velocity,
+                                },
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                "/smplr/clock/start" => {
+                    let _res = command_tx.try_send(ControlMessage::ClockStart { tcode });
+                }
+                "/smplr/clock/stop" => {
+                    let _res = command_tx.try_send(ControlMessage::ClockStop { tcode });
+                }
+                "/smplr/clock/tempo" => {
+                    match args.first() {
+                        Some(OscType::Float(val)) => {
+                            let _res = command_tx.try_send(ControlMessage::ClockTempo {
+                                tcode,
+                                val: *val,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
                 _ => {
                     println!("osc: unimplemented adress: {:?}", msg.addr);
                 }
@@ -258,9 +749,9 @@ fn handle_ping(
 ) {
     match msg.args {
         Some(args) => {
-            let rnd_ping = &args[0];
+            let rnd_ping = args.first();
             match rnd_ping {
-                OscType::Int(r) => {
+                Some(OscType::Int(r)) => {
                     // init the remote control
                     // change port to expected remote port
                     let mut new_from = from.clone();
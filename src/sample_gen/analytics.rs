@@ -10,7 +10,6 @@ use regex::Regex;
 // consts
 const HOP_SIZE: usize = 512;
 const WIND_SIZE: usize = 2048;
-const SR: usize = 44_100;
 
 // Parse the original tempo based on the beat value written in the filename
 fn parse_filepath_beats(path: &str) -> Result<(usize, String), &str> {
@@ -46,7 +45,7 @@ fn parse_filepath_beats(path: &str) -> Result<(usize, String), &str> {
 
 /// Get the original tempo based on the beat value written in the filename, or analized with Aubio if not present.
 /// Returns original tempo as computed from file name and the number of beats
-pub fn read_original_tempo(path: &str, num_samples: usize) -> Option<(f64, usize)> {
+pub fn read_original_tempo(path: &str, num_samples: usize, sample_rate: f64) -> Option<(f64, usize)> {
     // compute number of beats
     let (num, unit) = match parse_filepath_beats(path) {
         Ok(n) => n,
@@ -56,7 +55,7 @@ pub fn read_original_tempo(path: &str, num_samples: usize) -> Option<(f64, usize
     match unit.as_str() {
         // calculate bpm from num samples and num of beats
         "beats" => {
-            let ms = Samples((num_samples as i64) / 2).to_ms(44_100.0);
+            let ms = Samples((num_samples as i64) / 2).to_ms(sample_rate);
             let secs = ms.to_f64().unwrap() / 1000.0;
             return Some((60.0 / (secs / num as f64), num));
         }
@@ -64,7 +63,7 @@ pub fn read_original_tempo(path: &str, num_samples: usize) -> Option<(f64, usize
         "bpm" => {
             let num_beats = Samples((num_samples as i64) / 2).beats(
                 num as f64,
-                44_100.0,
+                sample_rate,
             );
             return Some((num as f64, num_beats as usize));
         }
@@ -73,7 +72,7 @@ pub fn read_original_tempo(path: &str, num_samples: usize) -> Option<(f64, usize
 }
 
 /// Onset detector via Aubio.
-pub fn detect_onsets(samples: &[f32]) -> Vec<usize> {
+pub fn detect_onsets(samples: &[f32], sample_rate: f64) -> Vec<usize> {
     let len = samples.len() / 2;
     let mono: Vec<f32> = samples
         .iter()
@@ -86,7 +85,7 @@ pub fn detect_onsets(samples: &[f32]) -> Vec<usize> {
     let mut chunk_iter = mono.chunks(HOP_SIZE);
 
     // onset detector
-    let mut onset = Onset::new(OnsetMode::SpecFlux(),WIND_SIZE, HOP_SIZE, SR).expect("Onset::new");
+    let mut onset = Onset::new(OnsetMode::SpecFlux(),WIND_SIZE, HOP_SIZE, sample_rate as usize).expect("Onset::new");
 
     // params
     onset.set_threshold(0.3);
@@ -135,7 +134,7 @@ pub fn detect_onsets(samples: &[f32]) -> Vec<usize> {
 }
 
 /// BPM detector via aubio.
-pub fn detect_bpm(samples: &[f32]) -> f64 {
+pub fn detect_bpm(samples: &[f32], sample_rate: f64) -> f64 {
     // mono version
     let mono: Vec<f32> = samples
         .iter()
@@ -146,7 +145,7 @@ pub fn detect_bpm(samples: &[f32]) -> f64 {
 
     // let mono: Vec<f32> = samples.iter().step_by(2).map(|x| *x).collect();
     let mut chunk_iter = mono.chunks(HOP_SIZE / 4); // by chunk
-    let mut tempo = Tempo::new(WIND_SIZE / 4, HOP_SIZE / 4, SR).expect("Tempo::new");
+    let mut tempo = Tempo::new(WIND_SIZE / 4, HOP_SIZE / 4, sample_rate as usize).expect("Tempo::new");
 
     loop {
         let next = chunk_iter.next();
@@ -0,0 +1,613 @@
+//! Audio files decoding layer.
+//! Decodes the supported audio formats with pure rust decoders,
+//! to interleaved stereo float samples, ready to be analysed by the SmartBuffer.
+//!
+//! Supported so far:
+//! - WAV with hound.
+//! - AIFF / AIFC (uncompressed), parsed here.
+//! - FLAC with claxon.
+//! - Ogg Vorbis with lewton.
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use claxon::FlacReader;
+use hound::{SampleFormat, WavReader};
+use lewton::inside_ogg::OggStreamReader;
+use sample::Sample;
+
+use crate::config::Downmix;
+
+/// Decoded audio file
+#[derive(Debug, Clone)]
+pub struct Decoded {
+    /// Interleaved stereo samples
+    pub samples: Vec<f32>,
+    /// Sample rate of the file
+    pub sample_rate: u32,
+}
+
+/// Supported audio formats
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav(),
+    Aiff(),
+    Flac(),
+    Vorbis(),
+}
+
+/// Guess the audio format from the file extension
+pub fn guess_format(path: &str) -> Option<AudioFormat> {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    match ext.as_ref().map(String::as_str) {
+        Some("wav") | Some("wave") => Some(AudioFormat::Wav()),
+        Some("aif") | Some("aiff") | Some("aifc") => Some(AudioFormat::Aiff()),
+        Some("flac") => Some(AudioFormat::Flac()),
+        Some("ogg") | Some("oga") => Some(AudioFormat::Vorbis()),
+        _ => None,
+    }
+}
+
+/// Decodes an audio file, whatever the supported format.
+/// Mono is upmixed, more than two channels are downmixed following the given rule.
+pub fn decode_file(path: &str, downmix: Downmix) -> Result<Decoded, &'static str> {
+    let decoded = match guess_format(path) {
+        Some(AudioFormat::Wav()) => decode_wav(path, downmix),
+        Some(AudioFormat::Aiff()) => decode_aiff(path, downmix),
+        Some(AudioFormat::Flac()) => decode_flac(path, downmix),
+        Some(AudioFormat::Vorbis()) => decode_vorbis(path, downmix),
+        None => Err("UnsupportedFormat"),
+    }?;
+
+    // a broken header, nothing to resample from
+    if decoded.sample_rate == 0 {
+        return Err("CorruptedAudio");
+    }
+    // a truncated stream, or a sample lost while decoding, leaves half a frame
+    if decoded.samples.len() % 2 != 0 {
+        return Err("CorruptedAudio");
+    }
+    Ok(decoded)
+}
+
+/// Decodes a wave file
+fn decode_wav(path: &str, downmix: Downmix) -> Result<Decoded, &'static str> {
+    // load some audio
+    let reader = match WavReader::open(path) {
+        Ok(r) => r,
+        Err(err) => {
+            println!("decoder: wav error {}", err);
+            return Err("UnreadablePath");
+        }
+    };
+
+    // get file spec
+    let spec = reader.spec();
+
+    // our samples interleaved
+    let samples: Vec<f32> = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, 32) => reader
+            .into_samples::<f32>()
+            .filter_map(Result::ok)
+            .collect(),
+        (SampleFormat::Int, 8..=32) => {
+            // samples are right aligned integers
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .filter_map(Result::ok)
+                .map(|s| s as f32 * scale)
+                .collect()
+        }
+        _ => return Err("Wave format not supported"),
+    };
+
+    Ok(Decoded {
+        samples: to_stereo(samples, spec.channels as usize, downmix),
+        sample_rate: spec.sample_rate,
+    })
+}
+
+/// Decodes a flac file
+fn decode_flac(path: &str, downmix: Downmix) -> Result<Decoded, &'static str> {
+    let mut reader = match FlacReader::open(path) {
+        Ok(r) => r,
+        Err(err) => {
+            println!("decoder: flac error {}", err);
+            return Err("UnreadablePath");
+        }
+    };
+
+    // get stream info
+    let info = reader.streaminfo();
+    if info.bits_per_sample == 0 || info.bits_per_sample > 32 {
+        return Err("Flac bits_per_sample not supported");
+    }
+
+    // samples are right aligned integers
+    let scale = 1.0 / (1u64 << (info.bits_per_sample - 1)) as f32;
+    let samples: Vec<f32> = reader
+        .samples()
+        .filter_map(Result::ok)
+        .map(|s| s as f32 * scale)
+        .collect();
+
+    Ok(Decoded {
+        samples: to_stereo(samples, info.channels as usize, downmix),
+        sample_rate: info.sample_rate,
+    })
+}
+
+/// Decodes an ogg vorbis file
+fn decode_vorbis(path: &str, downmix: Downmix) -> Result<Decoded, &'static str> {
+    let file = File::open(path).map_err(|_| "UnreadablePath")?;
+    let mut reader = match OggStreamReader::new(file) {
+        Ok(r) => r,
+        Err(err) => {
+            println!("decoder: vorbis error {}", err);
+            return Err("UnreadableVorbis");
+        }
+    };
+
+    let channels = reader.ident_hdr.audio_channels as usize;
+    let sample_rate = reader.ident_hdr.audio_sample_rate;
+
+    // decode all the packets
+    let mut samples = Vec::<f32>::new();
+    loop {
+        match reader.read_dec_packet_itl() {
+            Ok(Some(packet)) => samples.extend(packet.into_iter().map(i16::to_sample::<f32>)),
+            Ok(None) => break,
+            Err(err) => {
+                println!("decoder: vorbis error {}", err);
+                return Err("CorruptedVorbis");
+            }
+        }
+    }
+
+    Ok(Decoded {
+        samples: to_stereo(samples, channels, downmix),
+        sample_rate,
+    })
+}
+
+/// Decodes an aiff or aifc file.
+/// Only uncompressed PCM is supported (big endian, and 'sowt' little endian).
+fn decode_aiff(path: &str, downmix: Downmix) -> Result<Decoded, &'static str> {
+    let mut bytes = Vec::<u8>::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .map_err(|_| "UnreadablePath")?;
+
+    // FORM container
+    if bytes.len() < 12 || &bytes[0..4] != b"FORM" {
+        return Err("NotAnAiff");
+    }
+    let is_aifc = match &bytes[8..12] {
+        b"AIFF" => false,
+        b"AIFC" => true,
+        _ => return Err("NotAnAiff"),
+    };
+
+    // walk the chunks
+    let mut comm: Option<(usize, usize, u32, bool)> = None;
+    let mut ssnd: Option<&[u8]> = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let size = read_u32_be(&bytes[pos + 4..pos + 8]) as usize;
+        let start = pos + 8;
+        let end = (start + size).min(bytes.len());
+        let chunk = &bytes[start..end];
+
+        match id {
+            b"COMM" => {
+                if chunk.len() < 18 {
+                    return Err("CorruptedAiff");
+                }
+                let channels = read_u16_be(&chunk[0..2]) as usize;
+                let bits = read_u16_be(&chunk[6..8]) as usize;
+                let sample_rate = read_extended(&chunk[8..18]) as u32;
+                // aifc carries a compression type
+                let little_endian = if is_aifc {
+                    match chunk.get(18..22) {
+                        Some(b"NONE") | Some(b"twos") => false,
+                        Some(b"sowt") => true,
+                        _ => return Err("Aiff compression not supported"),
+                    }
+                } else {
+                    false
+                };
+                comm = Some((channels, bits, sample_rate, little_endian));
+            }
+            b"SSND" => {
+                if chunk.len() < 8 {
+                    return Err("CorruptedAiff");
+                }
+                let offset = read_u32_be(&chunk[0..4]) as usize;
+                ssnd = chunk.get(8 + offset..);
+            }
+            _ => {}
+        }
+
+        // chunks are padded to even sizes
+        pos = start + size + (size & 1);
+    }
+
+    let (channels, bits, sample_rate, little_endian) = comm.ok_or("CorruptedAiff")?;
+    let data = ssnd.ok_or("CorruptedAiff")?;
+    if channels == 0 || bits == 0 || bits > 32 {
+        return Err("Aiff bits_per_sample not supported");
+    }
+
+    // samples are left aligned in whole bytes
+    let width = (bits + 7) / 8;
+    let scale = 1.0 / 2f32.powi(width as i32 * 8 - 1);
+    let samples: Vec<f32> = data
+        .chunks_exact(width)
+        .map(|s| {
+            let mut v: i32 = 0;
+            for i in 0..width {
+                let b = if little_endian {
+                    s[width - 1 - i]
+                } else {
+                    s[i]
+                };
+                v = (v << 8) | b as i32;
+            }
+            // sign extend
+            let shift = 32 - width * 8;
+            ((v << shift) >> shift) as f32 * scale
+        })
+        .collect();
+
+    Ok(Decoded {
+        samples: to_stereo(samples, channels, downmix),
+        sample_rate,
+    })
+}
+
+/// Interleaved samples to interleaved stereo.
+/// Mono is duplicated on both sides, more channels are downmixed following the rule.
+fn to_stereo(samples: Vec<f32>, channels: usize, downmix: Downmix) -> Vec<f32> {
+    match channels {
+        2 => samples,
+        1 => {
+            let mut stereo = Vec::<f32>::with_capacity(samples.len() * 2);
+            for s in samples.iter() {
+                stereo.push(*s);
+                stereo.push(*s);
+            }
+            stereo
+        }
+        0 => Vec::new(),
+        _ => {
+            let mut stereo = Vec::<f32>::with_capacity(samples.len() / channels * 2);
+            for frame in samples.chunks_exact(channels) {
+                let (left, right) = match downmix {
+                    Downmix::FirstPair => (frame[0], frame[1]),
+                    Downmix::Fold => {
+                        let lefts = frame.iter().step_by(2);
+                        let rights = frame.iter().skip(1).step_by(2);
+                        (
+                            lefts.sum::<f32>() / ((channels + 1) / 2) as f32,
+                            rights.sum::<f32>() / (channels / 2) as f32,
+                        )
+                    }
+                    Downmix::Mono => {
+                        let mono = frame.iter().sum::<f32>() / channels as f32;
+                        (mono, mono)
+                    }
+                };
+                stereo.push(left);
+                stereo.push(right);
+            }
+            stereo
+        }
+    }
+}
+
+/// Reads a big endian u16
+fn read_u16_be(b: &[u8]) -> u16 {
+    (b[0] as u16) << 8 | b[1] as u16
+}
+
+/// Reads a big endian u32
+fn read_u32_be(b: &[u8]) -> u32 {
+    (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+}
+
+/// Reads a 80 bits IEEE 754 extended float, as used for the aiff sample rate
+fn read_extended(b: &[u8]) -> f64 {
+    let sign = if b[0] & 0x80 != 0 { -1.0 } else { 1.0 };
+    let exponent = (((b[0] & 0x7f) as i32) << 8) | b[1] as i32;
+    let mut mantissa: u64 = 0;
+    for byte in &b[2..10] {
+        mantissa = (mantissa << 8) | *byte as u64;
+    }
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+    sign * mantissa as f64 * 2f64.powi(exponent - 16383 - 63)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hound::{WavSpec, WavWriter};
+
+    /// temp file path for a test
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("smplr_decoder_{}", name));
+        String::from(path.to_str().unwrap())
+    }
+
+    /// writes a float wave file
+    fn write_float_wav(name: &str, channels: u16, samples: &[f32]) -> String {
+        let path = temp_path(name);
+        let spec = WavSpec {
+            channels,
+            sample_rate: 44_100,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for s in samples {
+            writer.write_sample(*s).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_decode_mono_wav() {
+        let path = temp_path("mono.wav");
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 22_050,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for s in &[0i16, 16384, -16384] {
+            writer.write_sample(*s).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let decoded = decode_file(&path, Downmix::FirstPair).unwrap();
+        assert_eq!(decoded.sample_rate, 22_050);
+        assert_eq!(decoded.samples, vec![0.0, 0.0, 0.5, 0.5, -0.5, -0.5]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_24_bits_wav() {
+        let path = temp_path("24bits.wav");
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 48_000,
+            bits_per_sample: 24,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for s in &[4_194_304i32, -4_194_304] {
+            writer.write_sample(*s).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let decoded = decode_file(&path, Downmix::FirstPair).unwrap();
+        assert_eq!(decoded.sample_rate, 48_000);
+        assert_eq!(decoded.samples, vec![0.5, -0.5]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_float_wav() {
+        let path = write_float_wav("float.wav", 2, &[0.25, -0.75, 1.0, -1.0]);
+
+        let decoded = decode_file(&path, Downmix::FirstPair).unwrap();
+        assert_eq!(decoded.samples, vec![0.25, -0.75, 1.0, -1.0]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_multichannel_wav() {
+        let path = write_float_wav("quad.wav", 4, &[0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8]);
+
+        let first_pair = decode_file(&path, Downmix::FirstPair).unwrap();
+        assert_eq!(first_pair.samples, vec![0.1, 0.2, 0.5, 0.6]);
+
+        let fold = decode_file(&path, Downmix::Fold).unwrap();
+        let expected = [0.2, 0.3, 0.6, 0.7];
+        assert_eq!(fold.samples.len(), expected.len());
+        for (s, e) in fold.samples.iter().zip(expected.iter()) {
+            assert!((s - e).abs() < 1e-6);
+        }
+
+        let mono = decode_file(&path, Downmix::Mono).unwrap();
+        let expected = [0.25, 0.25, 0.65, 0.65];
+        assert_eq!(mono.samples.len(), expected.len());
+        for (s, e) in mono.samples.iter().zip(expected.iter()) {
+            assert!((s - e).abs() < 1e-6);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// writes a 16 bits aiff file, the sample rate as 80 bits extended
+    fn write_aiff(name: &str, channels: u16, rate: [u8; 10], samples: &[i16]) -> String {
+        let path = temp_path(name);
+
+        // COMM: channels, frames, 16 bits, rate
+        let mut comm = Vec::<u8>::new();
+        comm.extend_from_slice(&channels.to_be_bytes());
+        comm.extend_from_slice(&((samples.len() / channels as usize) as u32).to_be_bytes());
+        comm.extend_from_slice(&16u16.to_be_bytes());
+        comm.extend_from_slice(&rate);
+        // SSND: offset, block size, big endian samples
+        let mut ssnd = vec![0; 8];
+        for s in samples {
+            ssnd.extend_from_slice(&s.to_be_bytes());
+        }
+
+        let mut bytes = Vec::<u8>::new();
+        bytes.extend_from_slice(b"FORM");
+        let form_size = 4 + 8 + comm.len() + 8 + ssnd.len();
+        bytes.extend_from_slice(&(form_size as u32).to_be_bytes());
+        bytes.extend_from_slice(b"AIFF");
+        bytes.extend_from_slice(b"COMM");
+        bytes.extend_from_slice(&(comm.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&comm);
+        bytes.extend_from_slice(b"SSND");
+        bytes.extend_from_slice(&(ssnd.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&ssnd);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// 44.1 kHz as 80 bits extended
+    const AIFF_44100: [u8; 10] = [0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0];
+
+    #[test]
+    fn test_decode_mono_aiff() {
+        let path = write_aiff("mono.aiff", 1, AIFF_44100, &[16384, -16384]);
+
+        let decoded = decode_file(&path, Downmix::FirstPair).unwrap();
+        assert_eq!(decoded.sample_rate, 44_100);
+        assert_eq!(decoded.samples, vec![0.5, 0.5, -0.5, -0.5]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_zero_rate_aiff() {
+        let path = write_aiff("zero_rate.aiff", 1, [0; 10], &[16384, -16384]);
+
+        assert_eq!(
+            decode_file(&path, Downmix::FirstPair).err(),
+            Some("CorruptedAudio")
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_half_frame() {
+        // a stereo stream cut in the middle of a frame is an error, not a panic later on
+        let path = write_aiff("half_frame.aiff", 2, AIFF_44100, &[16384, -16384, 16384]);
+
+        assert_eq!(
+            decode_file(&path, Downmix::FirstPair).err(),
+            Some("CorruptedAudio")
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// CRC-8 of the flac frame headers, polynomial 0x07
+    fn flac_crc8(bytes: &[u8]) -> u8 {
+        bytes.iter().fold(0u8, |mut crc, b| {
+            crc ^= b;
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 {
+                    (crc << 1) ^ 0x07
+                } else {
+                    crc << 1
+                };
+            }
+            crc
+        })
+    }
+
+    /// CRC-16 of the flac frames, polynomial 0x8005
+    fn flac_crc16(bytes: &[u8]) -> u16 {
+        bytes.iter().fold(0u16, |mut crc, b| {
+            crc ^= (*b as u16) << 8;
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x8005
+                } else {
+                    crc << 1
+                };
+            }
+            crc
+        })
+    }
+
+    /// writes a 16 bits flac file, one frame of verbatim subframes
+    fn write_flac(name: &str, channels: u8, rate: u32, samples: &[i16]) -> String {
+        let path = temp_path(name);
+        let block = samples.len() / channels as usize;
+
+        let mut bytes = Vec::<u8>::new();
+        bytes.extend_from_slice(b"fLaC");
+
+        // last metadata block, streaminfo
+        bytes.extend_from_slice(&[0x80, 0, 0, 34]);
+        bytes.extend_from_slice(&16u16.to_be_bytes());
+        bytes.extend_from_slice(&4096u16.to_be_bytes());
+        bytes.extend_from_slice(&[0; 6]);
+        let info = (rate as u64) << 44 | ((channels - 1) as u64) << 41 | 15u64 << 36 | block as u64;
+        bytes.extend_from_slice(&info.to_be_bytes());
+        bytes.extend_from_slice(&[0; 16]);
+
+        // frame header: 8 bits block size, rate from streaminfo, independent channels, 16 bits
+        let mut frame = vec![
+            0xff,
+            0xf8,
+            0x60,
+            (channels - 1) << 4 | 0b100 << 1,
+            0,
+            (block - 1) as u8,
+        ];
+        frame.push(flac_crc8(&frame));
+
+        // verbatim subframes, one per channel
+        for ch in 0..channels as usize {
+            frame.push(0x02);
+            for s in samples.iter().skip(ch).step_by(channels as usize) {
+                frame.extend_from_slice(&s.to_be_bytes());
+            }
+        }
+        let crc = flac_crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+
+        bytes.extend_from_slice(&frame);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_decode_mono_flac() {
+        let path = write_flac("mono.flac", 1, 22_050, &[0, 16384, -16384]);
+
+        let decoded = decode_file(&path, Downmix::FirstPair).unwrap();
+        assert_eq!(decoded.sample_rate, 22_050);
+        assert_eq!(decoded.samples, vec![0.0, 0.0, 0.5, 0.5, -0.5, -0.5]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_stereo_flac() {
+        let path = write_flac("stereo.flac", 2, 48_000, &[16384, -16384, -8192, 8192]);
+
+        let decoded = decode_file(&path, Downmix::FirstPair).unwrap();
+        assert_eq!(decoded.sample_rate, 48_000);
+        assert_eq!(decoded.samples, vec![0.5, -0.5, -0.25, 0.25]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_unsupported() {
+        assert!(decode_file("loop.mp3", Downmix::FirstPair).is_err());
+    }
+}
@@ -5,8 +5,17 @@ extern crate sample;
 extern crate easer;
 
 use self::easer::functions::*;
+use self::sample::frame::Stereo;
+use self::sample::Frame;
 use std::f32;
 
+use crate::audio::filters::{BiquadFilter, FilterOp, FilterType};
+
+/// Cutoff of the resampling anti-alias filter, relative to the new Nyquist frequency
+const RESAMPLE_CUTOFF: f64 = 0.9;
+/// Q of the two biquads of a 4th order Butterworth low pass
+const BUTTERWORTH_Q: [f32; 2] = [0.541_196_1, 1.306_563];
+
 /// A clamped cubic fade_in
 pub fn fade_in(t: i64, len: i64) -> f32 {
     if t == 0 {
@@ -70,6 +79,63 @@ mod tests {
             .save("plots/fade_out.svg")
             .expect("saving svg");
     }
+
+    /// one second of a sine tone
+    fn tone(freq: f64, rate: f64) -> Vec<Stereo<f32>> {
+        (0..rate as usize)
+            .map(|i| {
+                let s = (2.0 * std::f64::consts::PI * freq * i as f64 / rate).sin() as f32;
+                [s, s]
+            })
+            .collect()
+    }
+
+    /// peak of the frames, past the filter settling
+    fn peak(frames: &[Stereo<f32>]) -> f32 {
+        frames[frames.len() / 4..]
+            .iter()
+            .fold(0.0, |p, f| p.max(f[0].abs()))
+    }
+
+    #[test]
+    fn test_resample_len() {
+        let frames = vec![[0.0, 0.0]; 1000];
+        assert_eq!(resample(&frames, 96_000.0, 48_000.0).len(), 500);
+        assert_eq!(resample(&frames, 48_000.0, 96_000.0).len(), 2000);
+        assert_eq!(resample(&frames, 44_100.0, 48_000.0).len(), 1088);
+        assert_eq!(resample(&frames, 48_000.0, 48_000.0).len(), 1000);
+    }
+
+    #[test]
+    fn test_resample_tone() {
+        let rates = [
+            (44_100.0, 48_000.0),
+            (48_000.0, 44_100.0),
+            (88_200.0, 44_100.0),
+            (96_000.0, 48_000.0),
+            (96_000.0, 44_100.0),
+        ];
+        for (from_rate, to_rate) in rates.iter() {
+            let resampled = resample(&tone(1000.0, *from_rate), *from_rate, *to_rate);
+            assert!((resampled.len() as i64 - *to_rate as i64).abs() <= 1);
+
+            // same level, same pitch at the new rate
+            let p = peak(&resampled);
+            assert!(p > 0.95 && p < 1.05);
+            let crossings = resampled
+                .windows(2)
+                .filter(|w| (w[0][0] < 0.0) != (w[1][0] < 0.0))
+                .count();
+            assert!((crossings as i64 - 2000).abs() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_resample_anti_alias() {
+        // above the new nyquist, would fold back to 8 kHz
+        let resampled = resample(&tone(40_000.0, 96_000.0), 96_000.0, 48_000.0);
+        assert!(peak(&resampled) < 0.15);
+    }
 }
 
 /// Helper to normalize samples assuming interleaved stereo
@@ -109,3 +175,96 @@ pub fn normalize_samples(frames: &mut [f32]) {
         l_r[1] *= r_n_ratio;
     }
 }
+
+/// Resamples frames from a sample rate to another.
+/// Simple linear interpolation, as in RePitch.
+/// When downsampling, a low pass removes what is above the new Nyquist frequency first,
+/// or it would fold back as aliasing.
+pub fn resample(frames: &[Stereo<f32>], from_rate: f64, to_rate: f64) -> Vec<Stereo<f32>> {
+    // nothing to do
+    if frames.len() == 0 || from_rate == to_rate || from_rate <= 0.0 {
+        return frames.to_vec();
+    }
+
+    // anti-alias
+    let filtered;
+    let frames = if from_rate > to_rate {
+        filtered = anti_alias(frames, from_rate, to_rate);
+        &filtered[..]
+    } else {
+        frames
+    };
+
+    // step in source frames for each new frame
+    let step = from_rate / to_rate;
+    let new_len = (frames.len() as f64 / step) as usize;
+    let mut resampled = Vec::with_capacity(new_len);
+
+    for i in 0..new_len {
+        let pos = i as f64 * step;
+        let idx = pos as usize;
+        let frac = (pos - idx as f64) as f32;
+
+        // interpolate between this frame and the next
+        let left = frames[idx];
+        let right = match frames.get(idx + 1) {
+            Some(f) => *f,
+            None => left,
+        };
+        resampled.push(left.zip_map(right, |l, r| l + (r - l) * frac));
+    }
+
+    resampled
+}
+
+/// Low passes frames below the Nyquist frequency of the target rate, 4th order Butterworth
+fn anti_alias(frames: &[Stereo<f32>], from_rate: f64, to_rate: f64) -> Vec<Stereo<f32>> {
+    let cutoff = (to_rate / 2.0 * RESAMPLE_CUTOFF) as f32;
+    let mut filters: Vec<BiquadFilter> = BUTTERWORTH_Q
+        .iter()
+        .map(|q| {
+            BiquadFilter::create_filter(
+                FilterType::LowPass(),
+                FilterOp::UseQ(),
+                from_rate as f32,
+                cutoff,
+                0.0,
+                *q,
+                0.0,
+                0.0,
+            )
+        })
+        .collect();
+    frames
+        .iter()
+        .map(|f| filters.iter_mut().fold(*f, |f, filter| filter.process(f)))
+        .collect()
+}
+
+/// Small seeded random generator (splitmix64).
+/// The same seed always gives the same numbers, whatever the rand crate does.
+#[derive(Debug, Copy, Clone)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    /// Inits from a seed
+    pub fn new(seed: u64) -> Self {
+        SeededRng { state: seed }
+    }
+
+    /// Next random u64
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Next random f32 in 0..1
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
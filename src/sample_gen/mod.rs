@@ -13,17 +13,19 @@
 
 // re-publish submodule repitch as a public module;
 pub mod analytics;
+pub mod decoder;
 pub mod gen_utils;
 pub mod pvoc;
 pub mod repitch;
 pub mod slicer;
 
-use hound::WavReader;
 use sample::frame::Stereo;
-use sample::{Frame, Sample};
-use time_calc::{Beats, Ppqn, Samples};
+use sample::Frame;
+use time_calc::{Beats, Ppqn, Samples, Ticks};
 use std::collections::HashMap;
+use std::sync::Arc;
 
+use crate::config::Downmix;
 use crate::control::ControlMessage;
 
 /// pulse per quarter note
@@ -32,9 +34,14 @@ pub const PPQN: Ppqn = 24;
 /// how many sample to fade in / out to avoid clicks when resync audio
 const NOCLICK_FADE_LENGTH: u64 = 64;
 
+/// length of the window looped by a freeze, in ms
+const FREEZE_LEN_MS: f64 = 93.0;
+/// crossfade between the end and the start of the freeze window, in ms
+const FREEZE_FADE_MS: f64 = 23.0;
+
 /// PositionsMode defines how the slices are cut in a smart buffer.
 /// Can be Onset Detection or fixed BAR divisions.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum PositionsMode {
     /// Natural detected onsets.
     OnsetMode(),
@@ -48,6 +55,78 @@ pub enum PositionsMode {
     Bar16Mode(),
 }
 
+/// Trigger starts a triggered playback, the clock still drives the speed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Trigger {
+    /// Clock tick the playback starts on
+    pub tick: u64,
+    /// Slice to start from, the start of the sample if None
+    pub slice: Option<usize>,
+    /// Stops at the end of the sample or of the slice, plays until released otherwise
+    pub one_shot: bool,
+}
+
+/// TriggerState is a triggered playback, in frames of the buffer
+#[derive(Debug, Copy, Clone)]
+struct TriggerState {
+    /// Clock tick of the trigger
+    tick: u64,
+    /// First frame
+    start: u64,
+    /// End of a one shot
+    end: Option<u64>,
+}
+
+/// Freeze loops a small window of the buffer, independent of the clock
+#[derive(Debug, Copy, Clone)]
+struct Freeze {
+    /// First frame of the window
+    start: u64,
+    /// Frames played since the freeze
+    cursor: u64,
+    /// Length of the window, in frames
+    len: u64,
+    /// Length of the crossfade, in frames
+    fade: u64,
+}
+
+impl Freeze {
+    /// Freezes from a frame, the window lasts as long at any sample rate
+    fn new(start: u64, sample_rate: f64) -> Self {
+        let len = (FREEZE_LEN_MS * sample_rate / 1000.0) as u64;
+        let fade = (FREEZE_FADE_MS * sample_rate / 1000.0) as u64;
+        Freeze {
+            start,
+            cursor: 0,
+            len: len.max(1),
+            fade: fade.min(len),
+        }
+    }
+
+    /// Frame the window plays now
+    fn position(&self) -> u64 {
+        self.start + self.cursor % self.len
+    }
+
+    /// Gets the next frame of the window, its start fades in over the frames that follow its end.
+    /// The first pass plays straight on from the frozen frame
+    fn next_frame(&mut self, frames: &[Stereo<f32>]) -> Stereo<f32> {
+        let len = frames.len() as u64;
+        if len == 0 {
+            return Stereo::<f32>::equilibrium();
+        }
+        let pos = self.cursor % self.len;
+        let mut frame = frames[((self.start + pos) % len) as usize];
+        if self.cursor >= self.len && pos < self.fade {
+            let x = gen_utils::fade_in(pos as i64, self.fade as i64);
+            let tail = frames[((self.start + self.len + pos) % len) as usize];
+            frame = frame.scale_amp(x).add_amp(tail.scale_amp(1.0 - x));
+        }
+        self.cursor += 1;
+        frame
+    }
+}
+
 /// Basically an audio buffer (in frame format) with some metadata from analysis.
 #[derive(Debug, Clone)]
 pub struct SmartBuffer {
@@ -56,116 +135,196 @@ pub struct SmartBuffer {
     /// Samples in Stereo / float32 format. Use the `sample` Crate for convenience methods.
     /// We only support this format for the moment.
     frames: Vec<Stereo<f32>>,
+    /// Sample rate of the frames, always the engine sample rate once loaded.
+    sample_rate: f64,
     /// Original tempo of the audio phrase (if it's a phrase).
     original_tempo: f64,
     /// Number of beats analyzed in audio.
     num_beats: usize,
     /// Precomputed onsets positions. Contains detected Onsets positions and fixed divisions.
     positions: HashMap<PositionsMode, Vec<usize>>,
+    /// Slices of the slicer by positions mode, built with the positions off the audio thread.
+    slices: HashMap<PositionsMode, Arc<slicer::SliceMap>>,
+}
+
+/// Analysis is the metadata computed when a SmartBuffer is loaded, can be cached.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Analysis {
+    /// Original tempo of the audio phrase.
+    pub original_tempo: f64,
+    /// Number of beats analyzed in audio.
+    pub num_beats: usize,
+    /// Positions tables, as pairs because json keys must be strings.
+    pub positions: Vec<(PositionsMode, Vec<usize>)>,
 }
 
 /// Implementation
 impl SmartBuffer {
     /// returns an empty SmartBuffer, without allocation ?
-    pub fn new_empty() -> Self {
+    pub fn new_empty(sample_rate: f64) -> Self {
         SmartBuffer {
             frames: Vec::with_capacity(1024),
+            sample_rate,
             file_name: String::with_capacity(512),
             original_tempo: 120.0,
             num_beats: 4,
             positions: HashMap::<PositionsMode, Vec<usize>>::with_capacity(512),
+            slices: HashMap::new(),
         }
     }
 
-    /// Copy SmartBuffer without memory allocations
-    pub fn copy_from(&mut self, from: &SmartBuffer) {
-        // start by the frames
-        self.frames
-            .resize(from.frames.len(), Stereo::<f32>::equilibrium());
-        self.frames.copy_from_slice(&from.frames[..]);
-
-        // copy the fields
-        self.file_name.clear();
-        self.file_name.push_str(from.file_name.as_str());
-        self.num_beats = from.num_beats;
-        self.original_tempo = from.original_tempo;
-
-        // clone if empty
-        if self.positions.len() == 0 {
-            self.positions = from.positions.clone();
-        } else {
-            // trick to avoid mem allocs
-            for (key, val) in self.positions.iter_mut() {
-                let from_vec = &from.positions.get(key).unwrap();
-                val.resize(from_vec.len(), 0);
-                val.copy_from_slice(&from_vec[..]);
-            }
-        }
+    /// Loads and analyse an audio file, see the decoder module for the supported formats.
+    /// The frames are resampled to the engine sample rate if needed,
+    /// multichannel files are downmixed to stereo with the given rule.
+    pub fn load_file(
+        &mut self,
+        path: &str,
+        sample_rate: f64,
+        downmix: Downmix,
+    ) -> Result<bool, &str> {
+        // decode
+        let samples = self.decode_file(path, sample_rate, downmix)?;
+
+        // analyse
+        self.analyse(&samples[..], path);
+
+        Ok(true)
+    }
+
+    /// Loads an audio file with a known analysis, skips the analysis step.
+    pub fn load_file_analysed(
+        &mut self,
+        path: &str,
+        sample_rate: f64,
+        downmix: Downmix,
+        analysis: &Analysis,
+    ) -> Result<bool, &str> {
+        // decode
+        self.decode_file(path, sample_rate, downmix)?;
+
+        // restore analysis
+        self.original_tempo = analysis.original_tempo;
+        self.num_beats = analysis.num_beats;
+        self.positions = analysis.positions.iter().cloned().collect();
+        self.build_slices();
+
+        Ok(true)
     }
 
-    /// Loads and analyse a wave file
-    pub fn load_wave(&mut self, path: &str) -> Result<bool, &str> {
-        // load some audio
-        let reader = match WavReader::open(path) {
-            Ok(r) => r,
-            Err(err) => {
-                println!("Load_wave error {}", err);
-                return Err(concat!("UnreadablePath"));
+    /// Makes a SmartBuffer from recorded frames, at the engine sample rate.
+    /// The beat count is known, the tempo follows from the length, only the onsets are detected.
+    pub fn from_frames(
+        file_name: &str,
+        frames: Vec<Stereo<f32>>,
+        sample_rate: f64,
+        num_beats: usize,
+    ) -> Result<Self, &'static str> {
+        // nothing to analyse
+        if frames.is_empty() {
+            return Err("EmptyAudio");
+        }
+
+        let mut buffer = SmartBuffer::new_empty(sample_rate);
+        buffer.file_name = String::from(file_name);
+        buffer.frames = frames;
+        buffer.frames.shrink_to_fit();
+
+        // normalize on the loudest side, a one sided input keeps its balance
+        let peak = buffer
+            .frames
+            .iter()
+            .fold(0.0f32, |p, f| p.max(f[0].abs()).max(f[1].abs()));
+        if peak > 0.0 {
+            for frame in buffer.frames.iter_mut() {
+                *frame = frame.scale_amp(1.0 / peak);
             }
-        };
+        }
 
-        // get file spec
-        let spec = reader.spec();
+        // tempo and beats are exact for a take quantized to the bars
+        buffer.num_beats = num_beats.max(1);
+        buffer.original_tempo =
+            buffer.num_beats as f64 * 60.0 * sample_rate / buffer.frames.len() as f64;
+        let samples: Vec<f32> = buffer
+            .frames
+            .iter()
+            .flat_map(|f| f.iter().cloned())
+            .collect();
+        let onset_positions = analytics::detect_onsets(&samples[..], sample_rate);
+        buffer.set_postions(&samples[..], buffer.num_beats, onset_positions);
+
+        Ok(buffer)
+    }
 
-        // our samples interleaved
-        let mut samples: Vec<f32> = match spec.bits_per_sample {
-            24 | 32 => reader
-                .into_samples::<i32>()
-                .filter_map(Result::ok)
-                .map(i32::to_sample::<f32>)
-                .collect(),
-            16 => reader
-                .into_samples::<i16>()
-                .filter_map(Result::ok)
-                .map(i16::to_sample::<f32>)
+    /// Gets the analysis results
+    pub fn get_analysis(&self) -> Analysis {
+        Analysis {
+            original_tempo: self.original_tempo,
+            num_beats: self.num_beats,
+            positions: self
+                .positions
+                .iter()
+                .map(|(mode, pos)| (*mode, pos.clone()))
                 .collect(),
-            _ => {
-                return Err("Wave bits_per_sample not supported")
-            }    
-        };
+        }
+    }
+
+    /// Decodes an audio file in the frames, at the engine sample rate.
+    /// Returns the interleaved samples for analysis.
+    fn decode_file(
+        &mut self,
+        path: &str,
+        sample_rate: f64,
+        downmix: Downmix,
+    ) -> Result<Vec<f32>, &'static str> {
+        // decode some audio
+        let decoded = decoder::decode_file(path, downmix)?;
+        let mut samples = decoded.samples;
+
+        // nothing to analyse
+        if samples.len() < 2 {
+            return Err("EmptyAudio");
+        }
 
         // normalize samples
         // for consistency in volumes + better analysis
         gen_utils::normalize_samples(&mut samples[..]);
 
         // store in frames format
-        let frames = sample::slice::to_frame_slice(&samples[..]).unwrap() as &[Stereo<f32>]; // needed to be explicit
+        let frames: &[Stereo<f32>] = match sample::slice::to_frame_slice(&samples[..]) {
+            Some(f) => f,
+            None => return Err("CorruptedAudio"),
+        };
         self.frames = frames.to_vec();
+        self.sample_rate = sample_rate;
 
-        // analyse
-        self.analyse(&samples[..], path);
+        // resample to the engine sample rate
+        if decoded.sample_rate as f64 != sample_rate {
+            self.frames = gen_utils::resample(&self.frames[..], decoded.sample_rate as f64, sample_rate);
+            samples = self.frames.iter().flat_map(|f| f.iter().cloned()).collect();
+        }
 
-        Ok(true)
+        Ok(samples)
     }
 
     /// perform various sample analysis
     fn analyse(&mut self, samples: &[f32], path: &str) {
         // parse tempo from filename if possible
-        match analytics::read_original_tempo(path, samples.len()) {
+        match analytics::read_original_tempo(path, samples.len(), self.sample_rate) {
             Some((orig_tempo, beats)) => {
                 self.original_tempo = orig_tempo;
                 self.num_beats = beats;
             }
             None => {
                 // detect from aubio
-                self.original_tempo = analytics::detect_bpm(&samples[..]);
-                let beats = Samples(samples.len() as i64 / 2).beats(self.original_tempo, 44_100.0);
+                self.original_tempo = analytics::detect_bpm(&samples[..], self.sample_rate);
+                let beats =
+                    Samples(samples.len() as i64 / 2).beats(self.original_tempo, self.sample_rate);
                 self.num_beats = beats as usize;
             }
         }
 
         // compute onset positions
-        let onset_positions = analytics::detect_onsets(&samples[..]);
+        let onset_positions = analytics::detect_onsets(&samples[..], self.sample_rate);
 
         self.set_postions(samples, self.num_beats, onset_positions);
     }
@@ -214,15 +373,33 @@ impl SmartBuffer {
             PositionsMode::Bar16Mode(),
             analytics::slice_onsets(samples.len() / 2, ((beats / 4) * 16) as usize),
         );
+
+        self.build_slices();
+    }
+
+    /// builds the slices of each positions mode, the slicer only swaps them
+    fn build_slices(&mut self) {
+        let frames = &self.frames[..];
+        self.slices = self
+            .positions
+            .iter()
+            .map(|(mode, pos)| {
+                let slices = slicer::SliceMap::from_positions(frames, pos);
+                (*mode, Arc::new(slices))
+            })
+            .collect();
     }
 }
 
 /// SampleGen, abstract level struct common to all samples generators.
 /// Used to store common fields, we use Structural composition to `extend` this.
 struct SampleGen {
+    /// Engine sample rate, the smartbuf frames are at this rate.
+    sample_rate: f64,
     /// smartbuf is the main source of samples and metadata.
     /// The gen will directly use underlying frames as a wrapped buffer.
-    smartbuf: SmartBuffer,
+    /// Shared with the SampleLib, never copied in the audio thread.
+    smartbuf: Arc<SmartBuffer>,
     /// playback_rate is the ratio of current tempo over original tempo.
     playback_rate: f64,
     /// playback_mult is a factor of the playback_rate that can be twisted for fun and profit.
@@ -246,6 +423,12 @@ struct SampleGen {
     sync_cursor: u64,
     /// Next frame index to sync to when the Fade-Out/Fade-In is at zero
     sync_next_frame_index: u64,
+    /// Triggered playback, None when looping with the clock
+    trigger: Option<TriggerState>,
+    /// Freeze holding the playback, the clock is ignored
+    freeze: Option<Freeze>,
+    /// The freeze was released, resync to the clock on the next tick
+    freeze_resync: bool,
 }
 
 /// Standard implem mainly for sync
@@ -259,6 +442,11 @@ impl SampleGen {
 
     /// Get the next frame, being sure no click is generated by frame index sync
     fn sync_get_next_frame(&mut self) -> Stereo<f32> {
+        // the freeze loops its window
+        if let Some(freeze) = self.freeze.as_mut() {
+            return freeze.next_frame(&self.smartbuf.frames[..]);
+        }
+
         // grab some fresh frame
         let max_frame = self.loop_get_max_frame();
         let mut next_frame = self.smartbuf.frames[self.frame_index as usize % max_frame];
@@ -276,6 +464,15 @@ impl SampleGen {
             )),
         };
 
+        // one shots fade out before their end
+        if let Some(TriggerState { end: Some(end), .. }) = self.trigger {
+            next_frame = next_frame.scale_amp(gen_utils::fade_out(
+                self.frame_index as i64,
+                NOCLICK_FADE_LENGTH as i64,
+                end as i64,
+            ));
+        }
+
         // check if we must change the frame index now
         if self.sync_cursor == NOCLICK_FADE_LENGTH {
             self.frame_index = self.sync_next_frame_index + NOCLICK_FADE_LENGTH;
@@ -297,6 +494,98 @@ impl SampleGen {
         self.sync_next_frame_index = 0;
     }
 
+    /// Starts a triggered playback from the start of the sample or of a slice.
+    /// Crossfades if already playing, starts right on the frame otherwise.
+    fn trigger_start(&mut self, trigger: Trigger, mode: PositionsMode) {
+        // nothing to play
+        let len = self.smartbuf.frames.len() as u64;
+        if len == 0 {
+            return;
+        }
+
+        // slice bounds, the last slice ends with the sample
+        let bounds = trigger.slice.and_then(|slice| {
+            let positions = self.smartbuf.positions.get(&mode)?;
+            let i = slice % positions.len().max(1);
+            let start = *positions.get(i)? as u64;
+            let end = positions.get(i + 1).map_or(len, |p| *p as u64);
+            Some((start, end))
+        });
+        let (start, end) = bounds.unwrap_or((0, len));
+
+        self.trigger = Some(TriggerState {
+            tick: trigger.tick,
+            start,
+            end: if trigger.one_shot { Some(end) } else { None },
+        });
+
+        if self.playing {
+            self.sync_set_frame_index(start);
+        } else {
+            self.frame_index = start;
+            self.sync_cursor = NOCLICK_FADE_LENGTH * 2;
+            self.playing = true;
+        }
+    }
+
+    /// Ends the triggered playback
+    fn trigger_release(&mut self) {
+        self.trigger = None;
+        self.playing = false;
+        self.sync_reset();
+    }
+
+    /// Holds the playback on a window from the current frame
+    fn freeze_start(&mut self) {
+        if self.freeze.is_some() || self.smartbuf.frames.is_empty() {
+            return;
+        }
+        let start = self.frame_index % self.loop_get_max_frame() as u64;
+        self.freeze = Some(Freeze::new(start, self.sample_rate));
+    }
+
+    /// Plays on from the freeze position, back to the clock on the next tick
+    fn freeze_release(&mut self) {
+        if let Some(freeze) = self.freeze.take() {
+            self.frame_index = freeze.position();
+            // no pending resync
+            self.sync_cursor = NOCLICK_FADE_LENGTH * 2;
+            self.freeze_resync = true;
+        }
+    }
+
+    /// Frozen, the clock is ignored
+    fn is_frozen(&self) -> bool {
+        self.freeze.is_some()
+    }
+
+    /// Clock frames elapsed, since the trigger when triggered
+    fn clock_elapsed_frames(&self, tick: u64) -> u64 {
+        let tick = match &self.trigger {
+            Some(t) => tick.saturating_sub(t.tick),
+            None => tick,
+        };
+        Ticks(tick as i64).samples(self.smartbuf.original_tempo, PPQN, self.sample_rate) as u64
+    }
+
+    /// First frame of the playback, the trigger frame when triggered
+    fn clock_start_frame(&self) -> u64 {
+        self.trigger.map_or(0, |t| t.start)
+    }
+
+    /// The trigger placed the playback on this tick already
+    fn is_trigger_tick(&self, tick: u64) -> bool {
+        self.trigger.map_or(false, |t| t.tick == tick)
+    }
+
+    /// A one shot reached its end
+    fn trigger_ended(&self) -> bool {
+        match self.trigger {
+            Some(TriggerState { end: Some(end), .. }) => self.frame_index >= end,
+            _ => false,
+        }
+    }
+
     /// Get the loop upper bound in samples, according to the loop_div (sub-loop length)
     fn loop_get_max_frame(&self) -> usize {
         // how many beats we want
@@ -308,12 +597,12 @@ impl SampleGen {
         }
 
         // convert to samples, in original tempo ofc
-        Beats(num_beats_divided as i64).samples(self.smartbuf.original_tempo, 44_100.0) as usize
+        Beats(num_beats_divided as i64).samples(self.smartbuf.original_tempo, self.sample_rate) as usize
     }
 
     /// Is this frame a beat frame
     fn is_beat_frame(&self) -> bool {
-        let beat_samples = Beats(1).samples(self.smartbuf.original_tempo, 44_100.0) as u64;
+        let beat_samples = Beats(1).samples(self.smartbuf.original_tempo, self.sample_rate) as u64;
         if self.frame_index % beat_samples == 0 {
             return true;
         }
@@ -326,8 +615,8 @@ impl SampleGen {
 pub trait SampleGenerator {
     /// Processes the next block of samples, write it in referenced frame slice.
     fn next_block(&mut self, block_out: &mut [Stereo<f32>]);
-    /// Loads a SmartBuffer by copying
-    fn load_buffer(&mut self, smartbuf: &SmartBuffer);
+    /// Swaps the SmartBuffer, returns the previous one so it is not freed in the audio thread.
+    fn load_buffer(&mut self, smartbuf: Arc<SmartBuffer>) -> Arc<SmartBuffer>;
     /// Sync is used to synchronise the generator according to the global tempo and the current clock ticks elaspsed.
     /// Many operations can append during a sync internally.
     fn sync(&mut self, global_tempo: u64, tick: u64);
@@ -343,4 +632,104 @@ pub trait SampleGenerator {
     fn set_loop_div(&mut self, loop_div: u64);
     /// Used to pass control message that triggers actions specific to SampleGenerator implementations
     fn push_control_message(&mut self, message: ControlMessage);
+    /// Starts a triggered playback, from the start of the sample or of a slice, still synced to the clock.
+    /// A one shot stops by itself, otherwise it plays until released.
+    fn trigger(&mut self, trigger: Trigger);
+    /// Stops a triggered playback
+    fn release(&mut self);
+    /// Holds the playback on a small looped window from the current position, until unfrozen
+    fn freeze(&mut self);
+    /// Releases the freeze, the playback rejoins the clock
+    fn unfreeze(&mut self);
+    /// Gives back a replaced slicer pattern, so it is not freed in the audio thread.
+    fn replaced_pattern(&mut self) -> Option<Arc<slicer::SlicePattern>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trigger_one_shot_slice() {
+        let mut smartbuf = SmartBuffer::new_empty(1000.0);
+        smartbuf.frames = vec![[1.0, 1.0]; 400];
+        smartbuf
+            .positions
+            .insert(PositionsMode::Bar16Mode(), vec![0, 100, 200, 300]);
+        let mut gen = SampleGen {
+            sample_rate: 1000.0,
+            playback_rate: 1.0,
+            frame_index: 0,
+            playback_mult: 0,
+            loop_div: 1,
+            next_loop_div: 1,
+            loop_offset: 0,
+            playing: false,
+            smartbuf: Arc::new(smartbuf),
+            sync_cursor: 0,
+            sync_next_frame_index: 0,
+            trigger: None,
+            freeze: None,
+            freeze_resync: false,
+        };
+
+        // the last slice ends with the sample
+        let trigger = Trigger {
+            tick: 24,
+            slice: Some(3),
+            one_shot: true,
+        };
+        gen.trigger_start(trigger, PositionsMode::Bar16Mode());
+        assert!(gen.playing);
+        assert_eq!(gen.frame_index, 300);
+        assert_eq!(gen.clock_start_frame(), 300);
+        assert!(gen.is_trigger_tick(24));
+        assert_eq!(gen.clock_elapsed_frames(24), 0);
+
+        // stops on its end
+        for _ in 0..99 {
+            gen.sync_get_next_frame();
+        }
+        assert!(!gen.trigger_ended());
+        gen.sync_get_next_frame();
+        assert!(gen.trigger_ended());
+
+        gen.trigger_release();
+        assert!(!gen.playing);
+        assert!(!gen.trigger_ended());
+    }
+
+    #[test]
+    fn test_freeze_window() {
+        let frames: Vec<Stereo<f32>> = (0..20000).map(|i| [i as f32, i as f32]).collect();
+        let mut freeze = Freeze::new(100, 44_100.0);
+        let (len, fade) = (freeze.len, freeze.fade);
+
+        // plays on from the frozen frame, then loops
+        for i in 0..len {
+            assert_eq!(freeze.next_frame(&frames)[0], (100 + i) as f32);
+        }
+        let start = 100 + len;
+        assert_eq!(freeze.next_frame(&frames)[0], start as f32);
+
+        // the window start fades in over its tail
+        for _ in 1..fade {
+            freeze.next_frame(&frames);
+        }
+        assert_eq!(freeze.next_frame(&frames)[0], (100 + fade) as f32);
+        assert_eq!(freeze.position(), 101 + fade);
+    }
+
+    #[test]
+    fn test_freeze_window_sample_rate() {
+        // same hold time at any rate
+        let slow = Freeze::new(0, 44_100.0);
+        let fast = Freeze::new(0, 96_000.0);
+        let slow_ms = slow.len as f64 * 1000.0 / 44_100.0;
+        let fast_ms = fast.len as f64 * 1000.0 / 96_000.0;
+        assert!((slow_ms - fast_ms).abs() < 0.1);
+        assert!(fast.fade > slow.fade);
+    }
 }
@@ -1,10 +1,11 @@
 use aubio_port_rs::pvoc::Pvoc;
 use sample::frame::Stereo;
 use sample::Frame;
-use time_calc::{Beats, Ticks};
+use time_calc::Beats;
+use std::sync::Arc;
 use crate::control::ControlMessage;
 
-use super::{SampleGen, SampleGenerator, SmartBuffer, PPQN};
+use super::{PositionsMode, SampleGen, SampleGenerator, SmartBuffer, Trigger};
 
 ///
 const PI: f64 = std::f64::consts::PI;
@@ -173,13 +174,14 @@ pub struct PVOCGen {
 /// Specific sub SampleGen implementation
 impl PVOCGen {
     /// Inits and return a new SlicerGen sample generator
-    pub fn new() -> Self {
+    pub fn new(sample_rate: f64) -> Self {
         // pvoc 1 vars
         let pvoc_1_window_size = 512;
         let pvoc_1_hopsize = 32;
         let pvoc_1_analyse_size = pvoc_1_window_size / 2 + 1;
         PVOCGen {
             sample_gen: SampleGen {
+                sample_rate,
                 playback_rate: 1.0,
                 frame_index: 0,
                 playback_mult: 0,
@@ -187,9 +189,12 @@ impl PVOCGen {
                 next_loop_div: 1,
                 loop_offset: 0,
                 playing: false,
-                smartbuf: SmartBuffer::new_empty(),
+                smartbuf: Arc::new(SmartBuffer::new_empty(sample_rate)),
                 sync_cursor: 0,
                 sync_next_frame_index: 0,
+                trigger: None,
+                freeze: None,
+                freeze_resync: false,
             },
             pvoc_1: PVOCUnit {
                 hop_size: pvoc_1_hopsize,
@@ -230,6 +235,16 @@ impl SampleGenerator for PVOCGen {
             return;
         }
 
+        // a one shot is over, drops what is left in the pvoc
+        if self.sample_gen.trigger_ended() {
+            self.sample_gen.playing = false;
+            self.pvoc_1.buff_pvoc_out.clear();
+            for frame_out in block_out.iter_mut() {
+                *frame_out = Stereo::<f32>::equilibrium();
+            }
+            return;
+        }
+
         // hop loop
         // @TODO only working for one pvoc unit as now
         loop {
@@ -266,28 +281,39 @@ impl SampleGenerator for PVOCGen {
         }
     }
 
-    /// Loads a SmartBuffer, moving it
-    fn load_buffer(&mut self, smartbuf: &SmartBuffer) {
-        //
-        self.sample_gen.smartbuf.copy_from(smartbuf);
+    /// Swaps the SmartBuffer
+    fn load_buffer(&mut self, smartbuf: Arc<SmartBuffer>) -> Arc<SmartBuffer> {
+        std::mem::replace(&mut self.sample_gen.smartbuf, smartbuf)
     }
 
     /// Sync the pvoc according to global sync values
     fn sync(&mut self, global_tempo: u64, tick: u64) {
         // calculate elapsed clock frames according to the original tempo
+        // relative to the trigger when triggered
         let original_tempo = self.sample_gen.smartbuf.original_tempo;
-        let clock_frames = Ticks(tick as i64).samples(original_tempo, PPQN, 44_100.0) as u64;
+        let sample_rate = self.sample_gen.sample_rate;
+        let elapsed_frames = self.sample_gen.clock_elapsed_frames(tick);
+        let clock_frames = self.sample_gen.clock_start_frame() + elapsed_frames;
 
         // we want to resync for each beat
         let beat_samples =
-            Beats(1).samples(self.sample_gen.smartbuf.original_tempo, 44_100.0) as u64;
-        let is_beat = clock_frames % beat_samples == 0;
+            Beats(1).samples(self.sample_gen.smartbuf.original_tempo, sample_rate) as u64;
+        let is_beat = elapsed_frames % beat_samples == 0;
 
         // calculates the new playback rate
         let new_rate = global_tempo as f64 / original_tempo;
 
+        // the trigger just placed the playback, the freeze ignores the clock
+        if self.sample_gen.is_trigger_tick(tick) || self.sample_gen.is_frozen() {
+            self.sample_gen.playback_rate = new_rate;
+            return;
+        }
+
+        // back from a freeze
+        let resync = std::mem::replace(&mut self.sample_gen.freeze_resync, false);
+
         // has the tempo changed ? update accordingly
-        if self.sample_gen.playback_rate != new_rate || is_beat {
+        if self.sample_gen.playback_rate != new_rate || is_beat || resync {
             // simple update
             self.sample_gen.playback_rate = new_rate;
             // set the frameindex relative to the mixer ticks
@@ -308,6 +334,7 @@ impl SampleGenerator for PVOCGen {
 
     /// sets stop
     fn stop(&mut self) {
+        self.sample_gen.freeze = None;
         self.reset();
         self.sample_gen.playing = false;
     }
@@ -333,6 +360,33 @@ impl SampleGenerator for PVOCGen {
     fn push_control_message(&mut self, _message: ControlMessage) {
         // do nothing for now
     }
+
+    /// starts a triggered playback, slices are sixteenths of a bar
+    fn trigger(&mut self, trigger: Trigger) {
+        // no leftover from the previous playback
+        if !self.sample_gen.playing {
+            self.pvoc_1.buff_pvoc_out.clear();
+            self.pvoc_1.reset();
+        }
+        self.sample_gen
+            .trigger_start(trigger, PositionsMode::Bar16Mode());
+    }
+
+    /// stops a triggered playback
+    fn release(&mut self) {
+        self.sample_gen.trigger_release();
+        self.pvoc_1.reset();
+    }
+
+    /// loops a window at the play position, stretched as the playback
+    fn freeze(&mut self) {
+        self.sample_gen.freeze_start();
+    }
+
+    /// plays on, resynced on the next tick
+    fn unfreeze(&mut self) {
+        self.sample_gen.freeze_release();
+    }
 }
 
 /// Implement `Iterator` for `RePitchGen`.
@@ -3,11 +3,12 @@ extern crate time_calc;
 
 use self::sample::frame::Stereo;
 use self::sample::{Frame, Sample};
-use self::time_calc::{Beats, Ticks};
+use self::time_calc::Beats;
+use std::sync::Arc;
 
 use crate::control::ControlMessage;
 
-use super::{SampleGen, SampleGenerator, SmartBuffer, PPQN};
+use super::{PositionsMode, SampleGen, SampleGenerator, SmartBuffer, Trigger};
 
 
 /// LinInterp is a struct that helps interpolation operations.
@@ -49,9 +50,10 @@ pub struct RePitchGen {
 /// Specific sub SampleGen implementation
 impl RePitchGen {
     /// Inits and return a new RePitchGen sample generator
-    pub fn new() -> Self {
+    pub fn new(sample_rate: f64) -> Self {
         RePitchGen {
             sample_gen: SampleGen {
+                sample_rate,
                 playback_rate: 1.0,
                 frame_index: 0,
                 playback_mult: 0,
@@ -59,9 +61,12 @@ impl RePitchGen {
                 next_loop_div: 1,
                 loop_offset: 0,
                 playing: false,
-                smartbuf: SmartBuffer::new_empty(),
+                smartbuf: Arc::new(SmartBuffer::new_empty(sample_rate)),
                 sync_cursor: 0,
                 sync_next_frame_index: 0,
+                trigger: None,
+                freeze: None,
+                freeze_resync: false,
             },
             interpolation: LinInterp {
                 interp_val: 0.0,
@@ -88,33 +93,51 @@ impl SampleGenerator for RePitchGen {
 
         // playing, simply use the iterator
         for frame_out in block_out.iter_mut() {
+            // a one shot is over
+            if self.sample_gen.trigger_ended() {
+                self.sample_gen.playing = false;
+                *frame_out = Stereo::<f32>::equilibrium();
+                continue;
+            }
             // can safely be unwrapped because always return something
             *frame_out = self.next().unwrap().scale_amp(1.44); // factor that balance with other sample gen types
         }
     }
 
-    /// Loads a SmartBuffer, copy it
-    fn load_buffer(&mut self, smartbuf: &SmartBuffer) {
-        self.sample_gen.smartbuf.copy_from(smartbuf);
+    /// Swaps the SmartBuffer
+    fn load_buffer(&mut self, smartbuf: Arc<SmartBuffer>) -> Arc<SmartBuffer> {
+        std::mem::replace(&mut self.sample_gen.smartbuf, smartbuf)
     }
 
     /// Sync the sample buffer according to global sync values
     fn sync(&mut self, global_tempo: u64, tick: u64) {
         // calculate elapsed clock frames according to the original tempo
+        // relative to the trigger when triggered
         let original_tempo = self.sample_gen.smartbuf.original_tempo;
-        let clock_frames = Ticks(tick as i64).samples(original_tempo, PPQN, 44_100.0) as u64;
+        let sample_rate = self.sample_gen.sample_rate;
+        let elapsed_frames = self.sample_gen.clock_elapsed_frames(tick);
+        let clock_frames = self.sample_gen.clock_start_frame() + elapsed_frames;
 
         // we want to resync for each beat
         let beat_samples =
-            Beats(1).samples(self.sample_gen.smartbuf.original_tempo, 44_100.0) as u64;
-        let is_beat = clock_frames % beat_samples == 0;
+            Beats(1).samples(self.sample_gen.smartbuf.original_tempo, sample_rate) as u64;
+        let is_beat = elapsed_frames % beat_samples == 0;
 
         // calculates the new playback rate
         let new_rate = global_tempo as f64 / original_tempo;
 
+        // the trigger just placed the playback, the freeze ignores the clock
+        if self.sample_gen.is_trigger_tick(tick) || self.sample_gen.is_frozen() {
+            self.sample_gen.playback_rate = new_rate;
+            return;
+        }
+
+        // back from a freeze
+        let resync = std::mem::replace(&mut self.sample_gen.freeze_resync, false);
+
         // println!("gtempo: {} tick: {} newrate: {}", global_tempo, tick, new_rate);
         // has the tempo changed ? update accordingly
-        if self.sample_gen.playback_rate != new_rate || is_beat {
+        if self.sample_gen.playback_rate != new_rate || is_beat || resync {
             // simple update
             self.sample_gen.playback_rate = new_rate;
             // sync to the clock estimated frame index
@@ -133,6 +156,7 @@ impl SampleGenerator for RePitchGen {
 
     /// sets stop
     fn stop(&mut self) {
+        self.sample_gen.freeze = None;
         self.reset();
         self.sample_gen.playing = false;
     }
@@ -158,6 +182,33 @@ impl SampleGenerator for RePitchGen {
     fn push_control_message(&mut self, _message: ControlMessage) {
         // do nothing for now
     }
+
+    /// starts a triggered playback, slices are sixteenths of a bar
+    fn trigger(&mut self, trigger: Trigger) {
+        // no leftover from the previous playback
+        if !self.sample_gen.playing {
+            self.interpolation.interp_val = 0.0;
+            self.interpolation.left = Stereo::<f32>::equilibrium();
+            self.interpolation.right = Stereo::<f32>::equilibrium();
+        }
+        self.sample_gen
+            .trigger_start(trigger, PositionsMode::Bar16Mode());
+    }
+
+    /// stops a triggered playback
+    fn release(&mut self) {
+        self.sample_gen.trigger_release();
+    }
+
+    /// loops a window at the play position, repitched as the playback
+    fn freeze(&mut self) {
+        self.sample_gen.freeze_start();
+    }
+
+    /// plays on, resynced on the next tick
+    fn unfreeze(&mut self) {
+        self.sample_gen.freeze_release();
+    }
 }
 
 /// Implement `Iterator` for `RePitchGen`.
@@ -4,26 +4,46 @@ use heapless::consts::U512;
 type CrossfadeLen = U512;
 
 use rand::Rng;
+use serde::{Deserialize, Deserializer, Serializer};
 use sample::frame::Stereo;
 use sample::Frame;
 use time_calc::{TimeSig, Ticks, Bars};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::f64;
+use std::sync::Arc;
 
+use crate::config::SliceNotes;
 use crate::control::{ControlMessage, SlicerMessage};
-use super::{SampleGen, SampleGenerator, SmartBuffer, PPQN};
+use super::gen_utils::SeededRng;
+use super::{Freeze, SampleGen, SampleGenerator, SmartBuffer, Trigger, TriggerState, PPQN};
 
 
 /// Used to define slicer fadeins fadeouts in samples
 const SLICE_FADE_IN: usize = 256;
 const SLICE_FADE_OUT: usize = 512;
 
+/// Slices played at once by the notes, the oldest is stolen
+const MAX_SLICE_VOICES: usize = 8;
+/// Replaced patterns waiting for the loader, past that they are dropped in place
+const MAX_REPLACED_PATTERNS: usize = 8;
+/// Fade out of a choked slice, in samples
+const CHOKE_FADE: usize = 128;
+/// Attack of a slice measured for its onset strength, in samples
+const ATTACK_LEN: usize = 1024;
+
+/// Most slices of a buffer, the slice maps are allocated for it and never grow on the audio thread
+const MAX_SLICES: usize = 256;
+
+/// Shortest beat repeat interval in samples, the rolls stop there
+const MIN_REPEAT_LEN: f64 = 1024.0;
+
 
 
 /// A Slice struct, represnte a slice of audio in the buffer
 /// Doesn't store any audio data, but start and end index
 /// should be copied
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 struct Slice {
     /// slice id
     id: usize,
@@ -35,6 +55,30 @@ struct Slice {
     cursor: usize,
     // reverse
     reverse: bool,
+    /// stutter, the slice is retriggered this many times within its length
+    repeats: usize,
+    /// gain of the slice
+    gain: f32,
+    /// repitch ratio, the slice keeps its length
+    rate: f64,
+    /// onset strength, peak of the attack relative to the loudest slice
+    strength: f32,
+}
+
+impl Default for Slice {
+    fn default() -> Self {
+        Slice {
+            id: 0,
+            start: 0,
+            end: 0,
+            cursor: 0,
+            reverse: false,
+            repeats: 1,
+            gain: 1.0,
+            rate: 1.0,
+            strength: 0.0,
+        }
+    }
 }
 
 impl Slice {
@@ -44,43 +88,56 @@ impl Slice {
         // init with default
         let mut next_frame = Stereo::<f32>::equilibrium();
 
+        // length of a repeat, the whole slice when not stuttered
+        let repeat_len = (self.len() / self.repeats.max(1)).max(1);
+
         // grab the frame
         if !self.is_consumed() {
-            // get the frame index cursor
-            let frame_index = self.cursor + self.start;
+            // position in the repeat, faster or slower when repitched, backward when reversed
+            let read = ((self.cursor % repeat_len) as f64 * self.rate) as usize;
+            let offset = if self.reverse {
+                repeat_len.saturating_sub(read + 1)
+            } else {
+                read
+            };
+
+            // get the frame index cursor, repitched up the repeat ends early
+            let frame_index = self.start + offset;
 
             // safely grab a new frame
             let new_frame = frames.get(frame_index);
             match new_frame {
-                None => {
-                    // out of bounds, should never happend
-                    next_frame = Stereo::<f32>::equilibrium();
-                }
-                Some(f) => next_frame = *f,
+                Some(f) if read < repeat_len => next_frame = *f,
+                // out of bounds, should never happend
+                _ => next_frame = Stereo::<f32>::equilibrium(),
             }
         }
 
         // increment cursor
         self.cursor += 1;
 
+        // envelope cursor, each repeat has its own envelope
+        let cursor = (self.cursor - 1) % repeat_len + 1;
+
         // ajust len
         let new_len = match playback_rate {
-            playback_rate if playback_rate >= 1.0 => (self.len() as f64 / playback_rate) as i64,
-            _ => self.len() as i64,
+            playback_rate if playback_rate >= 1.0 => repeat_len as f64 / playback_rate,
+            _ => repeat_len as f64,
         };
+        let new_len = (new_len / self.rate.max(1.0)) as i64;
 
         // return enveloped, ajusted
         next_frame
             .scale_amp(super::gen_utils::fade_in(
-                self.cursor as i64,
+                cursor as i64,
                 (SLICE_FADE_IN as f64 * playback_rate) as i64,
             ))
             .scale_amp(super::gen_utils::fade_out(
-                self.cursor as i64,
+                cursor as i64,
                 (SLICE_FADE_OUT as f64 * playback_rate) as i64, // @TODO this should be param
                 new_len,                                        // adjust from playback rate
             ))
-            .scale_amp(1.45)
+            .scale_amp(1.45 * self.gain)
     }
 
     /// the cursor is consumed
@@ -103,6 +160,36 @@ impl Slice {
     }
 }
 
+/// A SliceVoice plays a slice triggered by a note, to its end or until choked
+#[derive(Debug, Copy, Clone)]
+struct SliceVoice {
+    /// the slice, from its start
+    slice: Slice,
+    /// velocity gain
+    gain: f32,
+    /// choke group of the slice
+    choke: Option<usize>,
+    /// remaining fade out samples once choked
+    choked: Option<usize>,
+}
+
+impl SliceVoice {
+    /// get the next frame of the slice, faded out if choked
+    fn next_frame(&mut self, playback_rate: f64, frames: &[Stereo<f32>]) -> Stereo<f32> {
+        let mut gain = self.gain;
+        if let Some(remaining) = self.choked.as_mut() {
+            gain *= *remaining as f32 / CHOKE_FADE as f32;
+            *remaining = remaining.saturating_sub(1);
+        }
+        self.slice.next_frame(playback_rate, frames).scale_amp(gain)
+    }
+
+    /// the slice is over or faded out
+    fn is_done(&self) -> bool {
+        self.slice.is_consumed() || self.choked == Some(0)
+    }
+}
+
 /// Slice Sequence transformation types
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum TransformType {
@@ -118,6 +205,181 @@ pub enum TransformType {
         // the slice to repeat forever
         slice_index: usize,
     },
+    /// Reverses the nth playing slice, or plays it forward again
+    Reverse { slice_index: usize },
+    /// Reverses each playing slice with a probability, 0 to 1
+    RandReverse { amount: f32 },
+    /// Retriggers the nth playing slice repeats times within its own length
+    Stutter { slice_index: usize, repeats: usize },
+    /// Sets the gain of the nth playing slice
+    SliceGain { slice_index: usize, gain: f32 },
+    /// Repitches the nth playing slice in semitones, it keeps its length
+    SliceRepitch { slice_index: usize, semitones: f32 },
+    /// Generative sequencing, each step picks a slice again on each loop.
+    /// Variation moves away from the original slices towards the neighbours and slices hitting as hard,
+    /// density is the probability a step plays, the seed makes it reproducible
+    Generate {
+        density: f32,
+        variation: f32,
+        seed: u64,
+    },
+}
+
+/// A step pattern of slices, played in place of the sample order and locked to the clock.
+/// Steps are 1/div of a bar, patterns of any length loop on their own for polymetric effects
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlicePattern {
+    /// Slice of each step in the sample order, None is a rest, written "0 0 3 2 - 5 5 7"
+    #[serde(with = "pattern_steps")]
+    pub steps: Vec<Option<usize>>,
+    /// Steps per bar
+    #[serde(default = "default_pattern_div")]
+    pub div: u64,
+}
+
+impl SlicePattern {
+    /// Parses the steps of a pattern, an empty one goes back to the sample order
+    pub fn parse(text: &str, div: u64) -> Result<Self, &'static str> {
+        Ok(SlicePattern {
+            steps: parse_steps(text)?,
+            div,
+        })
+    }
+}
+
+/// Parses pattern steps, slice indexes or "-" for a rest
+fn parse_steps(text: &str) -> Result<Vec<Option<usize>>, &'static str> {
+    text.split_whitespace()
+        .map(|step| match step {
+            "-" => Ok(None),
+            n => n.parse().map(Some).map_err(|_| "slicer: bad pattern step"),
+        })
+        .collect()
+}
+
+/// Sixteenth steps
+fn default_pattern_div() -> u64 {
+    16
+}
+
+/// Pattern steps as text, in the config and the messages
+mod pattern_steps {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(steps: &[Option<usize>], s: S) -> Result<S::Ok, S::Error> {
+        let text: Vec<String> = steps
+            .iter()
+            .map(|step| match step {
+                Some(i) => i.to_string(),
+                None => String::from("-"),
+            })
+            .collect();
+        s.serialize_str(&text.join(" "))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Option<usize>>, D::Error> {
+        let text = String::deserialize(d)?;
+        parse_steps(&text).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Serializes a shared pattern as the pattern itself
+pub mod shared_pattern {
+    use super::*;
+    use serde::Serialize;
+
+    pub fn serialize<S: Serializer>(pattern: &Arc<SlicePattern>, s: S) -> Result<S::Ok, S::Error> {
+        pattern.as_ref().serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Arc<SlicePattern>, D::Error> {
+        SlicePattern::deserialize(d).map(Arc::new)
+    }
+}
+
+/// A beat repeat, captures the playing slice and repeats it until released
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BeatRepeat {
+    /// Repeat interval, 1/quant of a bar, the repeat also starts on it
+    pub quant: u64,
+    /// Part of the interval that plays, 0 to 1
+    #[serde(default = "default_one")]
+    pub gate: f32,
+    /// Interval ratio from a repeat to the next, below 1 rolls faster
+    #[serde(default = "default_one")]
+    pub roll: f32,
+    /// Pitch change per repeat in semitones
+    #[serde(default)]
+    pub pitch: f32,
+    /// Gain ratio from a repeat to the next, 0 to 1
+    #[serde(default = "default_one")]
+    pub decay: f32,
+    /// Released after that many bars, held until released if 0
+    #[serde(default)]
+    pub bars: u64,
+}
+
+impl BeatRepeat {
+    /// A plain repeat on 1/quant of a bar
+    pub fn new(quant: u64) -> Self {
+        BeatRepeat {
+            quant,
+            gate: 1.0,
+            roll: 1.0,
+            pitch: 0.0,
+            decay: 1.0,
+            bars: 0,
+        }
+    }
+}
+
+fn default_one() -> f32 {
+    1.0
+}
+
+/// Beat repeat state
+#[derive(Debug, Copy, Clone)]
+struct RepeatState {
+    params: BeatRepeat,
+    /// the captured slice
+    slice: Slice,
+    /// tick the repeat started on
+    tick: u64,
+    /// repeats played
+    count: u32,
+    /// current interval in samples
+    interval: f64,
+    /// start of the next repeat in samples, from the repeat start
+    next_start: f64,
+}
+
+/// Generative sequencing state
+#[derive(Debug, Copy, Clone)]
+struct Generative {
+    /// probability a step plays
+    density: f32,
+    /// 0 keeps the original slices, 1 picks by neighbours and strength only
+    variation: f32,
+    /// seeded on each Generate transform
+    rng: SeededRng,
+}
+
+/// Peak of the attack of a slice
+fn attack_peak(frames: &[Stereo<f32>], start: usize, end: usize) -> f32 {
+    let end = end.min(start + ATTACK_LEN).min(frames.len());
+    frames[start.min(end)..end]
+        .iter()
+        .fold(0.0f32, |peak, f| peak.max(f[0].abs()).max(f[1].abs()))
+}
+
+/// Weight of the slice j for the step i, favours the step own slice, its neighbours
+/// and the slices with an onset strength close to it
+fn step_weight(i: usize, i_strength: f32, j: usize, j_strength: f32, variation: f32) -> f32 {
+    let distance = (i as isize - j as isize).abs();
+    let position = if distance == 0 { 1.0 } else { 0.0 };
+    let neighbour = if distance == 1 { 0.5 } else { 0.0 };
+    let similar = 1.0 - (i_strength - j_strength).abs().min(1.0);
+    (1.0 - variation) * position + variation * (0.01 + neighbour + similar * similar)
 }
 
 /// a SliceMap is useful encapsulation to perform transform on slice with hashmap and sorted keys index
@@ -125,7 +387,7 @@ pub enum TransformType {
 /// no support for remove as we trash all everytime
 /// @TODO add a shuffle on postions
 #[derive(Debug, Clone)]
-struct SliceMap {
+pub(super) struct SliceMap {
     /// Hashmap of all slices, unordered by the datastruct
     unord_slices: HashMap<usize, Slice>,
     /// keeps an ordered copy of the keys
@@ -138,12 +400,50 @@ impl SliceMap {
     /// new with allocation !
     fn new() -> Self {
         SliceMap {
-            unord_slices: HashMap::with_capacity(128),
-            ord_keys: Vec::with_capacity(128),
-            shifted_keys: Vec::with_capacity(128),
+            unord_slices: HashMap::with_capacity(MAX_SLICES),
+            ord_keys: Vec::with_capacity(MAX_SLICES),
+            shifted_keys: Vec::with_capacity(MAX_SLICES),
         }
     }
 
+    /// Builds the slices of a buffer from its positions, done by the loader.
+    /// Past MAX_SLICES the last slice runs to the last position
+    pub(super) fn from_positions(frames: &[Stereo<f32>], positions: &[usize]) -> Self {
+        let mut map = SliceMap::new();
+        let count = positions.len().saturating_sub(1).min(MAX_SLICES);
+        for idx in 0..count {
+            let start = positions[idx];
+            let end = if idx + 1 == count {
+                positions[positions.len() - 1]
+            } else {
+                positions[idx + 1]
+            };
+            map.insert_copy(
+                start,
+                Slice {
+                    id: idx,
+                    start,
+                    end,
+                    cursor: 0,
+                    strength: attack_peak(frames, start, end),
+                    ..Default::default()
+                },
+            );
+        }
+
+        // onset strength relative to the loudest slice
+        let max_strength = map
+            .unord_slices
+            .values()
+            .fold(0.0, |max: f32, s| max.max(s.strength));
+        if max_strength > 0.0 {
+            for slice in map.unord_slices.values_mut() {
+                slice.strength /= max_strength;
+            }
+        }
+        map
+    }
+
     // clear keeps allocated memory
     fn clear(&mut self) {
         self.unord_slices.clear();
@@ -157,8 +457,8 @@ impl SliceMap {
         self.unord_slices.insert(k, v);
         // insert in keys
         self.ord_keys.push(k);
-        // resort
-        self.ord_keys[..].sort();
+        // resort, in place
+        self.ord_keys[..].sort_unstable();
         assert_eq!(self.unord_slices.len(), self.ord_keys.len());
     }
 
@@ -228,7 +528,7 @@ impl SliceMap {
 
         // used to assign new id
         let mut ct: usize = 0;
-        for x in (0..max).step_by(quant) {
+        for x in (0..max).step_by(quant).take(MAX_SLICES) {
             // pass a new id
             to_repeat.id = ct;
             self.insert_copy(x, to_repeat);
@@ -239,6 +539,78 @@ impl SliceMap {
     fn len(&self) -> usize {
         self.ord_keys.len()
     }
+
+    // cumulative weights of the slices for each step, a row per step
+    // built once per Generate or buffer, within the capacity of the table
+    fn fill_weights(&self, variation: f32, weights: &mut Vec<f32>) {
+        weights.clear();
+        let keys = self.ord_keys();
+        for (i, ki) in keys.iter().enumerate() {
+            let i_strength = self.get_by_ref(ki).expect("key exists").strength;
+            let mut total = 0.0;
+            for (j, kj) in keys.iter().enumerate() {
+                let j_strength = self.get_by_ref(kj).expect("key exists").strength;
+                total += step_weight(i, i_strength, j, j_strength, variation);
+                weights.push(total);
+            }
+        }
+    }
+
+    // picks the slice of each step from the original ones, keeps the steps and their lengths
+    // both maps must have the same keys, the weights are filled from the original ones
+    fn generate(&mut self, orig: &Self, gen: &mut Generative, weights: &[f32]) {
+        let keys = orig.ord_keys();
+        let n = keys.len();
+        if weights.len() != n * n {
+            return;
+        }
+        for (i, k) in keys.iter().enumerate() {
+            let step = orig.get_by_copy(k).expect("key exists");
+
+            // weighted pick, the first slice its cumulative weight above the draw
+            let row = &weights[i * n..(i + 1) * n];
+            let r = gen.rng.next_f32() * row[n - 1];
+            let j = match row.binary_search_by(|w| {
+                if *w <= r {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }) {
+                Ok(j) | Err(j) => j,
+            };
+            let mut pick = keys
+                .get(j)
+                .and_then(|kj| orig.get_by_copy(kj))
+                .unwrap_or(step);
+
+            // fix length, silent if the step does not play
+            pick.end = pick.start + step.len();
+            if gen.rng.next_f32() >= gen.density {
+                pick.gain = 0.0;
+            }
+
+            if let Some(slice) = self.unord_slices.get_mut(k) {
+                *slice = pick;
+            }
+        }
+    }
+
+    // get the nth slice in order BY REF, mutable
+    fn get_nth_mut(&mut self, n: usize) -> Option<&mut Slice> {
+        let k = *self.ord_keys.get(n)?;
+        self.unord_slices.get_mut(&k)
+    }
+
+    // reverse each slice with a probability
+    fn rand_reverse(&mut self, amount: f32) {
+        let mut rng = rand::thread_rng();
+        for slice in self.unord_slices.values_mut() {
+            if rng.gen::<f32>() < amount {
+                slice.reverse = !slice.reverse;
+            }
+        }
+    }
 }
 
 /// A Slice Sequencer
@@ -251,15 +623,17 @@ struct SliceSeq {
     ticks: u64,
     /// Global current tempo from the mixer clock
     global_tempo: u64,
+    /// Engine sample rate
+    sample_rate: f64,
     /// count elapsed frames between each clock tick to have a more precise clock
     /// takes care of the playback rate
     inter_tick_frames: f64,
-    /// Holds a local copy of the gen smart buffer, so it can change without clicks
-    local_buffer: Option<SmartBuffer>,
+    /// Holds a reference to the gen smart buffer, so it can change without clicks
+    local_buffer: Option<Arc<SmartBuffer>>,
     /// Positions mode define which kind of positions to use in the slicer
     positions_mode: super::PositionsMode,
     /// Slices in orginal sample gen buffer order
-    slices_orig: SliceMap,
+    slices_orig: Arc<SliceMap>,
     /// Temp Slices used for applying transforms
     slices_temp: SliceMap,
     /// Currently playing Slices
@@ -269,8 +643,30 @@ struct SliceSeq {
     curr_slice_tup: (usize, Slice),
     /// pending next transfrom
     next_transform: Option<TransformType>,
+    /// generative sequencing, the slices are picked again on each loop
+    generative: Option<Generative>,
+    /// cumulative pick weights of the generative sequencing, allocated for MAX_SLICES steps
+    gen_weights: Vec<f32>,
+    /// step pattern played in place of the sample order
+    pattern: Option<Arc<SlicePattern>>,
+    /// pattern switched on the next bar, None goes back to the sample order
+    pending_pattern: Option<Option<Arc<SlicePattern>>>,
+    /// replaced patterns, given back to the loader so they are not freed here
+    replaced_patterns: Vec<Arc<SlicePattern>>,
+    /// tick the pattern started on
+    pattern_tick: u64,
+    /// step of the pattern playing, counted from its start
+    pattern_step: u64,
+    /// beat repeat playing in place of the slices
+    repeat: Option<RepeatState>,
+    /// beat repeat starting on its next quantization
+    pending_repeat: Option<BeatRepeat>,
     /// crossfade buffer
     crossfade_buffer: heapless::spsc::Queue<Stereo<f32>, CrossfadeLen>,
+    /// Triggered playback, the clock starts from the trigger
+    trigger: Option<TriggerState>,
+    /// Freeze holding the playing slice, the sequence goes on underneath
+    freeze: Option<Freeze>,
 }
 
 impl SliceSeq {
@@ -286,19 +682,242 @@ impl SliceSeq {
         self.global_tempo = global_tempo;
         // reset elapsed frames
         self.inter_tick_frames = 0f64;
+
+        // the trigger starts now, jump to its slice
+        if self.trigger.map_or(false, |t| t.tick == ticks) && self.local_buffer.is_some() {
+            self.adjust_current_slice();
+        }
+
+        // patterns switch on the bars
+        if ticks % (PPQN as u64 * 4) == 0 {
+            if let Some(pattern) = self.pending_pattern.take() {
+                self.fill_crossfade_buffer();
+                let old = std::mem::replace(&mut self.pattern, pattern);
+                self.retire_pattern(old);
+                self.pattern_tick = ticks;
+                self.adjust_current_slice();
+            }
+        }
+
+        // beat repeats start on their quantization, released after their bars
+        if let Some(params) = self.pending_repeat {
+            let quant_ticks = (PPQN as u64 * 4 / params.quant.max(1)).max(1);
+            if ticks % quant_ticks == 0 {
+                self.pending_repeat = None;
+                self.start_repeat(params, ticks);
+            }
+        }
+        let release = self.repeat.map_or(false, |r| {
+            r.params.bars > 0 && ticks >= r.tick + r.params.bars * PPQN as u64 * 4
+        });
+        if release {
+            self.release_repeat();
+        }
+    }
+
+    /// Switches to a pattern on the next bar, an empty one goes back to the sample order
+    fn set_pending_pattern(&mut self, pattern: Option<Arc<SlicePattern>>) {
+        let pattern = match pattern {
+            Some(p) if p.steps.is_empty() => {
+                self.retire_pattern(Some(p));
+                None
+            }
+            p => p,
+        };
+        if let Some(old) = self.pending_pattern.replace(pattern) {
+            self.retire_pattern(old);
+        }
+    }
+
+    /// Keeps a replaced pattern for the loader.
+    /// If too many wait it is dropped here, only case where the audio thread may free a pattern.
+    fn retire_pattern(&mut self, pattern: Option<Arc<SlicePattern>>) {
+        if let Some(p) = pattern {
+            if self.replaced_patterns.len() < self.replaced_patterns.capacity() {
+                self.replaced_patterns.push(p);
+            }
+        }
+    }
+
+    /// Captures the playing slice and repeats it from its start
+    fn start_repeat(&mut self, params: BeatRepeat, ticks: u64) {
+        let lb = match self.local_buffer.as_ref() {
+            Some(lb) => lb,
+            None => return,
+        };
+        let bar = Bars(1).samples(
+            lb.original_tempo,
+            TimeSig { top: 4, bottom: 4 },
+            self.sample_rate,
+        );
+        // the slice playing from the clock
+        self.fill_crossfade_buffer();
+        self.adjust_current_slice();
+        let mut slice = self.curr_slice_tup.1;
+        slice.cursor = 0;
+        slice.repeats = 1;
+        // a rest repeats silence
+        if slice.len() == 0 {
+            slice.gain = 0.0;
+        }
+        self.repeat = Some(RepeatState {
+            params,
+            slice,
+            tick: ticks,
+            count: 0,
+            interval: (bar as f64 / params.quant.max(1) as f64).max(MIN_REPEAT_LEN),
+            next_start: 0.0,
+        });
+        self.update_repeat_slice();
+    }
+
+    /// Stops the beat repeat, back to the slices from the clock
+    fn release_repeat(&mut self) {
+        self.pending_repeat = None;
+        if self.repeat.take().is_some() && self.local_buffer.is_some() {
+            self.fill_crossfade_buffer();
+            self.adjust_current_slice();
+        }
+    }
+
+    /// Starts the next repeat when its time comes, shorter when rolling, lower and quieter when decaying
+    fn update_repeat_slice(&mut self) {
+        let lb = match self.local_buffer.as_ref() {
+            Some(lb) => lb,
+            None => return,
+        };
+        let mut repeat = match self.repeat {
+            Some(repeat) => repeat,
+            None => return,
+        };
+        let ticks = self.ticks.saturating_sub(repeat.tick);
+        let elapsed = Ticks(ticks as i64).samples(lb.original_tempo, PPQN, self.sample_rate) as f64
+            + self.inter_tick_frames;
+        if elapsed < repeat.next_start {
+            return;
+        }
+
+        // catch up with the clock, counts the skipped repeats
+        let mut start = repeat.next_start;
+        while elapsed >= repeat.next_start {
+            start = repeat.next_start;
+            if start > 0.0 {
+                repeat.count += 1;
+                repeat.interval =
+                    (repeat.interval * repeat.params.roll.max(0.0) as f64).max(MIN_REPEAT_LEN);
+            }
+            repeat.next_start += repeat.interval;
+        }
+
+        // the gate cuts the captured slice
+        let params = repeat.params;
+        let mut slice = repeat.slice;
+        let gate_len = (repeat.interval * params.gate.max(0.0).min(1.0) as f64) as usize;
+        slice.end = slice.start + gate_len.min(lb.frames.len().saturating_sub(slice.start));
+        slice.cursor = (elapsed - start) as usize;
+        slice.rate *= 2f64.powf((params.pitch * repeat.count as f32) as f64 / 12.0);
+        slice.gain *= params.decay.max(0.0).min(1.0).powi(repeat.count as i32);
+
+        self.repeat = Some(repeat);
+        self.curr_slice_tup.1 = slice;
+    }
+
+    /// Holds the playing slice on a window from its read position
+    fn freeze_start(&mut self) {
+        if self.freeze.is_some() || self.local_buffer.is_none() {
+            return;
+        }
+        let slice = self.curr_slice_tup.1;
+        let start = slice.start + slice.cursor.min(slice.len());
+        self.fill_crossfade_buffer();
+        self.freeze = Some(Freeze::new(start as u64, self.sample_rate));
+    }
+
+    /// Crossfades from the freeze to the slice the clock is on
+    fn freeze_release(&mut self) {
+        let (mut freeze, lb) = match (self.freeze.take(), &self.local_buffer) {
+            (Some(freeze), Some(lb)) => (freeze, lb),
+            _ => return,
+        };
+        for _i in 0..(self.crossfade_buffer.capacity() - self.crossfade_buffer.len()) {
+            self.crossfade_buffer
+                .enqueue(freeze.next_frame(&lb.frames[..]).scale_amp(1.45))
+                .expect("no overflow");
+        }
+        self.adjust_current_slice();
+    }
+
+    /// Current step of the pattern from its start and the cursor in the step
+    fn pattern_position(&self) -> Option<(u64, usize)> {
+        let pattern = self.pattern.as_ref()?;
+        let lb = self.local_buffer.as_ref()?;
+        let bar = Bars(1).samples(
+            lb.original_tempo,
+            TimeSig { top: 4, bottom: 4 },
+            self.sample_rate,
+        );
+        let step_len = (bar as u64 / pattern.div.max(1)).max(1);
+        let ticks = self.ticks.saturating_sub(self.pattern_tick);
+        let elapsed = Ticks(ticks as i64).samples(lb.original_tempo, PPQN, self.sample_rate) as u64
+            + self.inter_tick_frames as u64;
+        Some((elapsed / step_len, (elapsed % step_len) as usize))
+    }
+
+    /// Plays the slice of the pattern step, cuts the previous one
+    fn update_pattern_slice(&mut self) {
+        let (step_num, cursor) = match self.pattern_position() {
+            Some(position) => position,
+            None => return,
+        };
+        if step_num == self.pattern_step {
+            return;
+        }
+        self.fill_crossfade_buffer();
+        self.pattern_step = step_num;
+
+        // rests and missing slices are silent
+        let steps = &self.pattern.as_ref().expect("pattern here").steps;
+        let step = (step_num % steps.len() as u64) as usize;
+        let keys = self.slices_playing.ord_keys();
+        let key = steps[step].and_then(|i| keys.get(i).cloned());
+        let mut slice = key
+            .and_then(|k| self.slices_playing.get_by_copy(&k))
+            .unwrap_or_default();
+        slice.cursor = cursor;
+
+        // keeps a playing key for the transforms
+        let key = key.or_else(|| keys.first().cloned()).unwrap_or(0);
+        self.curr_slice_tup = (key, slice);
+    }
+
+    /// Computes the clock in frames scaled according to the local smart buffer, not wrapped
+    fn get_clock_frames(&self, lb: &SmartBuffer) -> u64 {
+        let (start, ticks) = match self.trigger {
+            Some(t) => (t.start, self.ticks.saturating_sub(t.tick)),
+            None => (0, self.ticks),
+        };
+        start + Ticks(ticks as i64).samples(lb.original_tempo, PPQN, self.sample_rate) as u64
     }
 
     /// Computes the clock in frames scaled / wrapped according to the local smart buffer
     fn get_local_clock(&self) -> u64 {
         if let Some(lb) = &self.local_buffer {
-            let original_tempo = lb.original_tempo;
-            let abs = Ticks(self.ticks as i64).samples(original_tempo, PPQN, 44_100.0) as u64
-                % lb.frames.len() as u64;
+            let abs = self.get_clock_frames(lb) % lb.frames.len() as u64;
             return abs + self.inter_tick_frames as u64;
         }
         0
     }
 
+    /// A one shot reached its end
+    fn trigger_ended(&self) -> bool {
+        match (self.trigger, &self.local_buffer) {
+            (Some(TriggerState { end: Some(end), .. }), Some(lb)) => {
+                self.get_clock_frames(lb) + self.inter_tick_frames as u64 >= end
+            }
+            _ => false,
+        }
+    }
+
     /// Compute the current playback rate
     fn playback_rate(&self) -> f64 {
         if let Some(lb) = &self.local_buffer {
@@ -337,47 +956,31 @@ impl SliceSeq {
         }
     }
 
-    /// Copy a smart buffer frames into the local buffer
+    /// Swaps the local buffer
     /// trying to not generate clicks
-    fn load_buffer(&mut self, buffer: &SmartBuffer) {
+    fn load_buffer(&mut self, buffer: Arc<SmartBuffer>) {
         // prepare crossfade buffer
         self.fill_crossfade_buffer();
 
-        // check if we have a
-        match &mut self.local_buffer {
-            None => {
-                // clone only one time !
-                self.local_buffer = Some(buffer.clone());
-            }
-            Some(local) => {
-                local.copy_from(buffer);
-            }
-        }
+        // the captured slice and the freeze are in the previous buffer
+        self.repeat = None;
+        self.freeze = None;
 
-        // get positions
-        let positions = &buffer
-            .positions
-            .get(&self.positions_mode)
-            .expect("position mode exists");
+        // the previous buffer is still owned by the gen, this is not the last reference
+        self.local_buffer = Some(Arc::clone(&buffer));
 
-        self.slices_orig.clear();
+        // the slices are built by the loader, the buffer keeps them alive
+        self.slices_orig = Arc::clone(
+            buffer
+                .slices
+                .get(&self.positions_mode)
+                .expect("position mode exists"),
+        );
 
-        // iterate and set
-        for (idx, pos) in positions.windows(2).enumerate() {
-            self.slices_orig.insert_copy(
-                *pos.first().expect("have a first pos"),
-                Slice {
-                    id: idx,
-                    start: *pos.first().expect("have a first pos"),
-                    end: *pos.last().expect("have a last pos"), // can't fail
-                    cursor: 0,
-                    reverse: false,
-                },
-            );
-        }
-
-        // init the currently playing slice map 
+        // init the currently playing slice map, within its capacity
         self.slices_playing.copy_from(&self.slices_orig);
+        self.do_weights();
+        self.do_generate();
 
         // adjust current slice
         self.adjust_current_slice();
@@ -385,6 +988,18 @@ impl SliceSeq {
 
     /// Ajust current slice to local clock
     fn adjust_current_slice(&mut self) {
+        // the beat repeat holds its slice
+        if self.repeat.is_some() {
+            return;
+        }
+
+        // patterns follow their own steps
+        if self.pattern.is_some() {
+            self.pattern_step = u64::max_value();
+            self.update_pattern_slice();
+            return;
+        }
+
         // compute current slice index in the playing slices according to the clock
         let curr_slice_idx = self.current_slice_idx();
 
@@ -417,12 +1032,16 @@ impl SliceSeq {
             // apply according transform
             match nt {
                 TransformType::Reset() => { 
+                    self.generative = None;
                     self.do_reset();
                 },
                 TransformType::RandSwap() => { 
+                    self.generative = None;
                     self.do_rand_swap();
                 },
                 TransformType::QuantRepeat { quant, slice_index } => {
+                    self.generative = None;
+
                     // another way to avoid pattern matching
                     let local_buff = self.local_buffer.as_ref().expect("buffer here");
                     
@@ -430,7 +1049,7 @@ impl SliceSeq {
                     let smpls_per_bar = Bars(1).samples(
                         local_buff.original_tempo,
                         TimeSig { top: 4, bottom: 4 },
-                        44_100.0
+                        self.sample_rate
                     );
 
                     // repeat in samples
@@ -439,6 +1058,49 @@ impl SliceSeq {
                     // apply repeat
                     self.do_quant_repeat(quant_samples as usize, slice_index);
                 },
+                TransformType::Reverse { slice_index } => {
+                    if let Some(slice) = self.slices_playing.get_nth_mut(slice_index) {
+                        slice.reverse = !slice.reverse;
+                    }
+                }
+                TransformType::RandReverse { amount } => {
+                    self.slices_playing.rand_reverse(amount);
+                }
+                TransformType::Stutter {
+                    slice_index,
+                    repeats,
+                } => {
+                    if let Some(slice) = self.slices_playing.get_nth_mut(slice_index) {
+                        slice.repeats = repeats.max(1);
+                    }
+                }
+                TransformType::SliceGain { slice_index, gain } => {
+                    if let Some(slice) = self.slices_playing.get_nth_mut(slice_index) {
+                        slice.gain = gain.max(0.0);
+                    }
+                }
+                TransformType::SliceRepitch {
+                    slice_index,
+                    semitones,
+                } => {
+                    if let Some(slice) = self.slices_playing.get_nth_mut(slice_index) {
+                        slice.rate = 2f64.powf(semitones as f64 / 12.0);
+                    }
+                }
+                TransformType::Generate {
+                    density,
+                    variation,
+                    seed,
+                } => {
+                    self.generative = Some(Generative {
+                        density: density.max(0.0).min(1.0),
+                        variation: variation.max(0.0).min(1.0),
+                        rng: SeededRng::new(seed),
+                    });
+                    self.do_reset();
+                    self.do_weights();
+                    self.do_generate();
+                }
             }
 
             // adjust current slice (operation above changed it)
@@ -456,12 +1118,29 @@ impl SliceSeq {
             return;
         }
 
+        // the beat repeat plays in place of the slices
+        if self.repeat.is_some() {
+            self.update_repeat_slice();
+            return;
+        }
+
+        // patterns follow their own steps
+        if self.pattern.is_some() {
+            self.update_pattern_slice();
+            return;
+        }
+
         // compute current slice index in the playing slices according to the clock
         let (is_obsolete, curr_slice_idx) = self.compute_curr_slice();
 
         // check if clock given current slice is the same as the playing current slice
         // if not, we should set the self.curren_slice
         if is_obsolete {
+            // a new loop, generate its steps
+            if self.slices_playing.ord_keys().first() == Some(&curr_slice_idx) {
+                self.do_generate();
+            }
+
             // NEW SLICE HERE
             let next_curr_slice = self.slices_playing.get_by_copy(&curr_slice_idx).unwrap();
             self.curr_slice_tup = (curr_slice_idx, next_curr_slice);
@@ -504,36 +1183,47 @@ impl SliceSeq {
             None => return Stereo::<f32>::equilibrium(),
             // grab the next frame
             Some(local_buff) => {
-                // grab next frame
-                let next_frame = self
-                    .curr_slice_tup
-                    .1
-                    .next_frame(self.playback_rate(), &local_buff.frames[..]);
+                // grab next frame, from the freeze window when frozen
+                let next_frame = match self.freeze.as_mut() {
+                    Some(freeze) => freeze.next_frame(&local_buff.frames[..]).scale_amp(1.45),
+                    None => self
+                        .curr_slice_tup
+                        .1
+                        .next_frame(self.playback_rate(), &local_buff.frames[..]),
+                };
 
                 // crossfade
-                if self.crossfade_buffer.len() > 0 {
-                    let t = self.crossfade_buffer.capacity() - self.crossfade_buffer.len();
-                    let fade_in_ratio = super::gen_utils::fade_in(
-                        t as i64,
-                        self.crossfade_buffer.capacity() as i64,
-                    );
-                    let fade_out_ratio = super::gen_utils::fade_out(
-                        t as i64,
-                        self.crossfade_buffer.capacity() as i64,
-                        self.crossfade_buffer.capacity() as i64,
-                    );
+                return self.crossfade(next_frame);
+            }
+        }
+    }
 
-                    let old_f = self.crossfade_buffer.dequeue().unwrap();
+    /// Fades from the crossfade buffer to the next frame, while there is some left
+    fn crossfade(&mut self, next_frame: Stereo<f32>) -> Stereo<f32> {
+        if self.crossfade_buffer.len() > 0 {
+            let t = self.crossfade_buffer.capacity() - self.crossfade_buffer.len();
+            let fade_in_ratio =
+                super::gen_utils::fade_in(t as i64, self.crossfade_buffer.capacity() as i64);
+            let fade_out_ratio = super::gen_utils::fade_out(
+                t as i64,
+                self.crossfade_buffer.capacity() as i64,
+                self.crossfade_buffer.capacity() as i64,
+            );
 
-                    // actual crossfade
-                    let mixed_frame = next_frame
-                        .scale_amp(fade_in_ratio)
-                        .add_amp(old_f.scale_amp(fade_out_ratio));
-                    return mixed_frame;
-                }
-                return next_frame;
-            }
+            let old_f = self.crossfade_buffer.dequeue().unwrap();
+
+            // actual crossfade
+            let mixed_frame = next_frame
+                .scale_amp(fade_in_ratio)
+                .add_amp(old_f.scale_amp(fade_out_ratio));
+            return mixed_frame;
         }
+        next_frame
+    }
+
+    /// Empties the crossfade buffer, the notes don't play what the sequence left in it
+    fn clear_crossfade_buffer(&mut self) {
+        while self.crossfade_buffer.dequeue().is_some() {}
     }
 
     // transforms
@@ -550,6 +1240,22 @@ impl SliceSeq {
         self.slices_playing.rand_swap(&self.slices_orig);
     }
 
+    /// Builds the pick weights of the original slices if generative
+    fn do_weights(&mut self) {
+        if let Some(gen) = self.generative {
+            self.slices_orig
+                .fill_weights(gen.variation, &mut self.gen_weights);
+        }
+    }
+
+    /// Picks the slices of the steps again if generative
+    fn do_generate(&mut self) {
+        if let Some(gen) = self.generative.as_mut() {
+            self.slices_playing
+                .generate(&self.slices_orig, gen, &self.gen_weights);
+        }
+    }
+
     /// Repeat a slice accoding to a quantization in samples
     fn do_quant_repeat(&mut self, quant_samples: usize, slice_idx: usize) {
         if let Some(f) = &self.local_buffer {
@@ -566,14 +1272,25 @@ pub struct SlicerGen {
     sample_gen: SampleGen,
     /// Slice Sequencer
     slice_seq: SliceSeq,
+    /// Note triggered slices, the slice sequence is off when set
+    notes: Option<SliceNotes>,
+    /// Slices playing from the notes
+    voices: Vec<SliceVoice>,
+    /// Notes waiting for the quantization, slice and velocity
+    pending_notes: Vec<(usize, f32)>,
+    /// Pattern of the bank, played on the samples without their own
+    bank_pattern: Option<Arc<SlicePattern>>,
+    /// Patterns of the samples, by file name
+    sample_patterns: HashMap<String, Arc<SlicePattern>>,
 }
 
 /// Specific sub SampleGen implementation
 impl SlicerGen {
     /// Inits and return a new SlicerGen sample generator
-    pub fn new() -> Self {
+    pub fn new(sample_rate: f64) -> Self {
         SlicerGen {
             sample_gen: SampleGen {
+                sample_rate,
                 playback_rate: 1.0,
                 frame_index: 0,
                 playback_mult: 0,
@@ -581,23 +1298,145 @@ impl SlicerGen {
                 next_loop_div: 1,
                 loop_offset: 0,
                 playing: false,
-                smartbuf: SmartBuffer::new_empty(), // source of truth
+                smartbuf: Arc::new(SmartBuffer::new_empty(sample_rate)), // source of truth
                 sync_cursor: 0,
                 sync_next_frame_index: 0,
+                trigger: None,
+                freeze: None,
+                freeze_resync: false,
             },
             slice_seq: SliceSeq {
                 ticks: 0,
                 global_tempo: 120,
+                sample_rate,
                 inter_tick_frames: 0f64,
                 local_buffer: None, // one sec
-                slices_orig: SliceMap::new(),
+                slices_orig: Arc::new(SliceMap::new()),
                 slices_temp: SliceMap::new(),
                 slices_playing: SliceMap::new(),
                 curr_slice_tup: Default::default(),
                 positions_mode: super::PositionsMode::OnsetMode(),
                 next_transform: None,
+                generative: None,
+                gen_weights: Vec::with_capacity(MAX_SLICES * MAX_SLICES),
+                pattern: None,
+                pending_pattern: None,
+                replaced_patterns: Vec::with_capacity(MAX_REPLACED_PATTERNS),
+                pattern_tick: 0,
+                pattern_step: 0,
+                repeat: None,
+                pending_repeat: None,
                 crossfade_buffer: heapless::spsc::Queue::new(),
+                trigger: None,
+                freeze: None,
             },
+            notes: None,
+            voices: Vec::with_capacity(MAX_SLICE_VOICES),
+            pending_notes: Vec::with_capacity(MAX_SLICE_VOICES),
+            bank_pattern: None,
+            sample_patterns: HashMap::new(),
+        }
+    }
+
+    /// Sets the patterns played in place of the sample order, for the bank and by sample file name
+    pub fn set_patterns(
+        &mut self,
+        bank_pattern: Option<SlicePattern>,
+        sample_patterns: HashMap<String, SlicePattern>,
+    ) {
+        self.bank_pattern = bank_pattern.map(Arc::new);
+        self.sample_patterns = sample_patterns
+            .into_iter()
+            .map(|(name, pattern)| (name, Arc::new(pattern)))
+            .collect();
+    }
+
+    /// Plays the slices from the notes instead of the slice sequence
+    pub fn set_notes(&mut self, notes: SliceNotes) {
+        self.notes = Some(notes);
+    }
+
+    /// Starts a slice voice, chokes the slices of its group
+    fn note_on(&mut self, slice_num: usize, velocity: f32) {
+        // notes above the slices are silent
+        let slices = &self.slice_seq.slices_orig;
+        let mut slice = match slices.ord_keys().get(slice_num) {
+            Some(k) => slices.get_by_copy(k).expect("key exists"),
+            None => return,
+        };
+        slice.cursor = 0;
+
+        // cut the playing slices of the same group
+        let choke = self
+            .notes
+            .as_ref()
+            .and_then(|n| n.chokes.iter().position(|g| g.contains(&slice_num)));
+        if choke.is_some() {
+            for voice in self.voices.iter_mut() {
+                if voice.choke == choke && voice.choked.is_none() {
+                    voice.choked = Some(CHOKE_FADE);
+                }
+            }
+        }
+
+        // steal the oldest
+        if self.voices.len() >= MAX_SLICE_VOICES {
+            self.voices.remove(0);
+        }
+        self.voices.push(SliceVoice {
+            slice,
+            gain: velocity,
+            choke,
+            choked: None,
+        });
+    }
+
+    /// Mixes the slice voices in the block, they go on underneath the freeze
+    fn voices_next_block(&mut self, block_out: &mut [Stereo<f32>]) {
+        let playback_rate = self.slice_seq.playback_rate();
+        let frames = &self.sample_gen.smartbuf.frames[..];
+        for frame_out in block_out.iter_mut() {
+            let mut frame = Stereo::<f32>::equilibrium();
+            for voice in self.voices.iter_mut() {
+                frame = frame.add_amp(voice.next_frame(playback_rate, frames));
+            }
+            if let Some(freeze) = self.slice_seq.freeze.as_mut() {
+                frame = freeze.next_frame(frames).scale_amp(1.45);
+            }
+            *frame_out = self.slice_seq.crossfade(frame);
+        }
+        self.voices.retain(|v| !v.is_done());
+    }
+
+    /// Holds the newest voice on a window from its read position
+    fn voices_freeze(&mut self) {
+        if self.slice_seq.freeze.is_some() {
+            return;
+        }
+        // nothing playing, nothing to freeze
+        let slice = match self.voices.iter().rev().find(|v| !v.is_done()) {
+            Some(voice) => voice.slice,
+            None => return,
+        };
+        let start = slice.start + slice.cursor.min(slice.len());
+        self.slice_seq.clear_crossfade_buffer();
+        let sample_rate = self.slice_seq.sample_rate;
+        self.slice_seq.freeze = Some(Freeze::new(start as u64, sample_rate));
+    }
+
+    /// Crossfades from the freeze to the voices
+    fn voices_unfreeze(&mut self) {
+        let mut freeze = match self.slice_seq.freeze.take() {
+            Some(freeze) => freeze,
+            None => return,
+        };
+        let frames = &self.sample_gen.smartbuf.frames[..];
+        self.slice_seq.clear_crossfade_buffer();
+        for _i in 0..self.slice_seq.crossfade_buffer.capacity() {
+            self.slice_seq
+                .crossfade_buffer
+                .enqueue(freeze.next_frame(frames).scale_amp(1.45))
+                .expect("no overflow");
         }
     }
 
@@ -613,6 +1452,12 @@ impl SampleGenerator for SlicerGen {
     /// Yields processed block out of the samplegen.
     /// This method trigger all the processing.
     fn next_block(&mut self, block_out: &mut [Stereo<f32>]) {
+        // the notes play the slices, with or without the transport
+        if self.notes.is_some() {
+            self.voices_next_block(block_out);
+            return;
+        }
+
         // println!("block call {}", self.sample_gen.playing);
         // just write zero stero frames
         if !self.sample_gen.playing {
@@ -624,15 +1469,37 @@ impl SampleGenerator for SlicerGen {
 
         // playing, simply use the iterator
         for frame_out in block_out.iter_mut() {
+            // a one shot is over
+            if self.slice_seq.trigger_ended() {
+                self.sample_gen.playing = false;
+                *frame_out = Stereo::<f32>::equilibrium();
+                continue;
+            }
             // can safely be unwrapped because always return something
             *frame_out = self.next().unwrap();
         }
     }
 
-    /// Loads a SmartBuffer from a reference
-    fn load_buffer(&mut self, smartbuf: &SmartBuffer) {
-        self.slice_seq.load_buffer(smartbuf);
-        self.sample_gen.smartbuf.copy_from(smartbuf);
+    /// Swaps the SmartBuffer, the slice sequencer shares it
+    fn load_buffer(&mut self, smartbuf: Arc<SmartBuffer>) -> Arc<SmartBuffer> {
+        // the voices slices are in the previous buffer
+        self.voices.clear();
+
+        // the sample pattern or the bank one, from the next bar
+        if self.bank_pattern.is_some() || !self.sample_patterns.is_empty() {
+            let pattern = self
+                .sample_patterns
+                .get(&smartbuf.file_name)
+                .or_else(|| self.bank_pattern.as_ref())
+                .cloned();
+            self.slice_seq.set_pending_pattern(pattern);
+        }
+
+        self.slice_seq.load_buffer(Arc::clone(&smartbuf));
+        if self.notes.is_some() {
+            self.slice_seq.clear_crossfade_buffer();
+        }
+        std::mem::replace(&mut self.sample_gen.smartbuf, smartbuf)
     }
 
     /// Sync the slicer according to a clock
@@ -641,6 +1508,19 @@ impl SampleGenerator for SlicerGen {
         if let Some(_lb) = &self.slice_seq.local_buffer {
             self.slice_seq.sync(global_tempo, tick);
         }
+
+        // quantized notes start now
+        let quant_ticks = match self.notes.as_ref().map(|n| n.quant) {
+            None | Some(0) => return,
+            Some(quant) => (PPQN as u64 * 4 / quant).max(1),
+        };
+        if tick % quant_ticks == 0 {
+            for i in 0..self.pending_notes.len() {
+                let (slice_num, velocity) = self.pending_notes[i];
+                self.note_on(slice_num, velocity);
+            }
+            self.pending_notes.clear();
+        }
     }
 
     /// sets play
@@ -651,9 +1531,15 @@ impl SampleGenerator for SlicerGen {
         }
     }
 
-    /// sets stop
+    /// sets stop, the playing slices ring out
     fn stop(&mut self) {
         self.reset();
+        self.pending_notes.clear();
+        // patterns start over with the clock
+        self.slice_seq.pattern_tick = 0;
+        self.slice_seq.repeat = None;
+        self.slice_seq.pending_repeat = None;
+        self.slice_seq.freeze = None;
         self.sample_gen.playing = false;
     }
 
@@ -700,10 +1586,71 @@ impl SampleGenerator for SlicerGen {
                         }
                     }
                 }
+                SlicerMessage::BeatRepeat(repeat) => {
+                    // from the next quantization
+                    self.slice_seq.pending_repeat = Some(repeat);
+                }
+                SlicerMessage::BeatRelease() => self.slice_seq.release_repeat(),
+                SlicerMessage::Pattern(pattern) => {
+                    // from the next bar, empty goes back to the sample order
+                    self.slice_seq.set_pending_pattern(Some(pattern));
+                }
+                SlicerMessage::NoteOn { slice, velocity } => {
+                    // now or on the next quantization
+                    match self.notes.as_ref().map(|n| n.quant) {
+                        None => {}
+                        Some(0) => self.note_on(slice, velocity),
+                        Some(_) => {
+                            if self.pending_notes.len() < self.pending_notes.capacity() {
+                                self.pending_notes.push((slice, velocity));
+                            }
+                        }
+                    }
+                }
             },
             _ => (), // ignore the rest
         }
     }
+
+    /// starts a triggered playback, slices are the slicer ones
+    fn trigger(&mut self, trigger: Trigger) {
+        // crossfades with the playing slice
+        if self.sample_gen.playing {
+            self.slice_seq.fill_crossfade_buffer();
+        }
+        self.sample_gen
+            .trigger_start(trigger, self.slice_seq.positions_mode);
+        self.slice_seq.trigger = self.sample_gen.trigger;
+    }
+
+    /// stops a triggered playback
+    fn release(&mut self) {
+        self.sample_gen.trigger_release();
+        self.slice_seq.trigger = None;
+    }
+
+    /// loops a window of the playing slice, the sequence or the notes go on underneath
+    fn freeze(&mut self) {
+        if self.notes.is_some() {
+            self.voices_freeze();
+        } else {
+            self.slice_seq.freeze_start();
+        }
+    }
+
+    /// crossfades back to the sequence or the notes
+    fn unfreeze(&mut self) {
+        if self.notes.is_some() {
+            self.voices_unfreeze();
+        } else {
+            self.slice_seq.freeze_release();
+        }
+    }
+
+    /// Replaced patterns, from the bars and the messages
+    fn replaced_pattern(&mut self) -> Option<Arc<SlicePattern>> {
+        self.slice_seq.replaced_patterns.pop()
+    }
 }
 
 /// Implement `Iterator` for `SliceGen`.
@@ -720,3 +1667,292 @@ impl Iterator for SlicerGen {
         return Some(next_frame);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample_gen::PositionsMode;
+
+    #[test]
+    fn test_slice_stutter_reverse() {
+        let frames: Vec<Stereo<f32>> = (0..8000).map(|i| [i as f32, i as f32]).collect();
+        let mut slice = Slice {
+            start: 1000,
+            end: 5000,
+            repeats: 2,
+            reverse: true,
+            ..Default::default()
+        };
+
+        // each repeat plays the first half backward, past the fade in
+        for _ in 0..1000 {
+            slice.next_frame(1.0, &frames);
+        }
+        let f = slice.next_frame(1.0, &frames);
+        assert_eq!(f[0], 1999.0 * 1.45);
+        slice.cursor = 2000 + 1000;
+        let g = slice.next_frame(1.0, &frames);
+        assert_eq!(f, g);
+    }
+
+    #[test]
+    fn test_slices_from_positions() {
+        let frames = vec![[0.5, 0.5]; 1000];
+        let positions: Vec<usize> = (0..MAX_SLICES + 10).collect();
+        let map = SliceMap::from_positions(&frames, &positions);
+
+        // capped, the last slice runs to the last position
+        assert_eq!(map.len(), MAX_SLICES);
+        let last = map.get_by_ref(&(MAX_SLICES - 1)).unwrap();
+        assert_eq!(last.end, MAX_SLICES + 9);
+        assert_eq!(map.get_by_ref(&0).unwrap().end, 1);
+    }
+
+    #[test]
+    fn test_generate_reproducible() {
+        let mut orig = SliceMap::new();
+        for i in 0..8 {
+            let slice = Slice {
+                id: i,
+                start: i * 100,
+                end: i * 100 + 100,
+                strength: (i % 2) as f32,
+                ..Default::default()
+            };
+            orig.insert_copy(i * 100, slice);
+        }
+        let starts = |map: &SliceMap| -> Vec<usize> {
+            let keys = map.ord_keys();
+            keys.iter()
+                .map(|k| map.get_by_ref(k).unwrap().start)
+                .collect()
+        };
+        let generate = |density: f32, variation: f32, seed: u64| {
+            let mut map = SliceMap::new();
+            map.copy_from(&orig);
+            let mut gen = Generative {
+                density,
+                variation,
+                rng: SeededRng::new(seed),
+            };
+            let mut weights = Vec::new();
+            orig.fill_weights(variation, &mut weights);
+            map.generate(&orig, &mut gen, &weights);
+            map
+        };
+
+        // no variation keeps the original slices
+        assert_eq!(starts(&generate(1.0, 0.0, 7)), starts(&orig));
+
+        // the same seed gives the same steps
+        let a = generate(1.0, 1.0, 7);
+        assert_eq!(starts(&a), starts(&generate(1.0, 1.0, 7)));
+        assert_ne!(starts(&a), starts(&orig));
+
+        // no density silences all the steps
+        let silent = generate(0.0, 0.5, 7);
+        assert!(silent.unord_slices.values().all(|s| s.gain == 0.0));
+    }
+
+    #[test]
+    fn test_pattern_parse() {
+        let pattern = SlicePattern::parse("0 0 3 2 - 5 5 7", 16).unwrap();
+        assert_eq!(pattern.steps[2], Some(3));
+        assert_eq!(pattern.steps[4], None);
+        assert_eq!(pattern.steps.len(), 8);
+        assert!(SlicePattern::parse("0 x 2", 16).is_err());
+
+        // the steps are written as text in the config
+        let conf: SlicePattern = toml::from_str("steps = \"1 - 2\"").unwrap();
+        assert_eq!(conf.steps, vec![Some(1), None, Some(2)]);
+        assert_eq!(conf.div, 16);
+    }
+
+    #[test]
+    fn test_pattern_steps_on_bars() {
+        // one bar of 2000 samples at 120 bpm, 8 slices of 1000
+        let buffer = |name: &str| {
+            let mut smartbuf = SmartBuffer::new_empty(1000.0);
+            smartbuf.frames = vec![[0.5, 0.5]; 8000];
+            smartbuf.file_name = String::from(name);
+            smartbuf.positions.insert(
+                PositionsMode::OnsetMode(),
+                (0..9).map(|i| i * 1000).collect(),
+            );
+            smartbuf.build_slices();
+            Arc::new(smartbuf)
+        };
+        let step_at = |gen: &mut SlicerGen, tick: u64| {
+            gen.sync(120, tick);
+            gen.next();
+            gen.slice_seq.curr_slice_tup.1
+        };
+        let mut gen = SlicerGen::new(1000.0);
+        let mut sample_patterns = HashMap::new();
+        sample_patterns.insert(
+            String::from("amen.wav"),
+            SlicePattern::parse("6", 4).unwrap(),
+        );
+        gen.set_patterns(
+            Some(SlicePattern::parse("1 2 3", 2).unwrap()),
+            sample_patterns,
+        );
+        gen.load_buffer(buffer("other.wav"));
+        gen.play();
+
+        // the bank pattern waits for the bar
+        assert_eq!(step_at(&mut gen, 1).start, 0);
+        step_at(&mut gen, 95);
+        assert!(gen.slice_seq.pattern.is_none());
+        assert_eq!(step_at(&mut gen, 96).start, 1000);
+        assert_eq!(step_at(&mut gen, 144).start, 2000);
+
+        // longer than a bar, goes on across the bar then loops
+        assert_eq!(step_at(&mut gen, 192).start, 3000);
+        assert_eq!(step_at(&mut gen, 240).start, 1000);
+
+        // a new pattern waits for the next bar
+        gen.push_control_message(ControlMessage::Slicer {
+            tcode: 0,
+            track_num: 0,
+            message: SlicerMessage::Pattern(Arc::new(SlicePattern::parse("4 -", 4).unwrap())),
+        });
+        assert_eq!(step_at(&mut gen, 264).start, 1000);
+        assert_eq!(step_at(&mut gen, 288).start, 4000);
+        assert!(gen.replaced_pattern().is_some());
+
+        // rests are silent, shorter than a bar loops on its own
+        let mut rest = step_at(&mut gen, 312);
+        assert_eq!(rest.len(), 0);
+        assert_eq!(rest.next_frame(1.0, &[[0.5, 0.5]; 8]), [0.0, 0.0]);
+        assert_eq!(step_at(&mut gen, 336).start, 4000);
+
+        // the sample pattern takes precedence over the bank one
+        gen.load_buffer(buffer("amen.wav"));
+        assert_eq!(step_at(&mut gen, 384).start, 6000);
+        gen.load_buffer(buffer("other.wav"));
+        assert_eq!(step_at(&mut gen, 480).start, 1000);
+    }
+
+    #[test]
+    fn test_beat_repeat_roll_decay() {
+        // one bar of 2000 samples at 120 bpm
+        let mut smartbuf = SmartBuffer::new_empty(1000.0);
+        smartbuf.frames = vec![[0.5, 0.5]; 8000];
+        smartbuf
+            .positions
+            .insert(PositionsMode::OnsetMode(), vec![0, 2000, 4000, 8000]);
+        smartbuf.build_slices();
+        let mut gen = SlicerGen::new(1000.0);
+        gen.load_buffer(Arc::new(smartbuf));
+        gen.play();
+        gen.sync(120, 1);
+        gen.push_control_message(ControlMessage::Slicer {
+            tcode: 0,
+            track_num: 0,
+            message: SlicerMessage::BeatRepeat(BeatRepeat {
+                quant: 1,
+                gate: 0.5,
+                roll: 0.75,
+                pitch: -12.0,
+                decay: 0.5,
+                bars: 2,
+            }),
+        });
+
+        // starts on the next bar, captures the playing slice
+        gen.sync(120, 95);
+        assert!(gen.slice_seq.repeat.is_none());
+        gen.sync(120, 96);
+        let repeat = gen.slice_seq.repeat.expect("repeat started");
+        assert_eq!(repeat.slice.start, 2000);
+        assert_eq!(gen.slice_seq.curr_slice_tup.1.len(), 1000);
+
+        // the second repeat is shorter, an octave down and half as loud
+        gen.sync(120, 96 * 2);
+        gen.next();
+        let slice = gen.slice_seq.curr_slice_tup.1;
+        assert_eq!(gen.slice_seq.repeat.unwrap().count, 1);
+        assert_eq!(slice.len(), 750);
+        assert_eq!(slice.rate, 0.5);
+        assert_eq!(slice.gain, 0.5);
+
+        // released after its bars
+        gen.sync(120, 96 * 3);
+        assert!(gen.slice_seq.repeat.is_none());
+    }
+
+    #[test]
+    fn test_note_slices_choke() {
+        let mut smartbuf = SmartBuffer::new_empty(1000.0);
+        smartbuf.frames = vec![[0.5, 0.5]; 4000];
+        smartbuf
+            .positions
+            .insert(PositionsMode::OnsetMode(), vec![0, 1000, 2000, 3000, 4000]);
+        smartbuf.build_slices();
+        let mut gen = SlicerGen::new(1000.0);
+        gen.set_notes(SliceNotes {
+            channel: 1,
+            note: 36,
+            quant: 0,
+            chokes: vec![vec![1, 2]],
+        });
+        gen.load_buffer(Arc::new(smartbuf));
+
+        // notes above the slices are silent
+        gen.note_on(4, 1.0);
+        assert!(gen.voices.is_empty());
+
+        // the slices of a group cut each other, the others overlap
+        gen.note_on(0, 1.0);
+        gen.note_on(1, 1.0);
+        gen.note_on(2, 0.5);
+        assert_eq!(gen.voices.len(), 3);
+        assert_eq!(gen.voices[1].choked, Some(CHOKE_FADE));
+        let mut block = vec![Stereo::<f32>::equilibrium(); CHOKE_FADE];
+        gen.next_block(&mut block);
+        assert_eq!(gen.voices.len(), 2);
+        assert_eq!(gen.voices[1].gain, 0.5);
+    }
+
+    #[test]
+    fn test_note_slices_freeze() {
+        let mut smartbuf = SmartBuffer::new_empty(1000.0);
+        smartbuf.frames = (0..8000).map(|i| [i as f32, i as f32]).collect();
+        smartbuf
+            .positions
+            .insert(PositionsMode::OnsetMode(), vec![0, 4000, 8000]);
+        smartbuf.build_slices();
+        let mut gen = SlicerGen::new(1000.0);
+        gen.set_notes(SliceNotes {
+            channel: 1,
+            note: 36,
+            quant: 0,
+            chokes: vec![],
+        });
+        gen.load_buffer(Arc::new(smartbuf));
+
+        // nothing playing, nothing to freeze
+        gen.freeze();
+        assert!(gen.slice_seq.freeze.is_none());
+
+        // holds the voice from its read position, the voice goes on
+        gen.note_on(0, 1.0);
+        let mut block = vec![Stereo::<f32>::equilibrium(); 300];
+        gen.next_block(&mut block);
+        gen.freeze();
+        let mut block = vec![Stereo::<f32>::equilibrium(); 10];
+        gen.next_block(&mut block);
+        assert!((block[0][0] - 300.0 * 1.45).abs() < 1e-3);
+        assert!((block[9][0] - 309.0 * 1.45).abs() < 1e-3);
+        assert_eq!(gen.voices[0].slice.cursor, 310);
+
+        // crossfades back to the voices
+        gen.unfreeze();
+        assert!(gen.slice_seq.freeze.is_none());
+        assert_eq!(
+            gen.slice_seq.crossfade_buffer.len(),
+            gen.slice_seq.crossfade_buffer.capacity()
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Persistent analysis cache.
+//! Tempo, beats and positions tables of the SmartBuffers are stored in a json file in the audio root,
+//! so aubio does not run again on every startup.
+//! Entries are keyed by file path, and invalidated when the size, content hash,
+//! engine sample rate or downmix differ. The content is only hashed again when the modification date changed.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config::Downmix;
+use crate::sample_gen::Analysis;
+
+/// Cache file name, in the audio root
+const CACHE_FILE_NAME: &str = ".smplr_analysis.json";
+
+/// CacheKey tells if a cached analysis still matches the file
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CacheKey {
+    /// File size in bytes
+    size: u64,
+    /// Last modification date
+    modified: Option<SystemTime>,
+    /// FNV-1a hash of the file content
+    hash: u64,
+    /// Positions are in frames at the engine sample rate
+    sample_rate: f64,
+    /// The analysis runs on the downmixed signal
+    downmix: Downmix,
+}
+
+/// CacheEntry is the analysis of one file
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CacheEntry {
+    key: CacheKey,
+    analysis: Analysis,
+}
+
+/// AnalysisCache is the in memory cache, saved back to the audio root
+pub struct AnalysisCache {
+    /// Cache file path
+    path: PathBuf,
+    /// Entries by file path
+    entries: HashMap<String, CacheEntry>,
+    /// Files seen since the cache was open
+    used: HashSet<String>,
+}
+
+/// AnalysisCache implementation
+impl AnalysisCache {
+    /// Opens the cache of an audio root, empty if missing or unreadable
+    pub fn open(audio_root: &str) -> Self {
+        let path = Path::new(audio_root).join(CACHE_FILE_NAME);
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+
+        AnalysisCache {
+            path,
+            entries,
+            used: HashSet::new(),
+        }
+    }
+
+    /// Gets the analysis of a file if still valid.
+    /// A touched file of the same size is read and hashed, its new date is kept when the content is the same
+    pub fn get(&mut self, fpath: &Path, sample_rate: f64, downmix: Downmix) -> Option<&Analysis> {
+        let name = fpath.to_string_lossy().into_owned();
+        self.used.insert(name.clone());
+        let meta = fs::metadata(fpath).ok()?;
+        let entry = self.entries.get_mut(&name)?;
+        let key = &mut entry.key;
+        if key.sample_rate != sample_rate || key.downmix != downmix || key.size != meta.len() {
+            return None;
+        }
+
+        // same size but touched, the content tells
+        let modified = meta.modified().ok();
+        if key.modified != modified {
+            let content = fs::read(fpath).ok()?;
+            if fnv1a(&content) != key.hash {
+                return None;
+            }
+            key.modified = modified;
+        }
+        Some(&entry.analysis)
+    }
+
+    /// Stores the analysis of a file, replaces the stale one
+    pub fn insert(&mut self, fpath: &Path, key: CacheKey, analysis: Analysis) {
+        let name = fpath.to_string_lossy().into_owned();
+        self.used.insert(name.clone());
+        self.entries.insert(name, CacheEntry { key, analysis });
+    }
+
+    /// Forgets a file
+    pub fn remove(&mut self, fpath: &Path) {
+        let name = fpath.to_string_lossy().into_owned();
+        self.used.remove(&name);
+        self.entries.remove(&name);
+    }
+
+    /// Forgets the files not seen since the cache was open (deleted, moved ...)
+    pub fn prune(&mut self) {
+        let used = &self.used;
+        self.entries.retain(|name, _| used.contains(name));
+    }
+
+    /// Writes the cache back, a read only audio root just means no cache
+    pub fn save(&self) {
+        let res = serde_json::to_string(&self.entries)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            .and_then(|json| fs::write(&self.path, json));
+        if let Err(e) = res {
+            println!("sample_lib: unable to save the analysis cache: {}", e);
+        }
+    }
+}
+
+/// Computes the cache key of a fresh analysis, None if the file can't be read
+pub fn cache_key(fpath: &Path, sample_rate: f64, downmix: Downmix) -> Option<CacheKey> {
+    let meta = fs::metadata(fpath).ok()?;
+    let content = fs::read(fpath).ok()?;
+    Some(CacheKey {
+        size: meta.len(),
+        modified: meta.modified().ok(),
+        hash: fnv1a(&content),
+        sample_rate,
+        downmix,
+    })
+}
+
+/// 64 bits FNV-1a hash, fast enough and stable across versions
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let root = std::env::temp_dir().join("smplr_cache_roundtrip");
+        fs::create_dir_all(&root).unwrap();
+        let root_str = root.to_str().unwrap();
+        let fpath = root.join("loop.wav");
+        fs::write(&fpath, b"not really a wave").unwrap();
+
+        let analysis = Analysis {
+            original_tempo: 120.0,
+            num_beats: 4,
+            positions: vec![(
+                crate::sample_gen::PositionsMode::Bar4Mode(),
+                vec![0, 10, 20],
+            )],
+        };
+
+        // store and save
+        let mut cache = AnalysisCache::open(root_str);
+        assert!(cache.get(&fpath, 44_100.0, Downmix::Fold).is_none());
+        let key = cache_key(&fpath, 44_100.0, Downmix::Fold).unwrap();
+        cache.insert(&fpath, key, analysis.clone());
+        cache.save();
+
+        // reopen, still valid
+        let mut cache = AnalysisCache::open(root_str);
+        assert_eq!(cache.get(&fpath, 44_100.0, Downmix::Fold), Some(&analysis));
+
+        // other engine sample rate or downmix, stale
+        assert!(cache.get(&fpath, 48_000.0, Downmix::Fold).is_none());
+        assert!(cache.get(&fpath, 44_100.0, Downmix::Mono).is_none());
+
+        // content changed, stale
+        fs::write(&fpath, b"not really a wave either").unwrap();
+        assert!(cache.get(&fpath, 44_100.0, Downmix::Fold).is_none());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        // reference values of the 64 bits FNV-1a
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
@@ -0,0 +1,513 @@
+//! Background sample loader.
+//! Owns the SampleLib and the sample selection of each track,
+//! so the audio thread never copies nor frees a SmartBuffer.
+//! Buffers are handed to the mixer as reference counted pointers through lock-free queues,
+//! the replaced ones are sent back to be dropped here.
+//! When watching, library updates are applied here and the tracks playing a changed sample get the new one.
+//! Recorded takes are turned into SmartBuffers here too, the recorders get a fresh take buffer back.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel::{after, bounded, never, select, Receiver, Sender};
+use sample::frame::Stereo;
+
+use crate::config::Config;
+use crate::control::{ControlMessage, Direction, DirectionalParam, Notification};
+use crate::sample_gen::slicer::SlicePattern;
+use crate::sample_gen::SmartBuffer;
+
+use super::watcher::{initialize_watcher, LibUpdate};
+use super::SampleLib;
+
+/// How many requests / swaps / reclaims can wait in the queues
+const LOADER_QUEUE_CAPACITY: usize = 64;
+
+/// Delay before sending again the take buffers that found a full queue
+const TAKE_BUFFER_RETRY_MS: u64 = 10;
+
+/// BufferSwap is a ready to play buffer for a track
+pub struct BufferSwap {
+    /// Track that receives the buffer
+    pub track_num: usize,
+    /// The buffer itself
+    pub buffer: Arc<SmartBuffer>,
+}
+
+/// RecordedTake is a finished recording, quantized to the bars
+pub struct RecordedTake {
+    /// Recording track, plays the take right away
+    pub track_num: usize,
+    /// Bank the take is stored in
+    pub bank: usize,
+    /// The frames, at the engine sample rate
+    pub frames: Vec<Stereo<f32>>,
+    /// Length of the take in beats
+    pub num_beats: usize,
+}
+
+/// TakeBuffer is an empty take buffer for a recorder, allocated here
+pub struct TakeBuffer {
+    /// Recording track
+    pub track_num: usize,
+    /// The buffer, with room for a full take
+    pub frames: Vec<Stereo<f32>>,
+}
+
+/// TrackSelection keeps track of the sample played by a track
+struct TrackSelection {
+    /// Bank index (track-locked)
+    bank: usize,
+    /// Sample name to keep track for presets as the lib grows
+    sample_name: String,
+    /// Direction parameter for sample selection (Up/Down).
+    sample_select: DirectionalParam,
+}
+
+/// SampleLoader runs off the audio thread, answers the sample selection requests of the mixer.
+pub struct SampleLoader {
+    /// SampleLib, owned by the loader
+    sample_lib: SampleLib,
+    /// Selection state of each track
+    tracks: Vec<TrackSelection>,
+    /// Sample selection requests from the mixer
+    request_rx: Receiver<ControlMessage>,
+    /// Requests the mixer could not queue, reported here
+    missed_requests: Arc<AtomicUsize>,
+    /// Takes the recorders missed, reported here
+    missed_takes: Arc<AtomicUsize>,
+    /// Swaps dropped on a full queue, the tracks kept their sample
+    missed_swaps: usize,
+    /// Buffers ready for the mixer
+    swap_tx: Sender<BufferSwap>,
+    /// Buffers the mixer is done with
+    reclaim_rx: Receiver<Arc<SmartBuffer>>,
+    /// Slicer patterns the mixer is done with
+    pattern_reclaim_rx: Receiver<Arc<SlicePattern>>,
+    /// Library updates from the watcher, never ready if not watching
+    update_rx: Receiver<LibUpdate>,
+    /// Takes from the recorders
+    take_rx: Receiver<RecordedTake>,
+    /// Fresh take buffers for the recorders
+    take_buffer_tx: Sender<TakeBuffer>,
+    /// Take buffers waiting for room in the queue, a recorder can't arm without its buffer
+    pending_take_buffers: Vec<TakeBuffer>,
+    /// Takes recorded so far, names the next one
+    takes: usize,
+    /// Notifications to the remote controller
+    notify_tx: Option<Sender<Notification>>,
+    /// Engine sample rate
+    sample_rate: f64,
+}
+
+/// LoaderHandle is the mixer side of the loader, never blocks.
+pub struct LoaderHandle {
+    /// Sample selection requests to the loader
+    request_tx: Sender<ControlMessage>,
+    /// Requests dropped on a full queue
+    missed_requests: Arc<AtomicUsize>,
+    /// Takes refused, dropped or lost on a full queue
+    missed_takes: Arc<AtomicUsize>,
+    /// Buffers ready to be swapped in
+    swap_rx: Receiver<BufferSwap>,
+    /// Buffers to free off the audio thread
+    reclaim_tx: Sender<Arc<SmartBuffer>>,
+    /// Slicer patterns to free off the audio thread
+    pattern_reclaim_tx: Sender<Arc<SlicePattern>>,
+    /// Takes to turn into samples
+    take_tx: Sender<RecordedTake>,
+    /// Fresh take buffers
+    take_buffer_rx: Receiver<TakeBuffer>,
+}
+
+/// SampleLoader implementation
+impl SampleLoader {
+    /// Loads the sample lib at the engine sample rate and queues the first sample of each track.
+    /// Returns the loader and the handle to give to the mixer.
+    pub fn new(conf: Config, sample_rate: f64) -> (Self, LoaderHandle) {
+        // init the sample lib, crash of err
+        let sample_lib = super::init_lib(conf.clone(), sample_rate)
+            .expect("Unable to load some samples, maybe an issue with the AUDIO_ROOT in conf ?");
+
+        // queues
+        let (request_tx, request_rx) = bounded::<ControlMessage>(LOADER_QUEUE_CAPACITY);
+        let missed_requests = Arc::new(AtomicUsize::new(0));
+        let missed_takes = Arc::new(AtomicUsize::new(0));
+        let (swap_tx, swap_rx) = bounded::<BufferSwap>(LOADER_QUEUE_CAPACITY);
+        let (reclaim_tx, reclaim_rx) = bounded::<Arc<SmartBuffer>>(LOADER_QUEUE_CAPACITY);
+        let (pattern_reclaim_tx, pattern_reclaim_rx) =
+            bounded::<Arc<SlicePattern>>(LOADER_QUEUE_CAPACITY);
+        let (take_tx, take_rx) = bounded::<RecordedTake>(LOADER_QUEUE_CAPACITY);
+        let (take_buffer_tx, take_buffer_rx) = bounded::<TakeBuffer>(LOADER_QUEUE_CAPACITY);
+
+        // one selection per track, in config order
+        let tracks = conf
+            .tracks
+            .iter()
+            .map(|t| TrackSelection {
+                bank: t.generator.bank(),
+                sample_name: String::from(""),
+                sample_select: DirectionalParam::new(0.0, 0.0),
+            })
+            .collect();
+
+        let mut loader = SampleLoader {
+            sample_lib,
+            tracks,
+            request_rx,
+            missed_requests: Arc::clone(&missed_requests),
+            missed_takes: Arc::clone(&missed_takes),
+            missed_swaps: 0,
+            swap_tx,
+            reclaim_rx,
+            pattern_reclaim_rx,
+            update_rx: never(),
+            take_rx,
+            take_buffer_tx,
+            pending_take_buffers: Vec::new(),
+            takes: 0,
+            notify_tx: None,
+            sample_rate,
+        };
+
+        // the first sample of each bank is ready before the first audio block
+        for track_num in 0..loader.tracks.len() {
+            let first = loader
+                .sample_lib
+                .get_first_sample(loader.tracks[track_num].bank)
+                .clone();
+            loader.send_swap(track_num, first);
+        }
+
+        let handle = LoaderHandle {
+            request_tx,
+            missed_requests,
+            missed_takes,
+            swap_rx,
+            reclaim_tx,
+            pattern_reclaim_tx,
+            take_tx,
+            take_buffer_rx,
+        };
+
+        (loader, handle)
+    }
+
+    /// Watches the audio root for changes, the remote controller is notified of the updated banks.
+    pub fn watch(&mut self, conf: Config, notify_tx: Sender<Notification>) {
+        let bank_dirs = self.sample_lib.get_bank_dirs().to_vec();
+        let stamps = self.sample_lib.get_stamps().to_vec();
+        let (_watcher_thread, update_rx) =
+            initialize_watcher(conf, self.sample_rate, bank_dirs, stamps);
+        self.update_rx = update_rx;
+        self.notify_tx = Some(notify_tx);
+    }
+
+    /// Runs the loader until the mixer is gone
+    pub fn run(mut self) {
+        loop {
+            self.report_missed_takes();
+
+            // wakes up to send the pending take buffers again
+            let retry = if self.pending_take_buffers.is_empty() {
+                never()
+            } else {
+                after(Duration::from_millis(TAKE_BUFFER_RETRY_MS))
+            };
+            select! {
+                recv(self.request_rx) -> request => match request {
+                    Ok(r) => self.handle_request(r),
+                    Err(_) => break,
+                },
+                recv(self.reclaim_rx) -> old => match old {
+                    // the last reference is usually dropped here
+                    Ok(_) => {}
+                    Err(_) => break,
+                },
+                recv(self.pattern_reclaim_rx) -> old => match old {
+                    Ok(_) => {}
+                    Err(_) => break,
+                },
+                recv(self.take_rx) -> take => match take {
+                    Ok(t) => self.handle_take(t),
+                    Err(_) => break,
+                },
+                recv(self.update_rx) -> update => match update {
+                    Ok(u) => self.handle_update(u),
+                    // the watcher is gone, stop listening to it
+                    Err(_) => self.update_rx = never(),
+                },
+                recv(retry) -> _ => self.send_take_buffers(),
+            }
+        }
+    }
+
+    /// Handles all the pending requests and reclaims, without blocking.
+    /// Used by the offline renderer to stay deterministic.
+    pub fn poll(&mut self) {
+        while let Ok(request) = self.request_rx.try_recv() {
+            self.handle_request(request);
+        }
+        while let Ok(_old) = self.reclaim_rx.try_recv() {}
+        while let Ok(_old) = self.pattern_reclaim_rx.try_recv() {}
+        while let Ok(take) = self.take_rx.try_recv() {
+            self.handle_take(take);
+        }
+        self.send_take_buffers();
+        self.report_missed_takes();
+    }
+
+    /// Prints the takes the recorders missed since the last report
+    fn report_missed_takes(&self) {
+        let missed = self.missed_takes.swap(0, Ordering::Relaxed);
+        if missed > 0 {
+            println!(
+                "sample_loader: missed {} takes, not armed, too long or not stored",
+                missed
+            );
+        }
+    }
+
+    /// Handles a sample selection request
+    fn handle_request(&mut self, request: ControlMessage) {
+        // the mixer can't print, the requests it dropped are reported here
+        let missed = self.missed_requests.swap(0, Ordering::Relaxed);
+        if missed > 0 {
+            println!("sample_loader: missed {} requests", missed);
+        }
+
+        match request {
+            // Change tracked Sample inside the bank
+            ControlMessage::TrackSampleSelect {
+                tcode: _,
+                val,
+                track_num,
+            } => {
+                // check if tracknum is around
+                let dir = match self.tracks.get_mut(track_num) {
+                    Some(t) => {
+                        // set the new selecta
+                        t.sample_select.new_value(val);
+                        t.sample_select.get_param()
+                    }
+                    None => return,
+                };
+
+                // match the resulting dir enum
+                match dir {
+                    Direction::Up(_) => self.swap_sibling(track_num, 1),
+                    Direction::Down(_) => self.swap_sibling(track_num, -1),
+                    Direction::Stable(_) => {}
+                }
+            }
+            // Next Sample
+            ControlMessage::TrackNextSample {
+                tcode: _,
+                track_num,
+            } => self.swap_sibling(track_num, 1),
+            // Previous Sample
+            ControlMessage::TrackPrevSample {
+                tcode: _,
+                track_num,
+            } => self.swap_sibling(track_num, -1),
+            // not for us
+            _ => {}
+        }
+    }
+
+    /// Applies a library update, tracks playing the changed sample get the new version
+    fn handle_update(&mut self, update: LibUpdate) {
+        let bank = match update {
+            LibUpdate::Loaded { bank, buffer } => {
+                self.sample_lib.insert_sample(bank, Arc::clone(&buffer));
+                for track_num in 0..self.tracks.len() {
+                    let t = &self.tracks[track_num];
+                    // playing this sample, or nothing yet
+                    if t.bank == bank
+                        && (t.sample_name == buffer.file_name || t.sample_name.is_empty())
+                    {
+                        self.send_swap(track_num, Arc::clone(&buffer));
+                    }
+                }
+                bank
+            }
+            LibUpdate::Removed { bank, file_name } => {
+                // tracks playing it move to the next sample
+                let next = self
+                    .sample_lib
+                    .get_sibling_sample(bank, &file_name, 1)
+                    .clone();
+                self.sample_lib.remove_sample(bank, &file_name);
+                for track_num in 0..self.tracks.len() {
+                    let t = &self.tracks[track_num];
+                    // if it was the last one, the track keeps playing it until something new lands
+                    if t.bank == bank
+                        && t.sample_name == file_name
+                        && next.file_name != file_name
+                        && !next.file_name.is_empty()
+                    {
+                        self.send_swap(track_num, Arc::clone(&next));
+                    }
+                }
+                bank
+            }
+        };
+
+        // let the remote controller know
+        if let Some(notify_tx) = &self.notify_tx {
+            let _res = notify_tx.try_send(Notification::BankUpdated {
+                bank,
+                samples: self.sample_lib.get_sample_names(bank),
+            });
+        }
+    }
+
+    /// Stores a recorded take in its bank, the recording track plays it
+    fn handle_take(&mut self, take: RecordedTake) {
+        // the recorder needs room for its next take
+        let capacity = take.frames.capacity();
+        self.pending_take_buffers.push(TakeBuffer {
+            track_num: take.track_num,
+            frames: Vec::with_capacity(capacity),
+        });
+        self.send_take_buffers();
+
+        let name = self.take_name(take.bank);
+        let buffer =
+            match SmartBuffer::from_frames(&name, take.frames, self.sample_rate, take.num_beats) {
+                Ok(b) => Arc::new(b),
+                Err(e) => {
+                    println!("sample_loader: skipping {}: {}", name, e);
+                    return;
+                }
+            };
+        println!(
+            "sample_loader: {} in bank {}, {} beats at {:.2} bpm",
+            name,
+            take.bank,
+            take.num_beats,
+            buffer.get_analysis().original_tempo
+        );
+
+        // same path as a new file, tracks of the bank playing nothing get it too
+        self.handle_update(LibUpdate::Loaded {
+            bank: take.bank,
+            buffer: Arc::clone(&buffer),
+        });
+        let recording = self
+            .tracks
+            .get(take.track_num)
+            .map(|t| t.sample_name != name);
+        if recording == Some(true) {
+            self.send_swap(take.track_num, buffer);
+        }
+    }
+
+    /// Sends the pending take buffers, the ones finding a full queue wait for the next try
+    fn send_take_buffers(&mut self) {
+        while !self.pending_take_buffers.is_empty() {
+            let buffer = self.pending_take_buffers.remove(0);
+            if let Err(e) = self.take_buffer_tx.try_send(buffer) {
+                if e.is_disconnected() {
+                    // the mixer is gone
+                    self.pending_take_buffers.clear();
+                } else {
+                    self.pending_take_buffers.insert(0, e.into_inner());
+                }
+                return;
+            }
+        }
+    }
+
+    /// Names the next take, never the name of a sample or a file of the bank
+    fn take_name(&mut self, bank: usize) -> String {
+        let names = self.sample_lib.get_sample_names(bank);
+        let bank_dir = self.sample_lib.get_bank_dirs().get(bank).cloned();
+        loop {
+            self.takes += 1;
+            let name = format!("take_{:03}", self.takes);
+            let on_disk = bank_dir.as_ref().map_or(false, |d| d.join(&name).exists());
+            if !on_disk && !names.contains(&name) {
+                return name;
+            }
+        }
+    }
+
+    /// Sends the sibling of the current sample of the track
+    fn swap_sibling(&mut self, track_num: usize, dir: isize) {
+        let sibling = match self.tracks.get(track_num) {
+            Some(t) => self
+                .sample_lib
+                .get_sibling_sample(t.bank, t.sample_name.as_str(), dir)
+                .clone(),
+            None => return,
+        };
+        self.send_swap(track_num, sibling);
+    }
+
+    /// Sends a buffer to a track and memorize it, a missed swap leaves the track on its sample
+    fn send_swap(&mut self, track_num: usize, buffer: Arc<SmartBuffer>) {
+        let sent = Arc::clone(&buffer);
+
+        // send
+        if self
+            .swap_tx
+            .try_send(BufferSwap { track_num, buffer })
+            .is_err()
+        {
+            self.missed_swaps += 1;
+            println!(
+                "sample_loader: missed {} swaps, track {} keeps its sample",
+                self.missed_swaps, track_num
+            );
+            return;
+        }
+
+        // memorize
+        let t = &mut self.tracks[track_num];
+        t.sample_name.clear();
+        t.sample_name.push_str(sent.file_name.as_str());
+    }
+}
+
+/// LoaderHandle implementation, safe to use in the audio thread
+impl LoaderHandle {
+    /// Forwards a sample selection request to the loader, counts it as missed if the queue is full
+    pub fn request(&self, request: ControlMessage) {
+        if self.request_tx.try_send(request).is_err() {
+            self.missed_requests.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts takes the recorders missed, the loader reports them
+    pub fn miss_takes(&self, count: usize) {
+        self.missed_takes.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Gets the next buffer ready to swap, if any
+    pub fn try_swap(&self) -> Option<BufferSwap> {
+        self.swap_rx.try_recv().ok()
+    }
+
+    /// Sends a replaced buffer back to the loader.
+    /// If the queue is full it is dropped here, only case where the audio thread may free a buffer.
+    pub fn reclaim(&self, buffer: Arc<SmartBuffer>) {
+        let _ = self.reclaim_tx.try_send(buffer);
+    }
+
+    /// Sends a replaced slicer pattern back to the loader.
+    /// If the queue is full it is dropped here.
+    pub fn reclaim_pattern(&self, pattern: Arc<SlicePattern>) {
+        let _ = self.pattern_reclaim_tx.try_send(pattern);
+    }
+
+    /// Sends a finished take to the loader, gives it back if the queue is full
+    pub fn send_take(&self, take: RecordedTake) -> Result<(), RecordedTake> {
+        self.take_tx.try_send(take).map_err(|e| e.into_inner())
+    }
+
+    /// Gets the next fresh take buffer, if any
+    pub fn try_take_buffer(&self) -> Option<TakeBuffer> {
+        self.take_buffer_rx.try_recv().ok()
+    }
+}
@@ -1,17 +1,32 @@
+pub mod cache;
+pub mod loader;
+pub mod watcher;
+
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::config::Config;
+use crate::config::{Config, Downmix};
 use crate::sample_gen::SmartBuffer;
 
+use self::cache::AnalysisCache;
+use self::watcher::{file_stamp, FileStamp};
+
 /// SampleLib Manage samples loading and analytics.
 /// Its like a In-Memory Sample Database
 pub struct SampleLib {
     /// In-Memory SmartBuffer Store.
     /// SampleLib is organized in banks.
-    buffers: Vec<Vec<SmartBuffer>>,
+    /// Buffers are reference counted so they can be handed to the audio thread without copies.
+    buffers: Vec<Vec<Arc<SmartBuffer>>>,
+    /// Folder of each bank, in bank order
+    bank_dirs: Vec<PathBuf>,
+    /// Stamps of the files of each bank, taken before they were loaded
+    stamps: Vec<HashMap<String, FileStamp>>,
     /// empty buff for ref
-    empty_buff: SmartBuffer,
+    empty_buff: Arc<SmartBuffer>,
 }
 
 impl SampleLib {
@@ -32,7 +47,7 @@ impl SampleLib {
     }
 
     /// Gets the first sample of the bank, returns Empty if not found
-    pub fn get_first_sample(&self, bank: usize) -> &SmartBuffer {
+    pub fn get_first_sample(&self, bank: usize) -> &Arc<SmartBuffer> {
         match self.buffers.get(bank) {
             Some(b) => {
                 // take the first
@@ -48,7 +63,7 @@ impl SampleLib {
     }
 
     /// Gets the sample of the bank by double index position, returns Empty if not found
-    pub fn get_sample_by_pos(&self, pos: (usize, usize)) -> &SmartBuffer {
+    pub fn get_sample_by_pos(&self, pos: (usize, usize)) -> &Arc<SmartBuffer> {
         match self.buffers.get(pos.0) {
             Some(b) => {
                 // take the pos
@@ -64,7 +79,7 @@ impl SampleLib {
     }
 
     /// Gets the sample of the bank by name
-    pub fn get_sample_by_name(&self, bank: usize, name: &str) -> &SmartBuffer {
+    pub fn get_sample_by_name(&self, bank: usize, name: &str) -> &Arc<SmartBuffer> {
         match self.buffers.get(bank) {
             Some(b) => {
                 // take the matching string
@@ -85,7 +100,7 @@ impl SampleLib {
     }
 
     /// Gets the next sample given a name and a bank, wrapping around
-    pub fn get_sibling_sample(&self, bank: usize, name: &str, dir: isize) -> &SmartBuffer {
+    pub fn get_sibling_sample(&self, bank: usize, name: &str, dir: isize) -> &Arc<SmartBuffer> {
         match self.buffers.get(bank) {
             Some(b) => {
                 // take the matching string
@@ -108,69 +123,155 @@ impl SampleLib {
             None => return &self.empty_buff,
         };
     }
+
+    /// Gets the folders of the banks, in bank order
+    pub fn get_bank_dirs(&self) -> &[PathBuf] {
+        &self.bank_dirs
+    }
+
+    /// Gets the stamps of the files found by the first scan, in bank order
+    pub fn get_stamps(&self) -> &[HashMap<String, FileStamp>] {
+        &self.stamps
+    }
+
+    /// Gets the sample names of a bank, in bank order
+    pub fn get_sample_names(&self, bank: usize) -> Vec<String> {
+        match self.buffers.get(bank) {
+            Some(b) => b.iter().map(|sb| sb.file_name.clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Inserts a sample in a bank, replaces the sample with the same name if any.
+    /// Missing banks are created.
+    pub fn insert_sample(&mut self, bank: usize, buffer: Arc<SmartBuffer>) {
+        while self.buffers.len() <= bank {
+            self.buffers.push(Vec::new());
+        }
+        let b = &mut self.buffers[bank];
+        match b.iter().position(|sb| sb.file_name == buffer.file_name) {
+            Some(pos) => b[pos] = buffer,
+            None => b.push(buffer),
+        }
+    }
+
+    /// Removes a sample from a bank by name
+    pub fn remove_sample(&mut self, bank: usize, name: &str) {
+        if let Some(b) = self.buffers.get_mut(bank) {
+            b.retain(|sb| sb.file_name != name);
+        }
+    }
 }
 
-/// init the SampleLib, loads the samples
-pub fn init_lib(conf: Config) -> Result<SampleLib, Box<dyn Error>> {
+/// init the SampleLib, loads the samples at the engine sample rate
+pub fn init_lib(conf: Config, sample_rate: f64) -> Result<SampleLib, Box<dyn Error>> {
     // init lib
     let mut lib = SampleLib {
         buffers: Vec::new(),
-        empty_buff: SmartBuffer::new_empty(),
+        bank_dirs: Vec::new(),
+        stamps: Vec::new(),
+        empty_buff: Arc::new(SmartBuffer::new_empty(sample_rate)),
     };
 
+    // analysis of the previous launches
+    let mut cache = AnalysisCache::open(&conf.audio_root);
+
     // directory walk
-    let paths = fs::read_dir(conf.audio_root)?;
+    for bank_dir in list_bank_dirs(&conf.audio_root)? {
+        let mut buffs = Vec::<Arc<SmartBuffer>>::new();
+        let mut stamps = HashMap::new();
+
+        // read the samples, stamped first so a change while loading is seen by the watcher
+        for (file_name, fpath) in list_bank_files(&bank_dir)? {
+            if let Some(stamp) = file_stamp(&fpath) {
+                stamps.insert(file_name.clone(), stamp);
+            }
+            let buffer = load_sample(&fpath, &file_name, sample_rate, conf.downmix, &mut cache);
+            if let Some(buffer) = buffer {
+                buffs.push(Arc::new(buffer));
+            }
+        }
 
-    for bank_path in paths {
+        // finally push in lib
+        lib.buffers.push(buffs);
+        lib.bank_dirs.push(bank_dir);
+        lib.stamps.push(stamps);
+    }
+
+    // only keep the files still around
+    cache.prune();
+    cache.save();
+
+    // yeah
+    Ok(lib)
+}
+
+/// Lists the bank folders of the audio root
+pub fn list_bank_dirs(audio_root: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut dirs = Vec::new();
+    for bank_path in fs::read_dir(audio_root)? {
         // somewhat ugly
         let b = bank_path?;
-        let bank_name = b.file_name();
-        let ftype = b.file_type()?;
-
-        match bank_name.to_str().unwrap() {
-            // our junk filter
-            ".DS_Store" => {}
-            _ => {
-                // is it a directory ?
-                if ftype.is_dir() {
-                    let mut buffs = Vec::<SmartBuffer>::new();
-                    // read the samples
-                    let audio_paths = fs::read_dir(b.path())?;
-
-                    for file_path in audio_paths {
-                        let f = file_path?;
-                        let file_name = f.file_name();
-                        let ftype = f.file_type()?;
-
-                        // filter out crap
-                        match file_name.to_str().unwrap() {
-                            // our junk filter
-                            ".DS_Store" => {}
-                            _ => {
-                                // load file as smart buffer
-                                if !ftype.is_dir() {
-                                    // load smart buffer
-                                    let mut buffer = SmartBuffer::new_empty();
-                                    let fpath = f.path();
-                                    let fpath = fpath.to_str().unwrap(); // NoneError doesnt not implem Boxed Error
-                                                                         // sets name
-                                    buffer.file_name = String::from(file_name.to_str().unwrap());
-                                    buffer.load_wave(fpath).expect("all waves must be loadable");
-
-                                    // push
-                                    buffs.push(buffer);
-                                }
-                            }
-                        }
-                    }
-                    // finally push in lib
-                    lib.buffers.push(buffs);
+        // is it a directory ?
+        if b.file_type()?.is_dir() && !is_junk(&b.file_name().to_string_lossy()) {
+            dirs.push(b.path());
+        }
+    }
+    Ok(dirs)
+}
+
+/// Lists the files of a bank folder, as (file name, path)
+pub fn list_bank_files(bank_dir: &Path) -> Result<Vec<(String, PathBuf)>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    for file_path in fs::read_dir(bank_dir)? {
+        let f = file_path?;
+        let file_name = f.file_name().to_string_lossy().into_owned();
+        // filter out crap
+        if !f.file_type()?.is_dir() && !is_junk(&file_name) {
+            files.push((file_name, f.path()));
+        }
+    }
+    Ok(files)
+}
+
+/// Loads and analyse a sample file, skips what we can't decode.
+/// The analysis comes from the cache when it is still valid, the cache is updated otherwise.
+pub fn load_sample(
+    fpath: &Path,
+    file_name: &str,
+    sample_rate: f64,
+    downmix: Downmix,
+    cache: &mut AnalysisCache,
+) -> Option<SmartBuffer> {
+    // load smart buffer
+    let mut buffer = SmartBuffer::new_empty(sample_rate);
+    let cached = cache.get(fpath, sample_rate, downmix).cloned();
+    let fpath_str = fpath.to_string_lossy();
+    // sets name
+    buffer.file_name = String::from(file_name);
+    let res = match &cached {
+        Some(analysis) => buffer.load_file_analysed(&fpath_str, sample_rate, downmix, analysis),
+        None => buffer.load_file(&fpath_str, sample_rate, downmix),
+    };
+    match res {
+        Ok(_) => {
+            // fresh analysis, the file is hashed only then
+            if cached.is_none() {
+                if let Some(key) = cache::cache_key(fpath, sample_rate, downmix) {
+                    cache.insert(fpath, key, buffer.get_analysis());
                 }
             }
+            Some(buffer)
+        }
+        Err(e) => {
+            println!("sample_lib: skipping {}: {}", fpath_str, e);
+            cache.remove(fpath);
+            None
         }
-        //
     }
+}
 
-    // yeah
-    Ok(lib)
+/// our junk filter
+fn is_junk(name: &str) -> bool {
+    name == ".DS_Store"
 }
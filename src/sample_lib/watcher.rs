@@ -0,0 +1,196 @@
+//! Sample library watcher.
+//! Polls the bank folders of the audio root, loads and analyses new or changed files,
+//! reports the deleted ones. Runs in its own thread, the loader applies the updates.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+
+use crate::config::{Config, Downmix};
+use crate::sample_gen::SmartBuffer;
+
+use super::cache::AnalysisCache;
+use super::{list_bank_dirs, list_bank_files, load_sample};
+
+/// How often the bank folders are scanned
+const WATCH_INTERVAL_MS: u64 = 1000;
+
+/// LibUpdate is a change in the sample library
+pub enum LibUpdate {
+    /// A new or changed sample, loaded and analysed
+    Loaded {
+        bank: usize,
+        buffer: Arc<SmartBuffer>,
+    },
+    /// A deleted (or no more readable) sample
+    Removed { bank: usize, file_name: String },
+}
+
+/// FileStamp tells if a file changed between two scans
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// LibraryWatcher keeps the state of the last scan
+struct LibraryWatcher {
+    /// Audio root, new bank folders are looked for here
+    audio_root: String,
+    /// Engine sample rate
+    sample_rate: f64,
+    /// Multichannel rule
+    downmix: Downmix,
+    /// Bank folders, in SampleLib bank order
+    bank_dirs: Vec<PathBuf>,
+    /// Files known at the last scan, per bank
+    stamps: Vec<HashMap<String, FileStamp>>,
+    /// Files that changed at the last scan, loaded once they settle (copy in progress ...)
+    pending: Vec<HashMap<String, FileStamp>>,
+    /// Analysis cache, kept up to date for the next launch
+    cache: AnalysisCache,
+    /// Updates to the loader
+    update_tx: Sender<LibUpdate>,
+}
+
+/// LibraryWatcher implementation
+impl LibraryWatcher {
+    /// Scans all the banks once, sends the updates.
+    /// Returns false when the loader is gone.
+    fn scan(&mut self) -> bool {
+        // new bank folders are appended
+        if let Ok(dirs) = list_bank_dirs(&self.audio_root) {
+            for dir in dirs {
+                if !self.bank_dirs.contains(&dir) {
+                    println!("sample_lib: new bank {}", dir.to_string_lossy());
+                    self.bank_dirs.push(dir);
+                    self.stamps.push(HashMap::new());
+                    self.pending.push(HashMap::new());
+                }
+            }
+        }
+
+        for bank in 0..self.bank_dirs.len() {
+            // a removed folder is an empty bank
+            let files = list_bank_files(&self.bank_dirs[bank]).unwrap_or_default();
+
+            // deleted files
+            let deleted: Vec<String> = self.stamps[bank]
+                .keys()
+                .filter(|name| !files.iter().any(|(f, _)| f == *name))
+                .cloned()
+                .collect();
+            for file_name in deleted {
+                self.stamps[bank].remove(&file_name);
+                self.cache.remove(&self.bank_dirs[bank].join(&file_name));
+                self.cache.save();
+                if !self.send(LibUpdate::Removed { bank, file_name }) {
+                    return false;
+                }
+            }
+
+            // new or changed files
+            for (file_name, fpath) in files {
+                let stamp = match file_stamp(&fpath) {
+                    Some(s) => s,
+                    None => continue,
+                };
+
+                // nothing new
+                if self.stamps[bank].get(&file_name) == Some(&stamp) {
+                    self.pending[bank].remove(&file_name);
+                    continue;
+                }
+
+                // still moving, wait for the next scan
+                if self.pending[bank].get(&file_name) != Some(&stamp) {
+                    self.pending[bank].insert(file_name, stamp);
+                    continue;
+                }
+
+                // settled, load and analyse
+                self.pending[bank].remove(&file_name);
+                self.stamps[bank].insert(file_name.clone(), stamp);
+                let loaded = load_sample(
+                    &fpath,
+                    &file_name,
+                    self.sample_rate,
+                    self.downmix,
+                    &mut self.cache,
+                );
+                self.cache.save();
+                let update = match loaded {
+                    Some(buffer) => {
+                        println!("sample_lib: loaded {}", fpath.to_string_lossy());
+                        LibUpdate::Loaded {
+                            bank,
+                            buffer: Arc::new(buffer),
+                        }
+                    }
+                    None => LibUpdate::Removed { bank, file_name },
+                };
+                if !self.send(update) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Sends an update to the loader, false if it is gone
+    fn send(&self, update: LibUpdate) -> bool {
+        self.update_tx.send(update).is_ok()
+    }
+}
+
+/// Size and modification date of a file
+pub fn file_stamp(fpath: &Path) -> Option<FileStamp> {
+    let meta = fs::metadata(fpath).ok()?;
+    Some(FileStamp {
+        len: meta.len(),
+        modified: meta.modified().ok(),
+    })
+}
+
+/// Initialize the watcher thread.
+/// Bank folders and the stamps of their files must be given in SampleLib bank order,
+/// as taken when the files were loaded, the files still matching them are not reloaded.
+/// Returns the thread and the updates receiver.
+pub fn initialize_watcher(
+    conf: Config,
+    sample_rate: f64,
+    bank_dirs: Vec<PathBuf>,
+    stamps: Vec<HashMap<String, FileStamp>>,
+) -> (thread::JoinHandle<()>, Receiver<LibUpdate>) {
+    // updates out
+    let (update_tx, update_rx) = bounded::<LibUpdate>(64);
+
+    let watcher_thread = thread::spawn(move || {
+        let mut watcher = LibraryWatcher {
+            audio_root: conf.audio_root.clone(),
+            sample_rate,
+            downmix: conf.downmix,
+            pending: vec![HashMap::new(); bank_dirs.len()],
+            cache: AnalysisCache::open(&conf.audio_root),
+            bank_dirs,
+            stamps,
+            update_tx,
+        };
+
+        println!("sample_lib: watching {}", watcher.audio_root);
+
+        // watch loop, until the loader is gone
+        loop {
+            thread::sleep(Duration::from_millis(WATCH_INTERVAL_MS));
+            if !watcher.scan() {
+                break;
+            }
+        }
+    });
+
+    (watcher_thread, update_rx)
+}